@@ -1,29 +1,52 @@
 import { PublicKey } from "@solana/web3.js";
 
+/**
+ * Pools only exist for the canonically ordered pair (tokenAMint < tokenBMint).
+ * `flipped` is true when the caller's (x, y) maps to the pool's (B, A).
+ */
+export function sortMints(mintX: PublicKey, mintY: PublicKey) {
+  const flipped = Buffer.compare(mintX.toBuffer(), mintY.toBuffer()) > 0;
+  return flipped
+    ? { mintA: mintY, mintB: mintX, flipped }
+    : { mintA: mintX, mintB: mintY, flipped };
+}
+
+export function deriveConfig(programId: PublicKey) {
+  const [config] = PublicKey.findProgramAddressSync([Buffer.from("config")], programId);
+  return config;
+}
+
 export function derivePdas(
   programId: PublicKey,
   tokenAMint: PublicKey,
-  tokenBMint: PublicKey
+  tokenBMint: PublicKey,
+  poolIndex = 0
 ) {
+  const index = Buffer.alloc(2);
+  index.writeUInt16LE(poolIndex);
   const [pool] = PublicKey.findProgramAddressSync(
-    [Buffer.from("pool"), tokenAMint.toBuffer(), tokenBMint.toBuffer()],
+    [Buffer.from("pool"), tokenAMint.toBuffer(), tokenBMint.toBuffer(), index],
     programId
   );
   const [poolAuthority] = PublicKey.findProgramAddressSync(
-    [Buffer.from("pool_authority"), tokenAMint.toBuffer(), tokenBMint.toBuffer()],
+    [Buffer.from("pool_authority"), tokenAMint.toBuffer(), tokenBMint.toBuffer(), index],
     programId
   );
   const [tokenAVault] = PublicKey.findProgramAddressSync(
-    [Buffer.from("token_a_vault"), tokenAMint.toBuffer(), tokenBMint.toBuffer()],
+    [Buffer.from("token_a_vault"), tokenAMint.toBuffer(), tokenBMint.toBuffer(), index],
     programId
   );
   const [tokenBVault] = PublicKey.findProgramAddressSync(
-    [Buffer.from("token_b_vault"), tokenAMint.toBuffer(), tokenBMint.toBuffer()],
+    [Buffer.from("token_b_vault"), tokenAMint.toBuffer(), tokenBMint.toBuffer(), index],
     programId
   );
   const [lpTokenMint] = PublicKey.findProgramAddressSync(
-    [Buffer.from("lp_token_mint"), tokenAMint.toBuffer(), tokenBMint.toBuffer()],
+    [Buffer.from("lp_token_mint"), tokenAMint.toBuffer(), tokenBMint.toBuffer(), index],
+    programId
+  );
+  const [lockedLpToken] = PublicKey.findProgramAddressSync(
+    [Buffer.from("locked_lp"), pool.toBuffer()],
     programId
   );
-  return { pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint };
+  return { pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, lockedLpToken };
 }
@@ -6,15 +6,23 @@ import {
   createAssociatedTokenAccountInstruction,
 } from "@solana/spl-token";
 
+// Token program that owns `mint`: SPL Token or Token-2022.
+export async function getMintProgram(connection: Connection, mint: PublicKey): Promise<PublicKey> {
+  const info = await connection.getAccountInfo(mint);
+  return info ? info.owner : TOKEN_PROGRAM_ID;
+}
+
 export async function getOrCreateAtaIx(
   connection: Connection,
   owner: PublicKey,
-  mint: PublicKey
+  mint: PublicKey,
+  payer: PublicKey = owner
 ): Promise<{ address: PublicKey; ix: import("@solana/web3.js").TransactionInstruction | null }> {
-  const ata = await getAssociatedTokenAddress(mint, owner, true, TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID);
+  const tokenProgram = await getMintProgram(connection, mint);
+  const ata = await getAssociatedTokenAddress(mint, owner, true, tokenProgram, ASSOCIATED_TOKEN_PROGRAM_ID);
   const info = await connection.getAccountInfo(ata);
   if (info) return { address: ata, ix: null };
-  const ix = createAssociatedTokenAccountInstruction(owner, ata, owner, mint, TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID);
+  const ix = createAssociatedTokenAccountInstruction(payer, ata, owner, mint, tokenProgram, ASSOCIATED_TOKEN_PROGRAM_ID);
   return { address: ata, ix };
 }
 
@@ -2,13 +2,28 @@ import { useCallback, useEffect, useMemo, useState } from "react";
 import { useWallet } from "@solana/wallet-adapter-react";
 import { WalletMultiButton } from "@solana/wallet-adapter-react-ui";
 import { BN } from "@coral-xyz/anchor";
-import { PublicKey, Transaction } from "@solana/web3.js";
+import { Connection, PublicKey, Transaction } from "@solana/web3.js";
 import { TOKEN_PROGRAM_ID } from "@solana/spl-token";
 import { getProgram, PROGRAM_ID } from "@/lib/anchor";
-import { derivePdas } from "@/lib/pdas";
-import { ensureAtas } from "@/lib/tokens";
+import { deriveConfig, derivePdas, sortMints } from "@/lib/pdas";
+import { ensureAtas, getMintProgram, getOrCreateAtaIx } from "@/lib/tokens";
 
 const toPubkey = (s: string) => new PublicKey(s.trim());
+
+// Liquidity instructions take the protocol fee recipient's LP account; create it if missing.
+async function getFeeRecipientLpIx(program: any, payer: PublicKey, lpTokenMint: PublicKey) {
+  const config = await program.account.config.fetch(deriveConfig(PROGRAM_ID));
+  return getOrCreateAtaIx(program.provider.connection, config.feeRecipient, lpTokenMint, payer);
+}
+
+// Token A and B each pass their own token program, so Token-2022 mints can be pooled.
+async function getPairPrograms(connection: Connection, mintA: PublicKey, mintB: PublicKey) {
+  const [tokenAProgram, tokenBProgram] = await Promise.all([
+    getMintProgram(connection, mintA),
+    getMintProgram(connection, mintB),
+  ]);
+  return { tokenAProgram, tokenBProgram };
+}
 const LAMPORTS_9 = 1_000_000_000;
 
 export default function Home() {
@@ -24,11 +39,17 @@ export default function Home() {
     } catch { return false; }
   }, [tokenA, tokenB]);
 
-  const pdas = useMemo(() => {
+  // The pool's A/B sides follow canonical mint order, which may differ from the inputs.
+  const pair = useMemo(() => {
     if (!mintsValid) return null;
-    return derivePdas(PROGRAM_ID, toPubkey(tokenA), toPubkey(tokenB));
+    return sortMints(toPubkey(tokenA), toPubkey(tokenB));
   }, [mintsValid, tokenA, tokenB]);
 
+  const pdas = useMemo(() => {
+    if (!pair) return null;
+    return derivePdas(PROGRAM_ID, pair.mintA, pair.mintB);
+  }, [pair]);
+
   const [poolData, setPoolData] = useState<any>(null);
   const [balances, setBalances] = useState<{ a: number; b: number; lp: number; sol: number } | null>(null);
   const [status, setStatus] = useState<string>("");
@@ -67,7 +88,6 @@ export default function Home() {
 
       if (mintsValid && pdas) {
         try {
-          const { getAccount } = await import("@solana/spl-token");
           const { atAs } = await ensureAtas(
             program.provider.connection,
             wallet.publicKey,
@@ -77,8 +97,8 @@ export default function Home() {
           const balancesArr = await Promise.all(
             atAs.map(async (ata) => {
               try {
-                const account = await getAccount(program.provider.connection, ata);
-                return Number(account.amount);
+                const balance = await program.provider.connection.getTokenAccountBalance(ata);
+                return Number(balance.value.amount);
               } catch {
                 return 0;
               }
@@ -117,22 +137,26 @@ export default function Home() {
 
   const onInit = useCallback(async () => {
     if (!wallet.publicKey || !wallet.signTransaction) throw new Error("Connect a wallet");
-    if (!mintsValid || !pdas) throw new Error("Enter valid token mint addresses");
+    if (!pair || !pdas) throw new Error("Enter valid token mint addresses");
     const program = await getProgram(wallet);
 
     const { SystemProgram, SYSVAR_RENT_PUBKEY } = await import("@solana/web3.js");
 
-    const txBuilder = program.methods.initializePool().accounts({
+    // `null` fee: use the protocol's default fee from the config account
+    const txBuilder = program.methods.initializePool(0, null, { constantProduct: {} }, new BN(0), new BN(0)).accounts({
+      config: deriveConfig(PROGRAM_ID),
       pool: pdas.pool,
       poolAuthority: pdas.poolAuthority,
-      tokenAMint: toPubkey(tokenA),
-      tokenBMint: toPubkey(tokenB),
+      tokenAMint: pair.mintA,
+      tokenBMint: pair.mintB,
       tokenAVault: pdas.tokenAVault,
       tokenBVault: pdas.tokenBVault,
       lpTokenMint: pdas.lpTokenMint,
+      lockedLpToken: pdas.lockedLpToken,
       payer: wallet.publicKey,
       systemProgram: SystemProgram.programId,
       tokenProgram: TOKEN_PROGRAM_ID,
+      ...(await getPairPrograms(program.provider.connection, pair.mintA, pair.mintB)),
       rent: SYSVAR_RENT_PUBKEY,
     });
 
@@ -149,37 +173,44 @@ export default function Home() {
     loadBalances();
 
     return sig;
-  }, [wallet, mintsValid, pdas, tokenA, tokenB, loadBalances]);
+  }, [wallet, pair, pdas, loadBalances]);
 
   const [amountA, setAmountA] = useState("100");
   const [amountB, setAmountB] = useState("100");
 
   const onAddLiquidity = useCallback(async () => {
     if (!wallet.publicKey || !wallet.signTransaction) throw new Error("Connect a wallet");
-    if (!mintsValid || !pdas) throw new Error("Enter valid token mint addresses");
+    if (!pair || !pdas) throw new Error("Enter valid token mint addresses");
     const program = await getProgram(wallet);
 
     // Ensure user has ATAs for A, B, LP
     const { ixs, atAs } = await ensureAtas(
       program.provider.connection,
       wallet.publicKey,
-      [toPubkey(tokenA), toPubkey(tokenB), pdas.lpTokenMint]
+      [pair.mintA, pair.mintB, pdas.lpTokenMint]
     );
     const [userTokenA, userTokenB, userLpToken] = atAs;
+    const feeRecipientLp = await getFeeRecipientLpIx(program, wallet.publicKey, pdas.lpTokenMint);
+    if (feeRecipientLp.ix) ixs.push(feeRecipientLp.ix);
+    const [poolAmountA, poolAmountB] = pair.flipped ? [amountB, amountA] : [amountA, amountB];
 
     const txIx = await program.methods
-      .addLiquidity(new BN(Math.floor(parseFloat(amountA) * LAMPORTS_9)), new BN(Math.floor(parseFloat(amountB) * LAMPORTS_9)), new BN(0))
+      .addLiquidity(new BN(Math.floor(parseFloat(poolAmountA) * LAMPORTS_9)), new BN(Math.floor(parseFloat(poolAmountB) * LAMPORTS_9)), new BN(0), new BN(0), new BN(0), null)
       .accounts({
         pool: pdas.pool,
         poolAuthority: pdas.poolAuthority,
         tokenAVault: pdas.tokenAVault,
         tokenBVault: pdas.tokenBVault,
         lpTokenMint: pdas.lpTokenMint,
+        config: deriveConfig(PROGRAM_ID),
+        lockedLpToken: pdas.lockedLpToken,
+        feeRecipientLpToken: feeRecipientLp.address,
         userTokenA,
         userTokenB,
         userLpToken,
         user: wallet.publicKey,
         tokenProgram: TOKEN_PROGRAM_ID,
+        ...(await getPairPrograms(program.provider.connection, pair.mintA, pair.mintB)),
       })
       .transaction();
 
@@ -198,7 +229,7 @@ export default function Home() {
     loadBalances();
 
     return sig;
-  }, [wallet, mintsValid, pdas, tokenA, tokenB, amountA, amountB, loadBalances]);
+  }, [wallet, pair, pdas, amountA, amountB, loadBalances]);
 
   const [swapIn, setSwapIn] = useState("10");
   const [swapDir, setSwapDir] = useState<"AtoB" | "BtoA">("AtoB");
@@ -206,20 +237,21 @@ export default function Home() {
 
   const onSwap = useCallback(async () => {
     if (!wallet.publicKey || !wallet.signTransaction) throw new Error("Connect a wallet");
-    if (!mintsValid || !pdas) throw new Error("Enter valid token mint addresses");
+    if (!pair || !pdas) throw new Error("Enter valid token mint addresses");
     const program = await getProgram(wallet);
 
     const { ixs, atAs } = await ensureAtas(
       program.provider.connection,
       wallet.publicKey,
-      [toPubkey(tokenA), toPubkey(tokenB)]
+      [pair.mintA, pair.mintB]
     );
     const [userTokenA, userTokenB] = atAs;
-    const isAToB = swapDir === "AtoB";
+    const isAToB = (swapDir === "AtoB") !== pair.flipped;
 
     const swapTx = await program.methods
-      .swap(new BN(Math.floor(parseFloat(swapIn) * LAMPORTS_9)), new BN(Math.floor(parseFloat(minOut) * LAMPORTS_9)), isAToB)
+      .swap(new BN(Math.floor(parseFloat(swapIn) * LAMPORTS_9)), new BN(Math.floor(parseFloat(minOut) * LAMPORTS_9)), isAToB, null)
       .accounts({
+        config: deriveConfig(PROGRAM_ID),
         pool: pdas.pool,
         poolAuthority: pdas.poolAuthority,
         tokenAVault: pdas.tokenAVault,
@@ -227,7 +259,7 @@ export default function Home() {
         userTokenA,
         userTokenB,
         user: wallet.publicKey,
-        tokenProgram: TOKEN_PROGRAM_ID,
+        ...(await getPairPrograms(program.provider.connection, pair.mintA, pair.mintB)),
       })
       .transaction();
 
@@ -247,35 +279,40 @@ export default function Home() {
     loadBalances();
 
     return sig;
-  }, [wallet, mintsValid, pdas, tokenA, tokenB, swapIn, swapDir, minOut, loadBalances]);
+  }, [wallet, pair, pdas, swapIn, swapDir, minOut, loadBalances]);
 
   const [lpAmount, setLpAmount] = useState("0");
 
   const onRemove = useCallback(async () => {
     if (!wallet.publicKey || !wallet.signTransaction) throw new Error("Connect a wallet");
-    if (!mintsValid || !pdas) throw new Error("Enter valid token mint addresses");
+    if (!pair || !pdas) throw new Error("Enter valid token mint addresses");
     const program = await getProgram(wallet);
 
     const { ixs, atAs } = await ensureAtas(
       program.provider.connection,
       wallet.publicKey,
-      [pdas.lpTokenMint, toPubkey(tokenA), toPubkey(tokenB)]
+      [pdas.lpTokenMint, pair.mintA, pair.mintB]
     );
     const [userLpToken, userTokenA, userTokenB] = atAs;
+    const feeRecipientLp = await getFeeRecipientLpIx(program, wallet.publicKey, pdas.lpTokenMint);
+    if (feeRecipientLp.ix) ixs.push(feeRecipientLp.ix);
 
     const removeTx = await program.methods
-      .removeLiquidity(new BN(Math.floor(parseFloat(lpAmount) * LAMPORTS_9)), new BN(0), new BN(0))
+      .removeLiquidity(new BN(Math.floor(parseFloat(lpAmount) * LAMPORTS_9)), new BN(0), new BN(0), null)
       .accounts({
         pool: pdas.pool,
         poolAuthority: pdas.poolAuthority,
         tokenAVault: pdas.tokenAVault,
         tokenBVault: pdas.tokenBVault,
         lpTokenMint: pdas.lpTokenMint,
+        config: deriveConfig(PROGRAM_ID),
+        feeRecipientLpToken: feeRecipientLp.address,
         userTokenA,
         userTokenB,
         userLpToken,
         user: wallet.publicKey,
         tokenProgram: TOKEN_PROGRAM_ID,
+        ...(await getPairPrograms(program.provider.connection, pair.mintA, pair.mintB)),
       })
       .transaction();
 
@@ -295,7 +332,7 @@ export default function Home() {
     loadBalances();
 
     return sig;
-  }, [wallet, mintsValid, pdas, tokenA, tokenB, lpAmount, loadBalances]);
+  }, [wallet, pair, pdas, lpAmount, loadBalances]);
 
   // Auto-load balances when wallet connects or tokens change
   useEffect(() => {
@@ -0,0 +1,770 @@
+use anchor_lang::prelude::*;
+use crate::errors::DexError;
+use crate::math::U256;
+use crate::state::{Pool, Position, Tick, TickArray, TICK_ARRAY_SIZE};
+use crate::utils::{calculate_protocol_fee, FEE_DEN, Q64_SHIFT};
+
+/// Lowest tick a position may use; sqrt_price_at_tick(MIN_TICK) ~ 2^-32 in Q64.64 terms
+pub const MIN_TICK: i32 = -443_636;
+
+/// Highest tick a position may use; sqrt_price_at_tick(MAX_TICK) ~ 2^32 in Q64.64 terms
+pub const MAX_TICK: i32 = 443_636;
+
+/// sqrt_price_at_tick(MIN_TICK)
+pub const MIN_SQRT_PRICE: u128 = 4_295_048_017;
+
+/// sqrt_price_at_tick(MAX_TICK)
+pub const MAX_SQRT_PRICE: u128 = 79_226_673_515_401_279_992_447_579_062;
+
+/// Largest tick spacing a concentrated pool accepts
+pub const MAX_TICK_SPACING: u16 = 1_000;
+
+/// 2^128 / sqrt(1.0001)^(2^i) in Q128, for the bits of |tick| (Uniswap's TickMath table)
+const TICK_FACTORS: [u128; 20] = [
+    0xfffcb933bd6fad37aa2d162d1a594001,
+    0xfff97272373d413259a46990580e2139,
+    0xfff2e50f5f656932ef12357cf3c7fdcb,
+    0xffe5caca7e10e4e61c3624eaa0941ccf,
+    0xffcb9843d60f6159c9db58835c926643,
+    0xff973b41fa98c081472e6896dfb254bf,
+    0xff2ea16466c96a3843ec78b326b52860,
+    0xfe5dee046a99a2a811c461f1969c3052,
+    0xfcbe86c7900a88aedcffc83b479aa3a3,
+    0xf987a7253ac413176f2b074cf7815e53,
+    0xf3392b0822b70005940c7a398e4b70f2,
+    0xe7159475a2c29b7443b29c7fa6e889d8,
+    0xd097f3bdfd2022b8845ad8f792aa5825,
+    0xa9f746462d870fdf8a65dc1f90e061e4,
+    0x70d869a156d2a1b890bb3df62baf32f6,
+    0x31be135f97d08fd981231505542fcfa5,
+    0x09aa508b5b7a84e1c677de54f3e99bc8,
+    0x005d6af8dedb81196699c329225ee604,
+    0x00002216e584f5fa1ea926041bedfe97,
+    0x00000000048a170391f7dc42444e8fa2,
+];
+
+/// 2^32 / log2(sqrt(1.0001)), converts a Q64 log2 into ticks
+const TICKS_PER_LOG2_X32: i128 = 59_543_866_431_248;
+
+/// sqrt(1.0001^tick) in Q64.64.
+///
+/// Multiplies the Q128 factors for the set bits of |tick| (all below 1), inverts
+/// for positive ticks, and rounds the Q128 result up to Q64.64.
+///
+/// # Errors
+/// - `InvalidTick`: If tick is outside `MIN_TICK..=MAX_TICK`
+pub fn sqrt_price_at_tick(tick: i32) -> Result<u128> {
+    require!((MIN_TICK..=MAX_TICK).contains(&tick), DexError::InvalidTick);
+    let abs_tick = tick.unsigned_abs();
+    let one = U256::one() << 128;
+    let mut ratio = if abs_tick & 1 != 0 {
+        U256::from(TICK_FACTORS[0])
+    } else {
+        one
+    };
+    for (i, factor) in TICK_FACTORS.iter().enumerate().skip(1) {
+        if abs_tick & (1 << i) != 0 {
+            ratio = (ratio * U256::from(*factor)) >> 128;
+        }
+    }
+    if tick > 0 {
+        ratio = U256::MAX / ratio;
+    }
+
+    let round_up = !(ratio & U256::from(u64::MAX)).is_zero();
+    Ok((ratio >> Q64_SHIFT).as_u128() + round_up as u128)
+}
+
+/// Greatest tick whose sqrt price is at or below `sqrt_price` (Q64.64).
+///
+/// Estimates log2 with 16 fractional bits by repeated squaring, converts to ticks,
+/// then settles the estimate (off by at most one) against `sqrt_price_at_tick`.
+///
+/// # Errors
+/// - `InvalidSqrtPrice`: If sqrt_price is outside `MIN_SQRT_PRICE..=MAX_SQRT_PRICE`
+pub fn tick_at_sqrt_price(sqrt_price: u128) -> Result<i32> {
+    require!(
+        (MIN_SQRT_PRICE..=MAX_SQRT_PRICE).contains(&sqrt_price),
+        DexError::InvalidSqrtPrice
+    );
+    let msb = 127 - sqrt_price.leading_zeros() as i128;
+    let mut log2_x64 = (msb - 64) << 64;
+    // normalize into [2^63, 2^64), i.e. [1, 2) in Q63
+    let mut r = if msb >= 63 {
+        sqrt_price >> (msb - 63)
+    } else {
+        sqrt_price << (63 - msb)
+    };
+    let mut bit: i128 = 1 << 63;
+    for _ in 0..16 {
+        r = (r * r) >> 63;
+        if r >= 1 << 64 {
+            r >>= 1;
+            log2_x64 += bit;
+        }
+        bit >>= 1;
+    }
+
+    let estimate = ((log2_x64 * TICKS_PER_LOG2_X32) >> 96) as i32;
+    let mut tick = estimate.clamp(MIN_TICK, MAX_TICK);
+    while tick > MIN_TICK && sqrt_price_at_tick(tick)? > sqrt_price {
+        tick -= 1;
+    }
+    while tick < MAX_TICK && sqrt_price_at_tick(tick + 1)? <= sqrt_price {
+        tick += 1;
+    }
+    Ok(tick)
+}
+
+/// Divides, rounding up if `round_up`
+fn div_round(numerator: U256, denominator: U256, round_up: bool) -> Result<U256> {
+    require!(!denominator.is_zero(), DexError::MathOverflow);
+    let quotient = numerator / denominator;
+    if round_up && !(numerator % denominator).is_zero() {
+        Ok(quotient + 1)
+    } else {
+        Ok(quotient)
+    }
+}
+
+fn to_u128(value: U256) -> Result<u128> {
+    require!(value <= U256::from(u128::MAX), DexError::MathOverflow);
+    Ok(value.as_u128())
+}
+
+/// Token A held by `liquidity` between two sqrt prices
+///
+/// ```text
+/// amount_a = liquidity * (sqrt_upper - sqrt_lower) / (sqrt_upper * sqrt_lower)
+/// ```
+pub fn get_amount_a_delta(
+    sqrt_price_0: u128,
+    sqrt_price_1: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<u128> {
+    let (lower, upper) = (sqrt_price_0.min(sqrt_price_1), sqrt_price_0.max(sqrt_price_1));
+    if lower == upper || liquidity == 0 {
+        return Ok(0);
+    }
+    let numerator = (U256::from(liquidity) << Q64_SHIFT)
+        .checked_mul(U256::from(upper - lower))
+        .ok_or(DexError::MathOverflow)?;
+    let amount = div_round(
+        div_round(numerator, U256::from(upper), round_up)?,
+        U256::from(lower),
+        round_up,
+    )?;
+    to_u128(amount)
+}
+
+/// Token B held by `liquidity` between two sqrt prices
+///
+/// ```text
+/// amount_b = liquidity * (sqrt_upper - sqrt_lower)
+/// ```
+pub fn get_amount_b_delta(
+    sqrt_price_0: u128,
+    sqrt_price_1: u128,
+    liquidity: u128,
+    round_up: bool,
+) -> Result<u128> {
+    let (lower, upper) = (sqrt_price_0.min(sqrt_price_1), sqrt_price_0.max(sqrt_price_1));
+    let product = U256::from(liquidity) * U256::from(upper - lower);
+    to_u128(div_round(product, U256::one() << Q64_SHIFT, round_up)?)
+}
+
+/// Sqrt price after adding `amount_in` (net of fees) at `liquidity`.
+///
+/// Token A in moves the price down: `sqrt' = L / (L / sqrt + amount)`, rounded up.
+/// Token B in moves it up: `sqrt' = sqrt + amount / L`, rounded down.
+/// Both roundings keep the price from overshooting in the trader's favor.
+fn next_sqrt_price_from_input(
+    sqrt_price: u128,
+    liquidity: u128,
+    amount_in: u128,
+    a_to_b: bool,
+) -> Result<u128> {
+    if amount_in == 0 {
+        return Ok(sqrt_price);
+    }
+    let scaled_liquidity = U256::from(liquidity) << Q64_SHIFT;
+    if a_to_b {
+        let denominator = (scaled_liquidity / U256::from(sqrt_price))
+            .checked_add(U256::from(amount_in))
+            .ok_or(DexError::MathOverflow)?;
+        to_u128(div_round(scaled_liquidity, denominator, true)?)
+    } else {
+        let delta = to_u128((U256::from(amount_in) << Q64_SHIFT) / U256::from(liquidity))?;
+        Ok(sqrt_price.checked_add(delta).ok_or(DexError::MathOverflow)?)
+    }
+}
+
+/// One step of an exact-input swap within a range of constant liquidity.
+#[derive(Debug, PartialEq, Eq)]
+pub struct SwapStep {
+    pub sqrt_price_next: u128,
+    pub amount_in: u128,
+    pub amount_out: u128,
+    pub fee_amount: u128,
+}
+
+/// Moves the price from `sqrt_price_current` towards `sqrt_price_target`, spending at
+/// most `amount_remaining` (fee included) at `fee_bps` (Uniswap v3 `computeSwapStep`).
+///
+/// The input is rounded up and the output down.
+pub fn compute_swap_step(
+    sqrt_price_current: u128,
+    sqrt_price_target: u128,
+    liquidity: u128,
+    amount_remaining: u128,
+    fee_bps: u64,
+) -> Result<SwapStep> {
+    let a_to_b = sqrt_price_current >= sqrt_price_target;
+    let fee_bps = fee_bps as u128;
+    let amount_remaining_less_fee = amount_remaining
+        .checked_mul(FEE_DEN - fee_bps)
+        .ok_or(DexError::MathOverflow)?
+        / FEE_DEN;
+
+    // an input that overflows u128 can never be paid, so the target is out of reach
+    let amount_in_to_target = if a_to_b {
+        get_amount_a_delta(sqrt_price_target, sqrt_price_current, liquidity, true)
+    } else {
+        get_amount_b_delta(sqrt_price_current, sqrt_price_target, liquidity, true)
+    }
+    .unwrap_or(u128::MAX);
+
+    let sqrt_price_next = if amount_remaining_less_fee >= amount_in_to_target {
+        sqrt_price_target
+    } else {
+        next_sqrt_price_from_input(
+            sqrt_price_current,
+            liquidity,
+            amount_remaining_less_fee,
+            a_to_b,
+        )?
+    };
+    let reached_target = sqrt_price_next == sqrt_price_target;
+
+    let (amount_in, amount_out) = if a_to_b {
+        (
+            if reached_target {
+                amount_in_to_target
+            } else {
+                get_amount_a_delta(sqrt_price_next, sqrt_price_current, liquidity, true)?
+            },
+            get_amount_b_delta(sqrt_price_next, sqrt_price_current, liquidity, false)?,
+        )
+    } else {
+        (
+            if reached_target {
+                amount_in_to_target
+            } else {
+                get_amount_b_delta(sqrt_price_current, sqrt_price_next, liquidity, true)?
+            },
+            get_amount_a_delta(sqrt_price_current, sqrt_price_next, liquidity, false)?,
+        )
+    };
+
+    // short of the target the whole remainder is spent, so the rest is fee
+    let fee_amount = if reached_target {
+        (amount_in * fee_bps).div_ceil(FEE_DEN - fee_bps)
+    } else {
+        amount_remaining
+            .checked_sub(amount_in)
+            .ok_or(DexError::MathOverflow)?
+    };
+
+    Ok(SwapStep {
+        sqrt_price_next,
+        amount_in,
+        amount_out,
+        fee_amount,
+    })
+}
+
+/// Spot prices `(B per A, A per B)` in Q64.64 for a Q64.64 sqrt price, saturating at u128::MAX
+pub fn spot_prices_q64(sqrt_price: u128) -> (u128, u128) {
+    let squared = U256::from(sqrt_price) * U256::from(sqrt_price);
+    if squared.is_zero() {
+        return (0, 0);
+    }
+    let saturate = |price: U256| {
+        if price > U256::from(u128::MAX) {
+            u128::MAX
+        } else {
+            price.as_u128()
+        }
+    };
+    (
+        saturate(squared >> Q64_SHIFT),
+        saturate((U256::one() << (3 * Q64_SHIFT)) / squared),
+    )
+}
+
+/// Adds a signed liquidity delta to `liquidity`
+pub fn add_liquidity_delta(liquidity: u128, delta: i128) -> Result<u128> {
+    let result = if delta >= 0 {
+        liquidity.checked_add(delta as u128)
+    } else {
+        liquidity.checked_sub(delta.unsigned_abs())
+    };
+    Ok(result.ok_or(DexError::MathOverflow)?)
+}
+
+/// Fee growth per unit of liquidity between two ticks (Uniswap v3 `getFeeGrowthInside`).
+///
+/// All fee growth values wrap; only differences are meaningful.
+pub fn fee_growth_inside(
+    lower: &Tick,
+    tick_lower: i32,
+    upper: &Tick,
+    tick_upper: i32,
+    tick_current: i32,
+    fee_growth_global_a: u128,
+    fee_growth_global_b: u128,
+) -> (u128, u128) {
+    let below = |outside: u128, global: u128| {
+        if tick_current >= tick_lower {
+            outside
+        } else {
+            global.wrapping_sub(outside)
+        }
+    };
+    let above = |outside: u128, global: u128| {
+        if tick_current < tick_upper {
+            outside
+        } else {
+            global.wrapping_sub(outside)
+        }
+    };
+    let inside = |global: u128, lower_outside: u128, upper_outside: u128| {
+        global
+            .wrapping_sub(below(lower_outside, global))
+            .wrapping_sub(above(upper_outside, global))
+    };
+    (
+        inside(
+            fee_growth_global_a,
+            lower.fee_growth_outside_a,
+            upper.fee_growth_outside_a,
+        ),
+        inside(
+            fee_growth_global_b,
+            lower.fee_growth_outside_b,
+            upper.fee_growth_outside_b,
+        ),
+    )
+}
+
+/// Applies `liquidity_delta` to a position, its two ticks and the pool's active liquidity.
+///
+/// Pass `upper_array` as `None` when both ticks live in `lower_array`.
+///
+/// # Returns
+/// `(amount_a, amount_b)` the position gains (rounded up) or releases (rounded down)
+pub fn modify_position(
+    pool: &mut Pool,
+    position: &mut Position,
+    lower_array: &mut TickArray,
+    mut upper_array: Option<&mut TickArray>,
+    liquidity_delta: i128,
+) -> Result<(u64, u64)> {
+    let (tick_lower, tick_upper) = (position.tick_lower, position.tick_upper);
+    let (global_a, global_b) = (pool.fee_growth_global_a, pool.fee_growth_global_b);
+    let spacing = pool.tick_spacing;
+
+    let lower = lower_array.tick_mut(tick_lower, spacing)?;
+    lower.update(tick_lower, pool.tick_current, liquidity_delta, global_a, global_b, false)?;
+    let lower = *lower;
+    let upper = match upper_array.as_deref_mut() {
+        Some(array) => array,
+        None => &mut *lower_array,
+    }
+    .tick_mut(tick_upper, spacing)?;
+    upper.update(tick_upper, pool.tick_current, liquidity_delta, global_a, global_b, true)?;
+    let upper = *upper;
+
+    let (inside_a, inside_b) = fee_growth_inside(
+        &lower,
+        tick_lower,
+        &upper,
+        tick_upper,
+        pool.tick_current,
+        global_a,
+        global_b,
+    );
+    position.update(liquidity_delta, inside_a, inside_b)?;
+
+    // a tick nobody references any more is cleared, so crossing it is free
+    if lower.liquidity_gross == 0 {
+        *lower_array.tick_mut(tick_lower, spacing)? = Tick::default();
+    }
+    if upper.liquidity_gross == 0 {
+        *match upper_array {
+            Some(array) => array,
+            None => lower_array,
+        }
+        .tick_mut(tick_upper, spacing)? = Tick::default();
+    }
+
+    let round_up = liquidity_delta > 0;
+    let liquidity = liquidity_delta.unsigned_abs();
+    let sqrt_lower = sqrt_price_at_tick(tick_lower)?;
+    let sqrt_upper = sqrt_price_at_tick(tick_upper)?;
+    let (amount_a, amount_b) = if pool.tick_current < tick_lower {
+        (get_amount_a_delta(sqrt_lower, sqrt_upper, liquidity, round_up)?, 0)
+    } else if pool.tick_current >= tick_upper {
+        (0, get_amount_b_delta(sqrt_lower, sqrt_upper, liquidity, round_up)?)
+    } else {
+        pool.liquidity = add_liquidity_delta(pool.liquidity, liquidity_delta)?;
+        (
+            get_amount_a_delta(pool.sqrt_price, sqrt_upper, liquidity, round_up)?,
+            get_amount_b_delta(sqrt_lower, pool.sqrt_price, liquidity, round_up)?,
+        )
+    };
+    Ok((
+        u64::try_from(amount_a).map_err(|_| error!(DexError::MathOverflow))?,
+        u64::try_from(amount_b).map_err(|_| error!(DexError::MathOverflow))?,
+    ))
+}
+
+/// Next tick to swap towards: the nearest initialized tick in the swap direction, or
+/// the last tick the passed arrays cover.
+///
+/// # Returns
+/// `(array_index, tick, initialized)`
+///
+/// # Errors
+/// - `TickArraysExhausted`: If the arrays cover nothing further in the swap direction
+fn next_tick(
+    tick_arrays: &[&mut TickArray],
+    tick_current: i32,
+    spacing: u16,
+    a_to_b: bool,
+) -> Result<(usize, i32, bool)> {
+    for (index, array) in tick_arrays.iter().enumerate() {
+        if let Some(tick) = array.next_initialized_tick(tick_current, spacing, a_to_b) {
+            return Ok((index, tick, true));
+        }
+    }
+    let last_index = tick_arrays.len() - 1;
+    let last = &tick_arrays[last_index];
+    let boundary = if a_to_b {
+        last.start_tick_index
+    } else {
+        last.start_tick_index + (TICK_ARRAY_SIZE as i32 - 1) * spacing as i32
+    };
+    let ahead = if a_to_b {
+        boundary <= tick_current
+    } else {
+        boundary > tick_current
+    };
+    require!(ahead, DexError::TickArraysExhausted);
+    Ok((last_index, boundary, false))
+}
+
+/// Totals of a concentrated swap
+pub struct SwapResult {
+    /// Input consumed, fees included
+    pub amount_in: u64,
+    pub amount_out: u64,
+    /// Part of the fees kept for the protocol, outside the reserves
+    pub protocol_fee: u64,
+}
+
+/// Exact-input swap across initialized ticks (Uniswap v3 `swap`), updating the pool's
+/// price, tick, liquidity and fee growth and the crossed ticks.
+///
+/// Stops early at `sqrt_price_limit`, so `amount_in` may be only partly used.
+/// `tick_arrays` must belong to the pool, start at the array holding the current
+/// tick and continue in the swap direction.
+///
+/// # Errors
+/// - `InvalidSqrtPrice`: If the limit is not strictly ahead of the price in the swap direction
+/// - `InvalidTickArray`: If the arrays are out of sequence
+/// - `TickArraysExhausted`: If the input outlasts the passed tick arrays
+pub fn swap(
+    pool: &mut Pool,
+    tick_arrays: &mut [&mut TickArray],
+    amount_in: u64,
+    a_to_b: bool,
+    sqrt_price_limit: u128,
+    fee_bps: u64,
+    protocol_fee_share_bps: u64,
+) -> Result<SwapResult> {
+    require!(amount_in > 0, DexError::InvalidAmount);
+    if a_to_b {
+        require!(
+            sqrt_price_limit < pool.sqrt_price && sqrt_price_limit >= MIN_SQRT_PRICE,
+            DexError::InvalidSqrtPrice
+        );
+    } else {
+        require!(
+            sqrt_price_limit > pool.sqrt_price && sqrt_price_limit <= MAX_SQRT_PRICE,
+            DexError::InvalidSqrtPrice
+        );
+    }
+    let span = TICK_ARRAY_SIZE as i32 * pool.tick_spacing as i32;
+    for (index, array) in tick_arrays.iter().enumerate() {
+        let expected_start = tick_arrays[0].start_tick_index
+            + if a_to_b { -span } else { span } * index as i32;
+        require!(
+            array.start_tick_index == expected_start,
+            DexError::InvalidTickArray
+        );
+    }
+    require!(
+        tick_arrays[0].contains(pool.tick_current, pool.tick_spacing),
+        DexError::InvalidTickArray
+    );
+
+    let mut amount_remaining = amount_in as u128;
+    let mut amount_out: u128 = 0;
+    let mut protocol_fee: u64 = 0;
+    while amount_remaining > 0 && pool.sqrt_price != sqrt_price_limit {
+        let (array_index, tick_next, initialized) =
+            next_tick(tick_arrays, pool.tick_current, pool.tick_spacing, a_to_b)?;
+        let tick_next = tick_next.clamp(MIN_TICK, MAX_TICK);
+        let sqrt_price_next_tick = sqrt_price_at_tick(tick_next)?;
+        let sqrt_price_target = if a_to_b {
+            sqrt_price_next_tick.max(sqrt_price_limit)
+        } else {
+            sqrt_price_next_tick.min(sqrt_price_limit)
+        };
+
+        let step = compute_swap_step(
+            pool.sqrt_price,
+            sqrt_price_target,
+            pool.liquidity,
+            amount_remaining,
+            fee_bps,
+        )?;
+        amount_remaining -= step.amount_in + step.fee_amount;
+        amount_out += step.amount_out;
+
+        // the protocol's share comes off the fee; the rest accrues to in-range liquidity
+        let step_fee = step.fee_amount as u64;
+        let step_protocol_fee = calculate_protocol_fee(step_fee, FEE_DEN as u64, protocol_fee_share_bps)?;
+        protocol_fee += step_protocol_fee;
+        if pool.liquidity > 0 {
+            let growth = to_u128(
+                (U256::from(step_fee - step_protocol_fee) << Q64_SHIFT) / U256::from(pool.liquidity),
+            )?;
+            if a_to_b {
+                pool.fee_growth_global_a = pool.fee_growth_global_a.wrapping_add(growth);
+            } else {
+                pool.fee_growth_global_b = pool.fee_growth_global_b.wrapping_add(growth);
+            }
+        }
+
+        let sqrt_price_before = pool.sqrt_price;
+        pool.sqrt_price = step.sqrt_price_next;
+        if step.sqrt_price_next == sqrt_price_next_tick {
+            if initialized {
+                let tick = tick_arrays[array_index].tick_mut(tick_next, pool.tick_spacing)?;
+                let liquidity_net = tick.cross(pool.fee_growth_global_a, pool.fee_growth_global_b);
+                // leaving a range downwards removes what entering it upwards adds
+                let delta = if a_to_b { -liquidity_net } else { liquidity_net };
+                pool.liquidity = add_liquidity_delta(pool.liquidity, delta)?;
+            }
+            pool.tick_current = if a_to_b { tick_next - 1 } else { tick_next };
+        } else if step.sqrt_price_next != sqrt_price_before {
+            pool.tick_current = tick_at_sqrt_price(step.sqrt_price_next)?;
+        }
+    }
+
+    Ok(SwapResult {
+        amount_in: (amount_in as u128 - amount_remaining) as u64,
+        amount_out: u64::try_from(amount_out).map_err(|_| error!(DexError::MathOverflow))?,
+        protocol_fee,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ONE_Q64: u128 = 1 << Q64_SHIFT;
+
+    /// Ticks at ±`MAX_TICK`, 0 and either side of every bit of the `TICK_FACTORS` table
+    fn boundary_ticks() -> Vec<i32> {
+        let mut ticks = vec![MIN_TICK, MIN_TICK + 1, 0, MAX_TICK - 1, MAX_TICK];
+        for bit in 0..TICK_FACTORS.len() {
+            for tick in [(1 << bit) - 1, 1 << bit, (1 << bit) + 1] {
+                if tick <= MAX_TICK {
+                    ticks.extend([tick, -tick]);
+                }
+            }
+        }
+        ticks
+    }
+
+    #[test]
+    fn sqrt_price_bounds_match_the_extreme_ticks() {
+        assert_eq!(sqrt_price_at_tick(MIN_TICK).unwrap(), MIN_SQRT_PRICE);
+        assert_eq!(sqrt_price_at_tick(MAX_TICK).unwrap(), MAX_SQRT_PRICE);
+        assert_eq!(sqrt_price_at_tick(0).unwrap(), ONE_Q64);
+        assert_eq!(
+            sqrt_price_at_tick(MIN_TICK - 1).unwrap_err(),
+            DexError::InvalidTick.into()
+        );
+        assert_eq!(
+            sqrt_price_at_tick(MAX_TICK + 1).unwrap_err(),
+            DexError::InvalidTick.into()
+        );
+        assert_eq!(
+            tick_at_sqrt_price(MIN_SQRT_PRICE - 1).unwrap_err(),
+            DexError::InvalidSqrtPrice.into()
+        );
+        assert_eq!(
+            tick_at_sqrt_price(MAX_SQRT_PRICE + 1).unwrap_err(),
+            DexError::InvalidSqrtPrice.into()
+        );
+    }
+
+    #[test]
+    fn sqrt_price_at_tick_rounds_up_within_two_units() {
+        // (tick, floor of sqrt(1.0001^tick) * 2^64), references to 100 digits
+        let cases: [(i32, u128); 8] = [
+            (MIN_TICK, 4_295_048_016),
+            (-262_144, 37_481_735_321_082),
+            (-1, 18_445_821_805_675_392_311),
+            (1, 18_447_666_387_855_959_850),
+            (1_024, 19_415_764_168_677_886_926),
+            (262_144, 9_078_618_265_828_848_800_676_189),
+            (MAX_TICK - 1, 79_222_712_478_800_779_441_888_593_669),
+            (MAX_TICK, 79_226_673_515_401_279_992_447_579_061),
+        ];
+        for (tick, reference) in cases {
+            let sqrt_price = sqrt_price_at_tick(tick).unwrap();
+            assert!(
+                sqrt_price > reference && sqrt_price <= reference + 2,
+                "tick {tick}: {sqrt_price} vs {reference}"
+            );
+        }
+    }
+
+    #[test]
+    fn sqrt_price_at_tick_is_strictly_increasing() {
+        let mut ticks = boundary_ticks();
+        ticks.extend((MIN_TICK..=MAX_TICK).step_by(997));
+        ticks.sort_unstable();
+        ticks.dedup();
+        for pair in ticks.windows(2) {
+            assert!(sqrt_price_at_tick(pair[0]).unwrap() < sqrt_price_at_tick(pair[1]).unwrap());
+        }
+    }
+
+    #[test]
+    fn tick_at_sqrt_price_round_trips() {
+        let mut ticks = boundary_ticks();
+        ticks.extend((MIN_TICK..=MAX_TICK).step_by(997));
+        for tick in ticks {
+            let sqrt_price = sqrt_price_at_tick(tick).unwrap();
+            assert_eq!(tick_at_sqrt_price(sqrt_price).unwrap(), tick);
+            // one unit below a tick's price belongs to the tick beneath it
+            if tick > MIN_TICK {
+                assert_eq!(tick_at_sqrt_price(sqrt_price - 1).unwrap(), tick - 1);
+            }
+            if tick < MAX_TICK {
+                let next = sqrt_price_at_tick(tick + 1).unwrap();
+                assert_eq!(tick_at_sqrt_price(next - 1).unwrap(), tick);
+            }
+        }
+    }
+
+    #[test]
+    fn amount_deltas_round_in_the_requested_direction() {
+        let prices = [MIN_SQRT_PRICE, ONE_Q64 / 3, ONE_Q64, ONE_Q64 * 7 + 5, MAX_SQRT_PRICE];
+        for liquidity in [1, 1_000_003, u64::MAX as u128] {
+            for &lower in &prices {
+                for &upper in prices.iter().filter(|&&upper| upper > lower) {
+                    // exact amounts as U256 fractions
+                    let a_numerator =
+                        (U256::from(liquidity) << Q64_SHIFT) * U256::from(upper - lower);
+                    let a_denominator = U256::from(upper) * U256::from(lower);
+                    let b_numerator = U256::from(liquidity) * U256::from(upper - lower);
+                    let b_denominator = U256::one() << Q64_SHIFT;
+                    let deltas = [
+                        (
+                            get_amount_a_delta(lower, upper, liquidity, false),
+                            get_amount_a_delta(upper, lower, liquidity, true),
+                            a_numerator,
+                            a_denominator,
+                        ),
+                        (
+                            get_amount_b_delta(lower, upper, liquidity, false),
+                            get_amount_b_delta(upper, lower, liquidity, true),
+                            b_numerator,
+                            b_denominator,
+                        ),
+                    ];
+                    for (down, up, numerator, denominator) in deltas {
+                        let floor = numerator / denominator;
+                        let exact = (numerator % denominator).is_zero();
+                        let ceil = if exact { floor } else { floor + 1 };
+                        if ceil > U256::from(u128::MAX) {
+                            assert!(up.is_err(), "{lower}..{upper} at {liquidity} overflows");
+                            continue;
+                        }
+                        assert_eq!(U256::from(down.unwrap()), floor);
+                        assert_eq!(U256::from(up.unwrap()), ceil);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn swap_step_stops_at_the_target() {
+        let liquidity = 1_000_000_000_000;
+        for (current, target) in [(ONE_Q64, ONE_Q64 / 2), (ONE_Q64, ONE_Q64 * 2)] {
+            let step = compute_swap_step(current, target, liquidity, u64::MAX as u128, 30).unwrap();
+            let needed = if current > target {
+                get_amount_a_delta(target, current, liquidity, true).unwrap()
+            } else {
+                get_amount_b_delta(current, target, liquidity, true).unwrap()
+            };
+            assert_eq!(step.sqrt_price_next, target);
+            assert_eq!(step.amount_in, needed);
+            // fee rounded up on top of what the move needs
+            assert_eq!(step.fee_amount, (needed * 30).div_ceil(FEE_DEN - 30));
+        }
+    }
+
+    #[test]
+    fn swap_step_short_of_the_target_favors_the_pool() {
+        let liquidity = 1_000_000_000_000;
+        for (current, target) in [(ONE_Q64, MIN_SQRT_PRICE), (ONE_Q64, MAX_SQRT_PRICE)] {
+            let a_to_b = current > target;
+            for amount_remaining in [1, 999, 1_000_000_007, 123_456_789_012] {
+                for fee_bps in [0, 30, 10_000 - 1] {
+                    let step =
+                        compute_swap_step(current, target, liquidity, amount_remaining, fee_bps)
+                            .unwrap();
+                    let next = step.sqrt_price_next;
+                    assert_ne!(next, target);
+                    // the whole remainder is spent, and the price never overshoots
+                    assert_eq!(step.amount_in + step.fee_amount, amount_remaining);
+                    let fee = (amount_remaining * fee_bps as u128).div_ceil(FEE_DEN);
+                    assert!(step.fee_amount >= fee, "fee {} below {fee}", step.fee_amount);
+                    if a_to_b {
+                        assert!(next <= current && next > target);
+                        let paid = get_amount_a_delta(next, current, liquidity, true).unwrap();
+                        let owed = get_amount_b_delta(next, current, liquidity, false).unwrap();
+                        assert!(step.amount_in >= paid);
+                        assert_eq!(step.amount_out, owed);
+                    } else {
+                        assert!(next >= current && next < target);
+                        let paid = get_amount_b_delta(current, next, liquidity, true).unwrap();
+                        let owed = get_amount_a_delta(current, next, liquidity, false).unwrap();
+                        assert!(step.amount_in >= paid);
+                        assert_eq!(step.amount_out, owed);
+                    }
+                }
+            }
+        }
+    }
+}
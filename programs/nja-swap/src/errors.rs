@@ -18,4 +18,70 @@ pub enum DexError {
     InitialLiquidityTooLow,
     #[msg("Pool reserves cannot be zero")]
     ZeroReserves,
+    #[msg("Pool fee must be below the fee denominator")]
+    FeeOutOfRange,
+    #[msg("Token mints must be in canonical order (token_a_mint < token_b_mint)")]
+    MintsNotSorted,
+    #[msg("Signer is not authorized for this action")]
+    Unauthorized,
+    #[msg("Swaps and deposits are paused")]
+    Paused,
+    #[msg("Transaction deadline has passed")]
+    DeadlineExceeded,
+    #[msg("Observation window must be positive")]
+    InvalidTimeWindow,
+    #[msg("Requested observation is older than the oldest stored one")]
+    ObservationTooOld,
+    #[msg("Observation cardinality must grow and stay within the maximum")]
+    InvalidObservationCardinality,
+    #[msg("Vault balance is below its recorded reserve plus unclaimed protocol fees")]
+    VaultBelowReserve,
+    #[msg("Mint has the permanent delegate extension, which can move pool funds")]
+    PermanentDelegateNotAllowed,
+    #[msg("Mint has the non-transferable extension")]
+    NonTransferableNotAllowed,
+    #[msg("Mint has the transfer hook extension")]
+    TransferHookNotAllowed,
+    #[msg("Mint has the default account state extension set to frozen")]
+    DefaultFrozenNotAllowed,
+    #[msg("Mint has the confidential transfer extension")]
+    ConfidentialTransferNotAllowed,
+    #[msg("Amplification coefficient out of range for the curve type")]
+    InvalidAmp,
+    #[msg("StableSwap invariant did not converge")]
+    InvariantNotConverged,
+    #[msg("A fee ramp is already in progress")]
+    RampInProgress,
+    #[msg("Fee ramp is shorter than the minimum duration")]
+    RampTooShort,
+    #[msg("Fee ramp changes the fee by more than the maximum factor")]
+    FeeChangeTooLarge,
+    #[msg("Pool weights must be at least 1% each for weighted pools, and 0 otherwise")]
+    InvalidWeight,
+    #[msg("Swap exceeds the weighted pool's maximum share of a reserve")]
+    MaxSwapRatioExceeded,
+    #[msg("Instruction does not support the pool's curve type")]
+    WrongCurveType,
+    #[msg("Tick spacing must be between 1 and the maximum")]
+    InvalidTickSpacing,
+    #[msg("Tick is out of range or not a multiple of the tick spacing")]
+    InvalidTick,
+    #[msg("Sqrt price is out of range or on the wrong side of the current price")]
+    InvalidSqrtPrice,
+    #[msg("Tick array does not cover the tick or is out of sequence")]
+    InvalidTickArray,
+    #[msg("Swap ran past the last tick array passed")]
+    TickArraysExhausted,
+    #[msg("Route hop accounts do not match their pool or do not chain")]
+    InvalidRoute,
+    #[msg("A flash loan is outstanding on this pool")]
+    FlashLoanActive,
+    #[msg("No flash loan is outstanding on this pool")]
+    NoFlashLoan,
+    #[msg("Flash loan has no matching flash_repay later in the transaction")]
+    FlashRepayMissing,
+    #[msg("Flash loans cannot be taken through CPI")]
+    FlashLoanCpi,
+    #[msg("The fee recipient's LP token account is required in LpMint mode")]
+    FeeRecipientLpTokenMissing,
 }
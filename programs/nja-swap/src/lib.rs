@@ -1,9 +1,17 @@
 use anchor_lang::prelude::*;
-use anchor_spl::token::{self, Burn, Mint, MintTo, Token, TokenAccount, Transfer};
+use anchor_lang::solana_program::instruction::{get_stack_height, TRANSACTION_LEVEL_STACK_HEIGHT};
+use anchor_lang::solana_program::sysvar::instructions::{
+    load_current_index_checked, load_instruction_at_checked,
+};
+use anchor_spl::token_interface::{self, Burn, Mint, MintTo, TokenAccount, TokenInterface};
 
+pub mod clmm;
 pub mod errors;
+pub mod math;
+pub mod stable;
 pub mod state;
 pub mod utils;
+pub mod weighted;
 
 use errors::*;
 use state::*;
@@ -18,6 +26,55 @@ pub struct PoolInitialized {
     pub token_a_mint: Pubkey,
     pub token_b_mint: Pubkey,
     pub lp_token_mint: Pubkey,
+    pub pool_index: u16,
+    pub fee_bps: u64,
+    pub curve_type: CurveType,
+    pub amp: u64,
+    pub weight_a: u64,
+    pub weight_b: u64,
+    pub tick_spacing: u16,
+    pub sqrt_price: u128,
+}
+
+#[event]
+pub struct ConfigUpdated {
+    pub admin: Pubkey,
+    pub default_fee_bps: u64,
+    pub protocol_fee_share_bps: u64,
+    pub protocol_fee_mode: ProtocolFeeMode,
+    pub fee_recipient: Pubkey,
+}
+
+#[event]
+pub struct AdminTransferStarted {
+    pub admin: Pubkey,
+    pub pending_admin: Pubkey,
+}
+
+#[event]
+pub struct AdminTransferred {
+    pub previous_admin: Pubkey,
+    pub new_admin: Pubkey,
+}
+
+#[event]
+pub struct GuardianUpdated {
+    pub old: Pubkey,
+    pub new: Pubkey,
+}
+
+#[event]
+pub struct PauseUpdated {
+    /// None for the global flag
+    pub pool: Option<Pubkey>,
+    pub authority: Pubkey,
+    pub paused: bool,
+}
+
+#[event]
+pub struct MintAllowlistUpdated {
+    pub mint: Pubkey,
+    pub allowed: bool,
 }
 
 #[event]
@@ -45,96 +102,584 @@ pub struct SwapExecuted {
     pub amount_in: u64,
     pub amount_out: u64,
     pub is_a_to_b: bool,
+    pub protocol_fee: u64,
+}
+
+#[event]
+pub struct ProtocolFeesCollected {
+    pub pool: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+#[event]
+pub struct FeeRampScheduled {
+    pub pool: Pubkey,
+    pub start_fee_bps: u64,
+    pub target_fee_bps: u64,
+    pub start_time: i64,
+    pub end_time: i64,
+}
+
+#[event]
+pub struct FeeRampStopped {
+    pub pool: Pubkey,
+    pub fee_bps: u64,
+}
+
+#[event]
+pub struct ReservesSynced {
+    pub pool: Pubkey,
+    pub reserve_a: u64,
+    pub reserve_b: u64,
+}
+
+#[event]
+pub struct ExcessSkimmed {
+    pub pool: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+#[event]
+pub struct FlashLoanFeeUpdated {
+    pub admin: Pubkey,
+    pub flash_loan_fee_bps: u64,
+}
+
+#[event]
+pub struct FlashLoanTaken {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub fee_a: u64,
+    pub fee_b: u64,
+}
+
+#[event]
+pub struct FlashLoanRepaid {
+    pub pool: Pubkey,
+    pub user: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
+    pub fee_a: u64,
+    pub fee_b: u64,
+}
+
+#[event]
+pub struct PositionOpened {
+    pub pool: Pubkey,
+    pub position: Pubkey,
+    pub owner: Pubkey,
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+}
+
+#[event]
+pub struct PositionLiquidityChanged {
+    pub pool: Pubkey,
+    pub position: Pubkey,
+    /// Positive when liquidity was added
+    pub liquidity_delta: i128,
+    pub amount_a: u64,
+    pub amount_b: u64,
+}
+
+#[event]
+pub struct PositionFeesCollected {
+    pub pool: Pubkey,
+    pub position: Pubkey,
+    pub amount_a: u64,
+    pub amount_b: u64,
 }
 
 #[program]
 pub mod nja_swap {
     use super::*;
 
-    /// Initialize a new liquidity pool
-    pub fn initialize_pool(ctx: Context<Initialize>) -> Result<()> {
+    /// Create the global protocol config. Only the program's upgrade authority can call this,
+    /// and it becomes the first admin.
+    pub fn initialize_config(
+        ctx: Context<InitializeConfig>,
+        default_fee_bps: u64,
+        protocol_fee_share_bps: u64,
+        protocol_fee_mode: ProtocolFeeMode,
+        fee_recipient: Pubkey,
+    ) -> Result<()> {
+        require!((default_fee_bps as u128) < FEE_DEN, DexError::FeeOutOfRange);
+        require!(
+            protocol_fee_share_bps as u128 <= FEE_DEN,
+            DexError::InvalidFee
+        );
+
+        let config = &mut ctx.accounts.config;
+        config.admin = ctx.accounts.admin.key();
+        config.pending_admin = Pubkey::default();
+        config.default_fee_bps = default_fee_bps;
+        config.protocol_fee_share_bps = protocol_fee_share_bps;
+        config.protocol_fee_mode = protocol_fee_mode;
+        config.fee_recipient = fee_recipient;
+        config.bump = ctx.bumps.config;
+        config.guardian = ctx.accounts.admin.key();
+        config.paused = false;
+        config.flash_loan_fee_bps = 0;
+
+        emit!(ConfigUpdated {
+            admin: config.admin,
+            default_fee_bps,
+            protocol_fee_share_bps,
+            protocol_fee_mode,
+            fee_recipient,
+        });
+
+        msg!("Config initialized, admin: {}", config.admin);
+        Ok(())
+    }
+
+    /// Update the protocol settings. Admin only.
+    ///
+    /// Switching `protocol_fee_mode` only affects fees earned from then on: LpMint starts
+    /// tracking `k_last` at each pool's next liquidity event.
+    pub fn update_config(
+        ctx: Context<UpdateConfig>,
+        default_fee_bps: u64,
+        protocol_fee_share_bps: u64,
+        protocol_fee_mode: ProtocolFeeMode,
+        fee_recipient: Pubkey,
+    ) -> Result<()> {
+        require!((default_fee_bps as u128) < FEE_DEN, DexError::FeeOutOfRange);
+        require!(
+            protocol_fee_share_bps as u128 <= FEE_DEN,
+            DexError::InvalidFee
+        );
+
+        let config = &mut ctx.accounts.config;
+        config.default_fee_bps = default_fee_bps;
+        config.protocol_fee_share_bps = protocol_fee_share_bps;
+        config.protocol_fee_mode = protocol_fee_mode;
+        config.fee_recipient = fee_recipient;
+
+        emit!(ConfigUpdated {
+            admin: config.admin,
+            default_fee_bps,
+            protocol_fee_share_bps,
+            protocol_fee_mode,
+            fee_recipient,
+        });
+
+        msg!("Config updated");
+        Ok(())
+    }
+
+    /// Set the guardian, who can pause and unpause alongside the admin. Admin only.
+    pub fn set_guardian(ctx: Context<UpdateConfig>, guardian: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let old = config.guardian;
+        config.guardian = guardian;
+
+        emit!(GuardianUpdated { old, new: guardian });
+
+        msg!("Guardian set to {}", guardian);
+        Ok(())
+    }
+
+    /// Set the flash loan fee in basis points of the amount borrowed. Admin only.
+    pub fn set_flash_loan_fee(ctx: Context<UpdateConfig>, flash_loan_fee_bps: u64) -> Result<()> {
+        require!(
+            (flash_loan_fee_bps as u128) < FEE_DEN,
+            DexError::FeeOutOfRange
+        );
+        let config = &mut ctx.accounts.config;
+        config.flash_loan_fee_bps = flash_loan_fee_bps;
+
+        emit!(FlashLoanFeeUpdated {
+            admin: config.admin,
+            flash_loan_fee_bps,
+        });
+
+        msg!("Flash loan fee set to {} bps", flash_loan_fee_bps);
+        Ok(())
+    }
+
+    /// Pause or unpause swaps and deposits on every pool. Admin or guardian only.
+    pub fn set_global_pause(ctx: Context<SetGlobalPause>, paused: bool) -> Result<()> {
+        ctx.accounts.config.paused = paused;
+
+        emit!(PauseUpdated {
+            pool: None,
+            authority: ctx.accounts.authority.key(),
+            paused,
+        });
+
+        msg!("Global pause set to {}", paused);
+        Ok(())
+    }
+
+    /// Pause or unpause swaps and deposits on one pool. Admin or guardian only.
+    pub fn set_pool_pause(ctx: Context<SetPoolPause>, paused: bool) -> Result<()> {
         let pool = &mut ctx.accounts.pool;
+        pool.paused = paused;
+
+        emit!(PauseUpdated {
+            pool: Some(pool.key()),
+            authority: ctx.accounts.authority.key(),
+            paused,
+        });
+
+        msg!("Pool pause set to {}", paused);
+        Ok(())
+    }
+
+    /// Nominate `new_admin`. Rights only move once `new_admin` calls `accept_admin`.
+    pub fn transfer_admin(ctx: Context<UpdateConfig>, new_admin: Pubkey) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        config.pending_admin = new_admin;
+
+        emit!(AdminTransferStarted {
+            admin: config.admin,
+            pending_admin: new_admin,
+        });
+
+        msg!("Admin transfer to {} pending", new_admin);
+        Ok(())
+    }
+
+    /// Complete an admin transfer. Must be signed by the pending admin.
+    pub fn accept_admin(ctx: Context<AcceptAdmin>) -> Result<()> {
+        let config = &mut ctx.accounts.config;
+        let previous_admin = config.admin;
+        config.admin = ctx.accounts.pending_admin.key();
+        config.pending_admin = Pubkey::default();
 
+        emit!(AdminTransferred {
+            previous_admin,
+            new_admin: config.admin,
+        });
+
+        msg!("Admin transferred to {}", config.admin);
+        Ok(())
+    }
+
+    /// Allowlist a mint so `initialize_pool` skips its Token-2022 extension screening. Admin only.
+    pub fn allow_mint(ctx: Context<AllowMint>) -> Result<()> {
+        let allowed_mint = &mut ctx.accounts.allowed_mint;
+        allowed_mint.mint = ctx.accounts.mint.key();
+        allowed_mint.bump = ctx.bumps.allowed_mint;
+
+        emit!(MintAllowlistUpdated {
+            mint: allowed_mint.mint,
+            allowed: true,
+        });
+
+        msg!("Mint {} allowlisted", allowed_mint.mint);
+        Ok(())
+    }
+
+    /// Remove a mint from the allowlist. Existing pools are unaffected. Admin only.
+    pub fn disallow_mint(ctx: Context<DisallowMint>) -> Result<()> {
+        emit!(MintAllowlistUpdated {
+            mint: ctx.accounts.allowed_mint.mint,
+            allowed: false,
+        });
+
+        msg!("Mint {} removed from allowlist", ctx.accounts.allowed_mint.mint);
+        Ok(())
+    }
+
+    /// Move a pool's swap fee linearly to `target_fee` (basis points) by `end_time`. Admin only.
+    ///
+    /// The ramp must last at least `MIN_RAMP_DURATION` and may change the fee by at most
+    /// `MAX_FEE_CHANGE` times either way (a 0 fee counts as 1 bp). Fails while another ramp runs.
+    pub fn schedule_fee_ramp(ctx: Context<RampFee>, target_fee: u64, end_time: i64) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let now = Clock::get()?.unix_timestamp;
+        require!(now >= pool.ramp_end_time, DexError::RampInProgress);
+        require!((target_fee as u128) < FEE_DEN, DexError::FeeOutOfRange);
+        let min_end_time = now
+            .checked_add(MIN_RAMP_DURATION)
+            .ok_or(DexError::MathOverflow)?;
+        require!(end_time >= min_end_time, DexError::RampTooShort);
+        let start_fee = pool.fee_numerator;
+        let (start, target) = (start_fee.max(1), target_fee.max(1));
         require!(
-            ctx.accounts.token_a_mint.key() != ctx.accounts.token_b_mint.key(),
-            DexError::InvalidTokenPair
+            target <= start * MAX_FEE_CHANGE && start <= target * MAX_FEE_CHANGE,
+            DexError::FeeChangeTooLarge
         );
 
-        pool.authority = ctx.accounts.pool_authority.key();
-        pool.token_a_mint = ctx.accounts.token_a_mint.key();
-        pool.token_b_mint = ctx.accounts.token_b_mint.key();
-        pool.token_a_vault = ctx.accounts.token_a_vault.key();
-        pool.token_b_vault = ctx.accounts.token_b_vault.key();
-        pool.lp_token_mint = ctx.accounts.lp_token_mint.key();
-        pool.reserve_a = 0;
-        pool.reserve_b = 0;
-        pool.fee_numerator = 30; // Set a 0.3% fee
-        pool.authority_bump = ctx.bumps.pool_authority;
+        pool.ramp_start_fee = start_fee;
+        pool.ramp_start_time = now;
+        pool.ramp_end_time = end_time;
+        pool.fee_numerator = target_fee;
 
-        emit!(PoolInitialized {
+        emit!(FeeRampScheduled {
             pool: pool.key(),
-            token_a_mint: pool.token_a_mint,
-            token_b_mint: pool.token_b_mint,
-            lp_token_mint: pool.lp_token_mint,
+            start_fee_bps: start_fee,
+            target_fee_bps: target_fee,
+            start_time: now,
+            end_time,
         });
 
-        msg!("Pool initialized successfully");
+        msg!(
+            "Fee ramp scheduled: {} -> {} bps by {}",
+            start_fee,
+            target_fee,
+            end_time
+        );
         Ok(())
     }
 
-    pub fn add_liquidity(
-        ctx: Context<Add>,
-        amount_a: u64,
-        amount_b: u64,
+    /// Freeze a pool's swap fee at its current interpolated value, ending any ramp. Admin only.
+    pub fn stop_ramp(ctx: Context<RampFee>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let now = Clock::get()?.unix_timestamp;
+        let fee_bps = pool.fee_bps(now);
+
+        pool.fee_numerator = fee_bps;
+        pool.ramp_start_fee = fee_bps;
+        pool.ramp_start_time = now;
+        pool.ramp_end_time = now;
+
+        emit!(FeeRampStopped {
+            pool: pool.key(),
+            fee_bps,
+        });
+
+        msg!("Fee ramp stopped at {} bps", fee_bps);
+        Ok(())
+    }
+
+    /// Initialize a new liquidity pool
+    ///
+    /// Mints must be passed in canonical order (`token_a_mint < token_b_mint`, see `sort_mints`).
+    /// Param `pool_index` namespaces the pool within its pair, so the same pair can run
+    /// several pools (e.g. one per fee tier).
+    /// Param `fee_bps` is the swap fee in basis points (e.g. 30 = 0.3%) and must be below `FEE_DEN`.
+    /// When `None`, the config's `default_fee_bps` is used.
+    /// Param `curve_type` picks the pricing curve; `amp` is the StableSwap amplification
+    /// coefficient, within `MIN_AMP..=MAX_AMP` for stable pools and 0 otherwise.
+    /// Param `weight_a` is token A's weight in basis points for weighted pools, at least
+    /// `MIN_WEIGHT` either way (token B gets the rest), and 0 otherwise.
+    /// Concentrated pools are created with `initialize_concentrated_pool` instead.
+    pub fn initialize_pool(
+        ctx: Context<Initialize>,
+        pool_index: u16,
+        fee_bps: Option<u64>,
+        curve_type: CurveType,
+        amp: u64,
+        weight_a: u64,
+    ) -> Result<()> {
+        require!(
+            curve_type != CurveType::Concentrated,
+            DexError::WrongCurveType
+        );
+        match curve_type {
+            CurveType::Stable => require!(
+                (stable::MIN_AMP..=stable::MAX_AMP).contains(&amp),
+                DexError::InvalidAmp
+            ),
+            _ => require!(amp == 0, DexError::InvalidAmp),
+        }
+        let weight_b = match curve_type {
+            CurveType::Weighted => {
+                let max_weight = FEE_DEN as u64 - weighted::MIN_WEIGHT;
+                require!(
+                    (weighted::MIN_WEIGHT..=max_weight).contains(&weight_a),
+                    DexError::InvalidWeight
+                );
+                FEE_DEN as u64 - weight_a
+            }
+            _ => {
+                require!(weight_a == 0, DexError::InvalidWeight);
+                0
+            }
+        };
+
+        let pool = &mut ctx.accounts.pool;
+        pool.amp = amp;
+        pool.weight_a = weight_a;
+        pool.weight_b = weight_b;
+        ctx.accounts
+            .init_pool(ctx.bumps.pool_authority, pool_index, fee_bps, curve_type)
+    }
+
+    /// Initialize a concentrated liquidity pool.
+    ///
+    /// Takes the same accounts and `pool_index` / `fee_bps` as `initialize_pool`. The LP
+    /// mint stays unused: liquidity is held in `Position` accounts instead.
+    /// Param `tick_spacing` sets which ticks positions may use, within `1..=MAX_TICK_SPACING`.
+    /// Param `initial_sqrt_price` is sqrt(price of A in B) in Q64.64.
+    pub fn initialize_concentrated_pool(
+        ctx: Context<Initialize>,
+        pool_index: u16,
+        fee_bps: Option<u64>,
+        tick_spacing: u16,
+        initial_sqrt_price: u128,
+    ) -> Result<()> {
+        require!(
+            (1..=clmm::MAX_TICK_SPACING).contains(&tick_spacing),
+            DexError::InvalidTickSpacing
+        );
+        // nothing can be swapped upwards from the top price
+        require!(
+            (clmm::MIN_SQRT_PRICE..clmm::MAX_SQRT_PRICE).contains(&initial_sqrt_price),
+            DexError::InvalidSqrtPrice
+        );
+
+        let pool = &mut ctx.accounts.pool;
+        pool.tick_spacing = tick_spacing;
+        pool.sqrt_price = initial_sqrt_price;
+        pool.tick_current = clmm::tick_at_sqrt_price(initial_sqrt_price)?;
+        ctx.accounts.init_pool(
+            ctx.bumps.pool_authority,
+            pool_index,
+            fee_bps,
+            CurveType::Concentrated,
+        )
+    }
+
+    /// Deposit Token A and Token B for LP tokens.
+    ///
+    /// Only the pair matching the pool's reserve ratio is pulled, at most `max_amount_a` and
+    /// `max_amount_b` (see `calculate_deposit_amounts`); the first deposit takes both and sets
+    /// the price. Stable pools credit any ratio (charging an imbalance fee) and take both too.
+    /// Params `min_amount_a` / `min_amount_b` bound the credited amounts, net of transfer fees,
+    /// when the ratio moves before the deposit lands.
+    /// Param `deadline` is an optional unix timestamp after which the deposit fails.
+    /// Transfer hook accounts for Token-2022 mints go in `remaining_accounts`.
+    pub fn add_liquidity<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Add<'info>>,
+        max_amount_a: u64,
+        max_amount_b: u64,
+        min_amount_a: u64,
+        min_amount_b: u64,
         min_lp_tokens: u64,
+        deadline: Option<i64>,
     ) -> Result<()> {
+        check_deadline(deadline)?;
         let pool = &mut ctx.accounts.pool;
+        let config = &ctx.accounts.config;
+        require!(!config.paused && !pool.paused, DexError::Paused);
+        require!(!pool.flash_loan_active, DexError::FlashLoanActive);
         // ensure amounts are non zero
-        require!(amount_a > 0 && amount_b > 0, DexError::InvalidAmount);
+        require!(max_amount_a > 0 && max_amount_b > 0, DexError::InvalidAmount);
+        // the pool only credits what lands in the vaults, after any transfer fee.
+        let token_a_mint = &ctx.accounts.token_a_mint;
+        let token_b_mint = &ctx.accounts.token_b_mint;
+        let max_a_received = amount_after_transfer_fee(token_a_mint, max_amount_a)?;
+        let max_b_received = amount_after_transfer_fee(token_b_mint, max_amount_b)?;
+        let (amount_a_received, amount_b_received) = if pool.curve_type == CurveType::Stable {
+            (max_a_received, max_b_received)
+        } else {
+            calculate_deposit_amounts(max_a_received, max_b_received, pool.reserve_a, pool.reserve_b)?
+        };
+        require!(
+            amount_a_received > 0 && amount_b_received > 0,
+            DexError::InvalidAmount
+        );
+        require!(
+            amount_a_received >= min_amount_a && amount_b_received >= min_amount_b,
+            DexError::SlippageExceeded
+        );
+        // send only what the deposit uses, grossed up by any transfer fee.
+        let amount_a = if amount_a_received == max_a_received {
+            max_amount_a
+        } else {
+            amount_a_received
+                .checked_add(calculate_transfer_inverse_fee(token_a_mint, amount_a_received)?)
+                .ok_or(DexError::MathOverflow)?
+        };
+        let amount_b = if amount_b_received == max_b_received {
+            max_amount_b
+        } else {
+            amount_b_received
+                .checked_add(calculate_transfer_inverse_fee(token_b_mint, amount_b_received)?)
+                .ok_or(DexError::MathOverflow)?
+        };
+        let now = Clock::get()?.unix_timestamp;
+        pool.update_price_accumulators(now);
+        if let Some(observations) = ctx.accounts.observations.as_mut() {
+            observations.write(pool, now);
+        }
 
-        let lp_supply = ctx.accounts.lp_token_mint.supply;
-        let lp_tokens = calculate_lp_tokens(
-            amount_a,
-            amount_b,
-            pool.reserve_a,
-            pool.reserve_b,
-            lp_supply,
-        )?;
+        // mint the protocol's LP fee first so the depositor's share is priced after it.
+        let mut lp_supply = ctx.accounts.lp_token_mint.supply;
+        ctx.accounts.mint_protocol_lp_fee(&mut lp_supply)?;
+        let pool = &mut ctx.accounts.pool;
+        let pool_index = pool.pool_index.to_le_bytes();
+        let authority_seeds = &[
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool_index.as_ref(),
+            &[pool.authority_bump],
+        ];
+
+        let lp_tokens = match pool.curve_type {
+            CurveType::ConstantProduct | CurveType::Weighted => calculate_lp_tokens(
+                amount_a_received,
+                amount_b_received,
+                pool.reserve_a,
+                pool.reserve_b,
+                lp_supply,
+                pool.weight_a,
+                pool.weight_b,
+            )?,
+            CurveType::Stable => stable::calculate_lp_tokens(
+                amount_a_received,
+                amount_b_received,
+                pool.reserve_a,
+                pool.reserve_b,
+                lp_supply,
+                pool.fee_bps(now),
+                pool.amp,
+            )?,
+            CurveType::Concentrated => return err!(DexError::WrongCurveType),
+        };
         // ensure user gets minimum lp token or fail
         require!(lp_tokens >= min_lp_tokens, DexError::SlippageExceeded);
 
+        // first deposit: lock MINIMUM_LIQUIDITY for good so the supply (and the
+        // reserves backing it) can never be fully withdrawn.
+        if lp_supply == 0 {
+            token_interface::mint_to(
+                CpiContext::new_with_signer(
+                    ctx.accounts.token_program.to_account_info(),
+                    MintTo {
+                        mint: ctx.accounts.lp_token_mint.to_account_info(),
+                        to: ctx.accounts.locked_lp_token.to_account_info(),
+                        authority: ctx.accounts.pool_authority.to_account_info(),
+                    },
+                    &[authority_seeds],
+                ),
+                MINIMUM_LIQUIDITY,
+            )?;
+        }
+
         // transfer tokens to vault
-        token::transfer(
-            CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.user_token_a.to_account_info(),
-                    to: ctx.accounts.token_a_vault.to_account_info(),
-                    authority: ctx.accounts.user.to_account_info(),
-                },
-            ),
+        transfer_tokens(
+            &ctx.accounts.token_a_program,
+            &ctx.accounts.user_token_a,
+            &ctx.accounts.token_a_vault,
+            &ctx.accounts.token_a_mint,
+            ctx.accounts.user.to_account_info(),
+            &[],
+            ctx.remaining_accounts,
             amount_a,
         )?;
-        token::transfer(
-            CpiContext::new(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.user_token_b.to_account_info(),
-                    to: ctx.accounts.token_b_vault.to_account_info(),
-                    authority: ctx.accounts.user.to_account_info(),
-                },
-            ),
+        transfer_tokens(
+            &ctx.accounts.token_b_program,
+            &ctx.accounts.user_token_b,
+            &ctx.accounts.token_b_vault,
+            &ctx.accounts.token_b_mint,
+            ctx.accounts.user.to_account_info(),
+            &[],
+            ctx.remaining_accounts,
             amount_b,
         )?;
 
         // mint lp tokens to the user account
-        let authority_seeds = &[
-            b"pool_authority",
-            pool.token_a_mint.as_ref(),
-            pool.token_b_mint.as_ref(),
-            &[pool.authority_bump],
-        ];
-        token::mint_to(
+        token_interface::mint_to(
             CpiContext::new_with_signer(
                 ctx.accounts.token_program.to_account_info(),
                 MintTo {
@@ -150,155 +695,411 @@ pub mod nja_swap {
         // update reserves
         pool.reserve_a = pool
             .reserve_a
-            .checked_add(amount_a)
+            .checked_add(amount_a_received)
             .ok_or(DexError::MathOverflow)?;
         pool.reserve_b = pool
             .reserve_b
-            .checked_add(amount_b)
+            .checked_add(amount_b_received)
             .ok_or(DexError::MathOverflow)?;
+        pool.k_last = if config.protocol_fee_mode == ProtocolFeeMode::LpMint {
+            pool.root_k()?
+        } else {
+            0
+        };
 
         emit!(LiquidityAdded {
             pool: pool.key(),
             user: ctx.accounts.user.key(),
-            amount_a,
-            amount_b,
+            amount_a: amount_a_received,
+            amount_b: amount_b_received,
             lp_tokens,
         });
 
         msg!(
             "Liquidity added: {} Token A, {} Token B, {} LP tokens",
-            amount_a,
-            amount_b,
+            amount_a_received,
+            amount_b_received,
             lp_tokens
         );
         Ok(())
     }
 
-    /// Swap `amount_in` -> `min_amount_out`.
+    /// Deposit only Token A or only Token B for LP tokens (zap in).
     ///
-    /// May mint more than `min_amount_out`, but never less.
-    /// Param `is_a_to_b` should be set to true if `amount_in` is `pool.reserve_a`, else false.
-    pub fn swap(
-        ctx: Context<Swap>,
+    /// Swaps the share of the input that leaves the rest in the pool's post-swap ratio
+    /// (see `calculate_zap_swap_amount`), then deposits both sides, so next to nothing
+    /// is donated. The swapped tokens never leave the vaults. Constant product pools only,
+    /// and the pool must already hold liquidity.
+    /// Param `is_a` should be set to true if `amount_in` is Token A, else false.
+    /// Param `deadline` is an optional unix timestamp after which the deposit fails.
+    /// Transfer hook accounts for Token-2022 mints go in `remaining_accounts`.
+    pub fn add_liquidity_single<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Add<'info>>,
         amount_in: u64,
-        min_amount_out: u64,
-        is_a_to_b: bool,
+        is_a: bool,
+        min_lp_tokens: u64,
+        deadline: Option<i64>,
     ) -> Result<()> {
+        check_deadline(deadline)?;
         let pool = &mut ctx.accounts.pool;
-        // input amount cannot be zero.
+        let config = &ctx.accounts.config;
+        require!(!config.paused && !pool.paused, DexError::Paused);
+        require!(!pool.flash_loan_active, DexError::FlashLoanActive);
+        require!(
+            pool.curve_type == CurveType::ConstantProduct,
+            DexError::WrongCurveType
+        );
         require!(amount_in > 0, DexError::InvalidAmount);
+        // the split needs the pool's ratio, so there must be one
+        let mut lp_supply = ctx.accounts.lp_token_mint.supply;
+        require!(lp_supply > 0, DexError::InsufficientLiquidity);
 
-        // calculate the swap amount
-        let (reserve_in, reserve_out) = if is_a_to_b {
-            (pool.reserve_a, pool.reserve_b)
+        let (mint_in, token_program_in, user_token_in, vault_in) = if is_a {
+            (
+                &ctx.accounts.token_a_mint,
+                &ctx.accounts.token_a_program,
+                &ctx.accounts.user_token_a,
+                &ctx.accounts.token_a_vault,
+            )
         } else {
-            (pool.reserve_b, pool.reserve_a)
+            (
+                &ctx.accounts.token_b_mint,
+                &ctx.accounts.token_b_program,
+                &ctx.accounts.user_token_b,
+                &ctx.accounts.token_b_vault,
+            )
         };
-        let amount_out =
-            calculate_swap_amount(amount_in, reserve_in, reserve_out, pool.fee_numerator)?;
+        let amount_in_received = amount_after_transfer_fee(mint_in, amount_in)?;
+        let now = Clock::get()?.unix_timestamp;
+        pool.update_price_accumulators(now);
+        if let Some(observations) = ctx.accounts.observations.as_mut() {
+            observations.write(pool, now);
+        }
 
-        // ensure user can get `min_amount_out` or fail.
-        require!(amount_out >= min_amount_out, DexError::SlippageExceeded);
-        // ensure amount received is non-zero.
-        require!(amount_out > 0, DexError::InvalidAmount);
+        // swap part of the input against the reserves; the output stays in the vault.
+        let fee_bps = pool.fee_bps(now);
+        let reserve_in = if is_a { pool.reserve_a } else { pool.reserve_b };
+        let swap_amount = calculate_zap_swap_amount(amount_in_received, reserve_in, fee_bps)?;
+        let swap_out = pool.quote_swap(swap_amount, is_a, fee_bps)?;
+        require!(swap_out > 0, DexError::InvalidAmount);
+        let protocol_fee = config.swap_protocol_fee(swap_amount, fee_bps)?;
+        pool.book_swap(swap_amount, swap_out, protocol_fee, is_a)?;
+        let remainder = amount_in_received - swap_amount;
+        let (amount_a, amount_b) = if is_a {
+            (remainder, swap_out)
+        } else {
+            (swap_out, remainder)
+        };
 
-        // transfer `amount_in` to vault, `min_amount_out` to user.
+        // mint the protocol's LP fee, including the swap's k growth, before pricing the deposit.
+        ctx.accounts.mint_protocol_lp_fee(&mut lp_supply)?;
+        let pool = &mut ctx.accounts.pool;
+        let pool_index = pool.pool_index.to_le_bytes();
         let authority_seeds = &[
             b"pool_authority",
             pool.token_a_mint.as_ref(),
             pool.token_b_mint.as_ref(),
+            pool_index.as_ref(),
             &[pool.authority_bump],
         ];
-        if is_a_to_b {
-            token::transfer(
-                CpiContext::new(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.user_token_a.to_account_info(),
-                        to: ctx.accounts.token_a_vault.to_account_info(),
-                        authority: ctx.accounts.user.to_account_info(),
-                    },
-                ),
-                amount_in,
-            )?;
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.token_b_vault.to_account_info(),
-                        to: ctx.accounts.user_token_b.to_account_info(),
-                        authority: ctx.accounts.pool_authority.to_account_info(),
-                    },
-                    &[authority_seeds],
-                ),
-                amount_out,
-            )?;
-            pool.reserve_a = pool
-                .reserve_a
-                .checked_add(amount_in)
-                .ok_or(DexError::MathOverflow)?;
-            pool.reserve_b = pool
-                .reserve_b
-                .checked_sub(amount_out)
-                .ok_or(DexError::MathOverflow)?;
+
+        let lp_tokens = calculate_lp_tokens(
+            amount_a,
+            amount_b,
+            pool.reserve_a,
+            pool.reserve_b,
+            lp_supply,
+            pool.weight_a,
+            pool.weight_b,
+        )?;
+        require!(lp_tokens > 0, DexError::InvalidAmount);
+        // ensure user gets minimum lp token or fail
+        require!(lp_tokens >= min_lp_tokens, DexError::SlippageExceeded);
+
+        transfer_tokens(
+            token_program_in,
+            user_token_in,
+            vault_in,
+            mint_in,
+            ctx.accounts.user.to_account_info(),
+            &[],
+            ctx.remaining_accounts,
+            amount_in,
+        )?;
+        token_interface::mint_to(
+            CpiContext::new_with_signer(
+                ctx.accounts.token_program.to_account_info(),
+                MintTo {
+                    mint: ctx.accounts.lp_token_mint.to_account_info(),
+                    to: ctx.accounts.user_lp_token.to_account_info(),
+                    authority: ctx.accounts.pool_authority.to_account_info(),
+                },
+                &[authority_seeds],
+            ),
+            lp_tokens,
+        )?;
+
+        pool.reserve_a = pool
+            .reserve_a
+            .checked_add(amount_a)
+            .ok_or(DexError::MathOverflow)?;
+        pool.reserve_b = pool
+            .reserve_b
+            .checked_add(amount_b)
+            .ok_or(DexError::MathOverflow)?;
+        pool.k_last = if config.protocol_fee_mode == ProtocolFeeMode::LpMint {
+            pool.root_k()?
         } else {
-            token::transfer(
-                CpiContext::new(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.user_token_b.to_account_info(),
-                        to: ctx.accounts.token_b_vault.to_account_info(),
-                        authority: ctx.accounts.user.to_account_info(),
-                    },
-                ),
-                amount_in,
-            )?;
-            token::transfer(
-                CpiContext::new_with_signer(
-                    ctx.accounts.token_program.to_account_info(),
-                    Transfer {
-                        from: ctx.accounts.token_a_vault.to_account_info(),
-                        to: ctx.accounts.user_token_a.to_account_info(),
-                        authority: ctx.accounts.pool_authority.to_account_info(),
-                    },
-                    &[authority_seeds],
-                ),
-                amount_out,
-            )?;
-            pool.reserve_b = pool
-                .reserve_b
-                .checked_add(amount_in)
-                .ok_or(DexError::MathOverflow)?;
-            pool.reserve_a = pool
-                .reserve_a
-                .checked_sub(amount_out)
-                .ok_or(DexError::MathOverflow)?;
-        }
+            0
+        };
 
-        emit!(SwapExecuted {
+        // the pool's net intake is the whole input, on one side
+        let (amount_a, amount_b) = if is_a {
+            (amount_in_received, 0)
+        } else {
+            (0, amount_in_received)
+        };
+        emit!(LiquidityAdded {
             pool: pool.key(),
             user: ctx.accounts.user.key(),
-            amount_in,
-            amount_out,
-            is_a_to_b,
+            amount_a,
+            amount_b,
+            lp_tokens,
         });
 
-        msg!("Swap executed: {} in -> {} out", amount_in, amount_out);
+        msg!(
+            "Single-sided liquidity added: {} in (swapped {}), {} LP tokens",
+            amount_in_received,
+            swap_amount,
+            lp_tokens
+        );
+        Ok(())
+    }
+
+    /// Swap `amount_in` -> `min_amount_out`.
+    ///
+    /// May mint more than `min_amount_out`, but never less.
+    /// Param `is_a_to_b` should be set to true if `amount_in` is `pool.reserve_a`, else false.
+    /// Param `deadline` is an optional unix timestamp after which the swap fails.
+    /// Transfer hook accounts for Token-2022 mints go in `remaining_accounts`.
+    pub fn swap<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Swap<'info>>,
+        amount_in: u64,
+        min_amount_out: u64,
+        is_a_to_b: bool,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        let pool = &ctx.accounts.pool;
+        let config = &ctx.accounts.config;
+        require!(!config.paused && !pool.paused, DexError::Paused);
+        require!(!pool.flash_loan_active, DexError::FlashLoanActive);
+        // input amount cannot be zero.
+        require!(amount_in > 0, DexError::InvalidAmount);
+
+        // calculate the swap amount on what reaches the vault, after any transfer fee.
+        let (mint_in, mint_out) = if is_a_to_b {
+            (&ctx.accounts.token_a_mint, &ctx.accounts.token_b_mint)
+        } else {
+            (&ctx.accounts.token_b_mint, &ctx.accounts.token_a_mint)
+        };
+        let amount_in_received = amount_after_transfer_fee(mint_in, amount_in)?;
+        let fee_bps = pool.fee_bps(Clock::get()?.unix_timestamp);
+        let amount_out = pool.quote_swap(amount_in_received, is_a_to_b, fee_bps)?;
+
+        // ensure user can get `min_amount_out`, net of the output transfer fee, or fail.
+        let amount_out_received = amount_after_transfer_fee(mint_out, amount_out)?;
+        require!(amount_out_received >= min_amount_out, DexError::SlippageExceeded);
+        // ensure amount received is non-zero.
+        require!(amount_out_received > 0, DexError::InvalidAmount);
+
+        ctx.accounts
+            .settle_swap(amount_in, amount_out, is_a_to_b, ctx.remaining_accounts)
+    }
+
+    /// Swap at most `max_amount_in` -> exactly `amount_out`.
+    ///
+    /// The input is rounded up, so the pool never receives less than the invariant requires.
+    /// Param `is_a_to_b` should be set to true if the input token is Token A, else false.
+    /// Param `deadline` is an optional unix timestamp after which the swap fails.
+    /// Transfer hook accounts for Token-2022 mints go in `remaining_accounts`.
+    pub fn swap_exact_out<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Swap<'info>>,
+        amount_out: u64,
+        max_amount_in: u64,
+        is_a_to_b: bool,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        let pool = &ctx.accounts.pool;
+        let config = &ctx.accounts.config;
+        require!(!config.paused && !pool.paused, DexError::Paused);
+        require!(!pool.flash_loan_active, DexError::FlashLoanActive);
+        // output amount cannot be zero.
+        require!(amount_out > 0, DexError::InvalidAmount);
+
+        // gross both legs up by their transfer fees, so the user receives exactly
+        // `amount_out` and the vault receives the input the invariant requires.
+        let (mint_in, mint_out) = if is_a_to_b {
+            (&ctx.accounts.token_a_mint, &ctx.accounts.token_b_mint)
+        } else {
+            (&ctx.accounts.token_b_mint, &ctx.accounts.token_a_mint)
+        };
+        let amount_out_sent = amount_out
+            .checked_add(calculate_transfer_inverse_fee(mint_out, amount_out)?)
+            .ok_or(DexError::MathOverflow)?;
+        let fee_bps = pool.fee_bps(Clock::get()?.unix_timestamp);
+        let amount_in_required = pool.quote_swap_in(amount_out_sent, is_a_to_b, fee_bps)?;
+        let amount_in = amount_in_required
+            .checked_add(calculate_transfer_inverse_fee(mint_in, amount_in_required)?)
+            .ok_or(DexError::MathOverflow)?;
+
+        // ensure user pays at most `max_amount_in` or fail.
+        require!(amount_in <= max_amount_in, DexError::SlippageExceeded);
+
+        ctx.accounts
+            .settle_swap(amount_in, amount_out_sent, is_a_to_b, ctx.remaining_accounts)
+    }
+
+    /// Swap `amount_in` along a route of pools -> at least `min_amount_out`.
+    ///
+    /// Each hop's output moves vault to vault into the next pool, so only the route's
+    /// input and final output touch the user's token accounts.
+    /// Param `hops` is the number of pools in the route, at most `MAX_ROUTE_HOPS`.
+    /// The first `hops * ROUTE_HOP_ACCOUNTS` of `remaining_accounts` describe the hops in
+    /// order, as `[pool, pool_authority, vault_in, vault_out, mint_out, token_program_out,
+    /// observations]`, passing the program id for a pool's `observations` to skip its oracle.
+    /// Transfer hook accounts for Token-2022 mints follow them.
+    /// Param `deadline` is an optional unix timestamp after which the swap fails.
+    pub fn swap_route<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SwapRoute<'info>>,
+        amount_in: u64,
+        min_amount_out: u64,
+        hops: u8,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        let config = &ctx.accounts.config;
+        require!(!config.paused, DexError::Paused);
+        // input amount cannot be zero.
+        require!(amount_in > 0, DexError::InvalidAmount);
+        let hops = hops as usize;
+        require!((1..=MAX_ROUTE_HOPS).contains(&hops), DexError::InvalidRoute);
+        let hop_accounts_len = hops * ROUTE_HOP_ACCOUNTS;
+        require!(
+            ctx.remaining_accounts.len() >= hop_accounts_len,
+            DexError::InvalidRoute
+        );
+        let (hop_accounts, hook_accounts) = ctx.remaining_accounts.split_at(hop_accounts_len);
+
+        // load and check every hop before moving any tokens.
+        let mut route: Vec<RouteHop> = Vec::with_capacity(hops);
+        let mut mint_in = ctx.accounts.token_in_mint.key();
+        for accounts in hop_accounts.chunks(ROUTE_HOP_ACCOUNTS) {
+            let hop = RouteHop::load(accounts, mint_in)?;
+            // a pool visited twice would be priced on stale reserves.
+            require!(
+                route.iter().all(|visited| visited.pool.key() != hop.pool.key()),
+                DexError::InvalidRoute
+            );
+            mint_in = hop.mint_out.key();
+            route.push(hop);
+        }
+        require_keys_eq!(
+            mint_in,
+            ctx.accounts.token_out_mint.key(),
+            DexError::InvalidRoute
+        );
+
+        // pull the input into the first pool; each hop then prices what reached its vault.
+        transfer_tokens(
+            &ctx.accounts.token_in_program,
+            &ctx.accounts.user_token_in,
+            &route[0].vault_in,
+            &ctx.accounts.token_in_mint,
+            ctx.accounts.user.to_account_info(),
+            &[],
+            hook_accounts,
+            amount_in,
+        )?;
+        let mut amount = amount_after_transfer_fee(&ctx.accounts.token_in_mint, amount_in)?;
+        let now = Clock::get()?.unix_timestamp;
+        for i in 0..hops {
+            let (visited, ahead) = route.split_at_mut(i + 1);
+            let to = match ahead.first() {
+                Some(next) => &next.vault_in,
+                None => &ctx.accounts.user_token_out,
+            };
+            amount = visited[i].swap(
+                config,
+                ctx.accounts.user.key(),
+                to,
+                amount,
+                now,
+                hook_accounts,
+            )?;
+        }
+
+        // ensure user can get `min_amount_out`, net of the output transfer fee, or fail.
+        require!(amount >= min_amount_out, DexError::SlippageExceeded);
+        // ensure amount received is non-zero.
+        require!(amount > 0, DexError::InvalidAmount);
+
+        // the pools came from `remaining_accounts`, so their state is written back here.
+        for hop in &route {
+            hop.pool.exit(&crate::ID)?;
+            if let Some(observations) = &hop.observations {
+                observations.exit(&crate::ID)?;
+            }
+        }
+
+        msg!(
+            "Route swap executed: {} in -> {} out over {} pools",
+            amount_in,
+            amount,
+            hops
+        );
         Ok(())
     }
 
-    pub fn remove_liquidity(
-        ctx: Context<Remove>,
+    /// Burn LP tokens for a proportional share of the reserves.
+    ///
+    /// Never paused, so LPs can always exit.
+    /// Param `deadline` is an optional unix timestamp after which the withdrawal fails.
+    /// Transfer hook accounts for Token-2022 mints go in `remaining_accounts`.
+    pub fn remove_liquidity<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Remove<'info>>,
         lp_token_amount: u64,
         min_amount_a: u64,
         min_amount_b: u64,
+        deadline: Option<i64>,
     ) -> Result<()> {
+        check_deadline(deadline)?;
         let pool = &mut ctx.accounts.pool;
-        let lp_supply = ctx.accounts.lp_token_mint.supply;
+        let config = &ctx.accounts.config;
+        require!(!pool.flash_loan_active, DexError::FlashLoanActive);
+        let mut lp_supply = ctx.accounts.lp_token_mint.supply;
 
         // ensure amounts are non-zero
         require!(lp_token_amount > 0, DexError::InvalidAmount);
         require!(lp_supply > 0, DexError::InsufficientLiquidity);
+        let now = Clock::get()?.unix_timestamp;
+        pool.update_price_accumulators(now);
+        if let Some(observations) = ctx.accounts.observations.as_mut() {
+            observations.write(pool, now);
+        }
+
+        // mint the protocol's LP fee first so the withdrawal is priced after it.
+        ctx.accounts.mint_protocol_lp_fee(&mut lp_supply)?;
+        let pool = &mut ctx.accounts.pool;
+        let pool_index = pool.pool_index.to_le_bytes();
+        let authority_seeds = &[
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool_index.as_ref(),
+            &[pool.authority_bump],
+        ];
 
         // calculate amount_a
         let amount_a = (pool.reserve_a as u128)
@@ -314,14 +1115,15 @@ pub mod nja_swap {
         .checked_div(lp_supply as u128)
         .ok_or(DexError::MathOverflow)? as u64;
 
-        // ensure amount to pay to user is under slippage.
+        // ensure amount the user receives, net of transfer fees, is under slippage.
         require!(
-            amount_a >= min_amount_a && amount_b >= min_amount_b,
+            amount_after_transfer_fee(&ctx.accounts.token_a_mint, amount_a)? >= min_amount_a
+                && amount_after_transfer_fee(&ctx.accounts.token_b_mint, amount_b)? >= min_amount_b,
             DexError::SlippageExceeded
         );
 
         // burn the lp tokens
-        token::burn(
+        token_interface::burn(
             CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
                 Burn {
@@ -334,34 +1136,24 @@ pub mod nja_swap {
         )?;
 
         // transfer token pair to user
-        let authority_seeds = &[
-            b"pool_authority",
-            pool.token_a_mint.as_ref(),
-            pool.token_b_mint.as_ref(),
-            &[pool.authority_bump],
-        ];
-        token::transfer(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.token_a_vault.to_account_info(),
-                    to: ctx.accounts.user_token_a.to_account_info(),
-                    authority: ctx.accounts.pool_authority.to_account_info(),
-                },
-                &[authority_seeds],
-            ),
+        transfer_tokens(
+            &ctx.accounts.token_a_program,
+            &ctx.accounts.token_a_vault,
+            &ctx.accounts.user_token_a,
+            &ctx.accounts.token_a_mint,
+            ctx.accounts.pool_authority.to_account_info(),
+            &[authority_seeds],
+            ctx.remaining_accounts,
             amount_a,
         )?;
-        token::transfer(
-            CpiContext::new_with_signer(
-                ctx.accounts.token_program.to_account_info(),
-                Transfer {
-                    from: ctx.accounts.token_b_vault.to_account_info(),
-                    to: ctx.accounts.user_token_b.to_account_info(),
-                    authority: ctx.accounts.pool_authority.to_account_info(),
-                },
-                &[authority_seeds],
-            ),
+        transfer_tokens(
+            &ctx.accounts.token_b_program,
+            &ctx.accounts.token_b_vault,
+            &ctx.accounts.user_token_b,
+            &ctx.accounts.token_b_mint,
+            ctx.accounts.pool_authority.to_account_info(),
+            &[authority_seeds],
+            ctx.remaining_accounts,
             amount_b,
         )?;
 
@@ -374,6 +1166,11 @@ pub mod nja_swap {
             .reserve_b
             .checked_sub(amount_b)
             .ok_or(DexError::MathOverflow)?;
+        pool.k_last = if config.protocol_fee_mode == ProtocolFeeMode::LpMint {
+            pool.root_k()?
+        } else {
+            0
+        };
 
         emit!(LiquidityRemoved {
             pool: pool.key(),
@@ -390,131 +1187,2138 @@ pub mod nja_swap {
         );
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    /// Creating an account for the liquidity pool
-    #[account(
-        init,
-        payer = payer,
-        space = Pool::LEN,
-        seeds = [b"pool", token_a_mint.key().as_ref(), token_b_mint.key().as_ref()],
-        bump
-    )]
-    pub pool: Account<'info, Pool>,
-    /// CHECK: PDA authority controlled by program
-    #[account(
-        seeds = [b"pool_authority", token_a_mint.key().as_ref(), token_b_mint.key().as_ref()],
-        bump
-    )]
-    pub pool_authority: UncheckedAccount<'info>,
-    pub token_a_mint: Account<'info, Mint>,
-    pub token_b_mint: Account<'info, Mint>,
-    #[account(
-        init,
-        payer = payer,
-        token::mint = token_a_mint,
-        token::authority = pool_authority,
-        seeds = [b"token_a_vault", token_a_mint.key().as_ref(), token_b_mint.key().as_ref()],
-        bump
-    )]
-    pub token_a_vault: Account<'info, TokenAccount>,
-    #[account(
-        init,
-        payer = payer,
-        token::mint = token_b_mint,
-        token::authority = pool_authority,
-        seeds = [b"token_b_vault", token_a_mint.key().as_ref(), token_b_mint.key().as_ref()],
-        bump
-    )]
-    pub token_b_vault: Account<'info, TokenAccount>,
-    #[account(
-        init,
-        payer = payer,
-        seeds = [b"lp_token_mint", token_a_mint.key().as_ref(), token_b_mint.key().as_ref()],
-        bump,
-        mint::decimals = 9,
-        mint::authority = pool_authority
-    )]
-    pub lp_token_mint: Account<'info, Mint>,
-    #[account(mut)]
-    pub payer: Signer<'info>,
-    pub system_program: Program<'info, System>,
-    pub token_program: Program<'info, Token>,
-    pub rent: Sysvar<'info, Rent>,
-}
+    /// Burn LP tokens for a single token (zap out).
+    ///
+    /// Withdraws the proportional amounts like `remove_liquidity`, then swaps the unwanted
+    /// side back into the pool against the post-withdrawal reserves and pays out one token.
+    /// Constant product pools only.
+    /// The swap leg halts with the pool, so unlike `remove_liquidity` this is paused too.
+    /// Param `out_is_a` should be set to true to receive Token A, else false.
+    /// Param `min_amount_out` bounds the payout, net of the output transfer fee.
+    /// Param `deadline` is an optional unix timestamp after which the withdrawal fails.
+    /// Transfer hook accounts for Token-2022 mints go in `remaining_accounts`.
+    pub fn remove_liquidity_single<'info>(
+        ctx: Context<'_, '_, 'info, 'info, Remove<'info>>,
+        lp_token_amount: u64,
+        out_is_a: bool,
+        min_amount_out: u64,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        let pool = &mut ctx.accounts.pool;
+        let config = &ctx.accounts.config;
+        require!(!config.paused && !pool.paused, DexError::Paused);
+        require!(!pool.flash_loan_active, DexError::FlashLoanActive);
+        require!(
+            pool.curve_type == CurveType::ConstantProduct,
+            DexError::WrongCurveType
+        );
+        let mut lp_supply = ctx.accounts.lp_token_mint.supply;
 
-#[derive(Accounts)]
-pub struct Add<'info> {
-    #[account(mut)]
-    pub pool: Account<'info, Pool>,
-    /// CHECK: PDA authority controlled by program
-    #[account(
-        seeds = [b"pool_authority", pool.token_a_mint.as_ref(), pool.token_b_mint.as_ref()],
-        bump = pool.authority_bump
-    )]
-    pub pool_authority: UncheckedAccount<'info>,
-    #[account(mut, address = pool.token_a_vault)]
-    pub token_a_vault: Account<'info, TokenAccount>,
-    #[account(mut, address = pool.token_b_vault)]
-    pub token_b_vault: Account<'info, TokenAccount>,
-    #[account(mut, address = pool.lp_token_mint)]
-    pub lp_token_mint: Account<'info, Mint>,
-    #[account(mut, constraint = user_token_a.mint == pool.token_a_mint)]
-    pub user_token_a: Account<'info, TokenAccount>,
-    #[account(mut, constraint = user_token_b.mint == pool.token_b_mint)]
-    pub user_token_b: Account<'info, TokenAccount>,
-    #[account(mut, constraint = user_lp_token.mint == pool.lp_token_mint)]
-    pub user_lp_token: Account<'info, TokenAccount>,
-    pub user: Signer<'info>,
-    pub token_program: Program<'info, Token>,
-}
+        // ensure amounts are non-zero
+        require!(lp_token_amount > 0, DexError::InvalidAmount);
+        require!(lp_supply > 0, DexError::InsufficientLiquidity);
+        let now = Clock::get()?.unix_timestamp;
+        pool.update_price_accumulators(now);
+        if let Some(observations) = ctx.accounts.observations.as_mut() {
+            observations.write(pool, now);
+        }
 
-#[derive(Accounts)]
-pub struct Swap<'info> {
-    #[account(mut)]
-    pub pool: Account<'info, Pool>,
-    /// CHECK: PDA authority controlled by program
-    #[account(
-        seeds = [b"pool_authority", pool.token_a_mint.as_ref(), pool.token_b_mint.as_ref()],
-        bump = pool.authority_bump
-    )]
-    pub pool_authority: UncheckedAccount<'info>,
-    #[account(mut, address = pool.token_a_vault)]
-    pub token_a_vault: Account<'info, TokenAccount>,
-    #[account(mut, address = pool.token_b_vault)]
-    pub token_b_vault: Account<'info, TokenAccount>,
-    #[account(mut, constraint = user_token_a.mint == pool.token_a_mint)]
-    pub user_token_a: Account<'info, TokenAccount>,
-    #[account(mut, constraint = user_token_b.mint == pool.token_b_mint)]
-    pub user_token_b: Account<'info, TokenAccount>,
-    pub user: Signer<'info>,
-    pub token_program: Program<'info, Token>,
+        // mint the protocol's LP fee first so the withdrawal is priced after it.
+        ctx.accounts.mint_protocol_lp_fee(&mut lp_supply)?;
+        let pool = &mut ctx.accounts.pool;
+        let pool_index = pool.pool_index.to_le_bytes();
+        let authority_seeds = &[
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool_index.as_ref(),
+            &[pool.authority_bump],
+        ];
+
+        // proportional share, taken off the reserves before the swap is priced.
+        let share = |reserve: u64| -> Result<u64> {
+            Ok((reserve as u128)
+                .checked_mul(lp_token_amount as u128)
+                .ok_or(DexError::MathOverflow)?
+                .checked_div(lp_supply as u128)
+                .ok_or(DexError::MathOverflow)? as u64)
+        };
+        let amount_a = share(pool.reserve_a)?;
+        let amount_b = share(pool.reserve_b)?;
+        let reserve_a = pool
+            .reserve_a
+            .checked_sub(amount_a)
+            .ok_or(DexError::MathOverflow)?;
+        let reserve_b = pool
+            .reserve_b
+            .checked_sub(amount_b)
+            .ok_or(DexError::MathOverflow)?;
+
+        // swap the unwanted side; its tokens never leave the vault.
+        let (amount_kept, amount_swapped) = if out_is_a {
+            (amount_a, amount_b)
+        } else {
+            (amount_b, amount_a)
+        };
+        let fee_bps = pool.fee_bps(now);
+        let (swap_out, protocol_fee) = if amount_swapped > 0 {
+            let swap_out = if out_is_a {
+                calculate_swap_amount(amount_swapped, reserve_b, reserve_a, fee_bps)?
+            } else {
+                calculate_swap_amount(amount_swapped, reserve_a, reserve_b, fee_bps)?
+            };
+            (swap_out, config.swap_protocol_fee(amount_swapped, fee_bps)?)
+        } else {
+            (0, 0)
+        };
+        let amount_out = amount_kept
+            .checked_add(swap_out)
+            .ok_or(DexError::MathOverflow)?;
+
+        let (mint_out, token_program_out, vault_out, user_token_out) = if out_is_a {
+            (
+                &ctx.accounts.token_a_mint,
+                &ctx.accounts.token_a_program,
+                &ctx.accounts.token_a_vault,
+                &ctx.accounts.user_token_a,
+            )
+        } else {
+            (
+                &ctx.accounts.token_b_mint,
+                &ctx.accounts.token_b_program,
+                &ctx.accounts.token_b_vault,
+                &ctx.accounts.user_token_b,
+            )
+        };
+        // ensure amount the user receives, net of the transfer fee, is under slippage.
+        let amount_out_received = amount_after_transfer_fee(mint_out, amount_out)?;
+        require!(amount_out_received >= min_amount_out, DexError::SlippageExceeded);
+        require!(amount_out_received > 0, DexError::InvalidAmount);
+
+        // burn the lp tokens
+        token_interface::burn(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                Burn {
+                    mint: ctx.accounts.lp_token_mint.to_account_info(),
+                    from: ctx.accounts.user_lp_token.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
+                },
+            ),
+            lp_token_amount,
+        )?;
+        transfer_tokens(
+            token_program_out,
+            vault_out,
+            user_token_out,
+            mint_out,
+            ctx.accounts.pool_authority.to_account_info(),
+            &[authority_seeds],
+            ctx.remaining_accounts,
+            amount_out,
+        )?;
+
+        // take the withdrawal off the reserves and checkpoint k there, so the swap leg's
+        // fee growth is left for the next liquidity event to mint.
+        pool.reserve_a = reserve_a;
+        pool.reserve_b = reserve_b;
+        pool.k_last = if config.protocol_fee_mode == ProtocolFeeMode::LpMint {
+            pool.root_k()?
+        } else {
+            0
+        };
+        pool.book_swap(amount_swapped, swap_out, protocol_fee, !out_is_a)?;
+
+        let (amount_a, amount_b) = if out_is_a {
+            (amount_out, 0)
+        } else {
+            (0, amount_out)
+        };
+        emit!(LiquidityRemoved {
+            pool: pool.key(),
+            user: ctx.accounts.user.key(),
+            amount_a,
+            amount_b,
+            lp_tokens_burned: lp_token_amount,
+        });
+
+        msg!(
+            "Single-sided liquidity removed: {} out (swapped {}), {} LP tokens burned",
+            amount_out,
+            amount_swapped,
+            lp_token_amount
+        );
+        Ok(())
+    }
+
+    /// Send the accrued protocol fees to the fee recipient's treasury accounts. Admin only.
+    pub fn collect_protocol_fees<'info>(
+        ctx: Context<'_, '_, 'info, 'info, CollectProtocolFees<'info>>,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let amount_a = pool.protocol_fees_a;
+        let amount_b = pool.protocol_fees_b;
+
+        let pool_index = pool.pool_index.to_le_bytes();
+        let authority_seeds = &[
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool_index.as_ref(),
+            &[pool.authority_bump],
+        ];
+        if amount_a > 0 {
+            transfer_tokens(
+                &ctx.accounts.token_a_program,
+                &ctx.accounts.token_a_vault,
+                &ctx.accounts.treasury_token_a,
+                &ctx.accounts.token_a_mint,
+                ctx.accounts.pool_authority.to_account_info(),
+                &[authority_seeds],
+                ctx.remaining_accounts,
+                amount_a,
+            )?;
+        }
+        if amount_b > 0 {
+            transfer_tokens(
+                &ctx.accounts.token_b_program,
+                &ctx.accounts.token_b_vault,
+                &ctx.accounts.treasury_token_b,
+                &ctx.accounts.token_b_mint,
+                ctx.accounts.pool_authority.to_account_info(),
+                &[authority_seeds],
+                ctx.remaining_accounts,
+                amount_b,
+            )?;
+        }
+
+        pool.protocol_fees_a = 0;
+        pool.protocol_fees_b = 0;
+
+        emit!(ProtocolFeesCollected {
+            pool: pool.key(),
+            amount_a,
+            amount_b,
+        });
+
+        msg!(
+            "Protocol fees collected: {} Token A, {} Token B",
+            amount_a,
+            amount_b
+        );
+        Ok(())
+    }
+
+    /// Set the reserves to the vault balances, minus the unclaimed protocol fees.
+    /// Anyone can call it, e.g. to absorb tokens donated straight to a vault.
+    pub fn sync(ctx: Context<SyncReserves>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        require!(!pool.flash_loan_active, DexError::FlashLoanActive);
+        let balance_a = ctx.accounts.token_a_vault.amount;
+        let balance_b = ctx.accounts.token_b_vault.amount;
+
+        // fails if a vault is short of what it owes
+        let excess_a = calculate_vault_excess(balance_a, pool.reserve_a, pool.protocol_fees_a)?;
+        let excess_b = calculate_vault_excess(balance_b, pool.reserve_b, pool.protocol_fees_b)?;
+
+        let now = Clock::get()?.unix_timestamp;
+        pool.update_price_accumulators(now);
+        if let Some(observations) = ctx.accounts.observations.as_mut() {
+            observations.write(pool, now);
+        }
+        pool.reserve_a = pool
+            .reserve_a
+            .checked_add(excess_a)
+            .ok_or(DexError::MathOverflow)?;
+        pool.reserve_b = pool
+            .reserve_b
+            .checked_add(excess_b)
+            .ok_or(DexError::MathOverflow)?;
+
+        emit!(ReservesSynced {
+            pool: pool.key(),
+            reserve_a: pool.reserve_a,
+            reserve_b: pool.reserve_b,
+        });
+
+        msg!(
+            "Reserves synced: {} Token A, {} Token B",
+            pool.reserve_a,
+            pool.reserve_b
+        );
+        Ok(())
+    }
+
+    /// Send vault balances above the reserves and unclaimed protocol fees to the
+    /// recipient accounts. Anyone can call it.
+    pub fn skim<'info>(ctx: Context<'_, '_, 'info, 'info, Skim<'info>>) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        require!(!pool.flash_loan_active, DexError::FlashLoanActive);
+        let amount_a = calculate_vault_excess(
+            ctx.accounts.token_a_vault.amount,
+            pool.reserve_a,
+            pool.protocol_fees_a,
+        )?;
+        let amount_b = calculate_vault_excess(
+            ctx.accounts.token_b_vault.amount,
+            pool.reserve_b,
+            pool.protocol_fees_b,
+        )?;
+
+        let pool_index = pool.pool_index.to_le_bytes();
+        let authority_seeds = &[
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool_index.as_ref(),
+            &[pool.authority_bump],
+        ];
+        if amount_a > 0 {
+            transfer_tokens(
+                &ctx.accounts.token_a_program,
+                &ctx.accounts.token_a_vault,
+                &ctx.accounts.recipient_token_a,
+                &ctx.accounts.token_a_mint,
+                ctx.accounts.pool_authority.to_account_info(),
+                &[authority_seeds],
+                ctx.remaining_accounts,
+                amount_a,
+            )?;
+        }
+        if amount_b > 0 {
+            transfer_tokens(
+                &ctx.accounts.token_b_program,
+                &ctx.accounts.token_b_vault,
+                &ctx.accounts.recipient_token_b,
+                &ctx.accounts.token_b_mint,
+                ctx.accounts.pool_authority.to_account_info(),
+                &[authority_seeds],
+                ctx.remaining_accounts,
+                amount_b,
+            )?;
+        }
+
+        emit!(ExcessSkimmed {
+            pool: pool.key(),
+            amount_a,
+            amount_b,
+        });
+
+        msg!("Skimmed: {} Token A, {} Token B", amount_a, amount_b);
+        Ok(())
+    }
+
+    /// Lend `amount_a`/`amount_b` from the vaults until `flash_repay` later in this transaction.
+    ///
+    /// The transaction must carry a top-level `flash_repay` for this pool after this
+    /// instruction (checked through the instructions sysvar), which pays back the loan plus
+    /// `config.flash_loan_fee_bps`; the fee goes to LPs. Until then the pool is locked:
+    /// swaps, liquidity changes, `sync` and `skim` fail.
+    /// Not available for concentrated pools.
+    /// Transfer hook accounts for Token-2022 mints go in `remaining_accounts`.
+    pub fn flash_loan<'info>(
+        ctx: Context<'_, '_, 'info, 'info, FlashLoan<'info>>,
+        amount_a: u64,
+        amount_b: u64,
+    ) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        let config = &ctx.accounts.config;
+        require!(!config.paused && !pool.paused, DexError::Paused);
+        require!(!pool.flash_loan_active, DexError::FlashLoanActive);
+        require!(
+            pool.curve_type != CurveType::Concentrated,
+            DexError::WrongCurveType
+        );
+        require!(amount_a > 0 || amount_b > 0, DexError::InvalidAmount);
+        require!(
+            amount_a <= pool.reserve_a && amount_b <= pool.reserve_b,
+            DexError::InsufficientLiquidity
+        );
+
+        // the sysvar lists top-level instructions only, so the loan must be one too.
+        require!(
+            get_stack_height() == TRANSACTION_LEVEL_STACK_HEIGHT,
+            DexError::FlashLoanCpi
+        );
+        // find a later flash_repay naming this pool; the lock makes it repay this loan.
+        let instructions = ctx.accounts.instructions.to_account_info();
+        let mut index = load_current_index_checked(&instructions)? as usize + 1;
+        let mut repay_found = false;
+        while let Ok(ix) = load_instruction_at_checked(index, &instructions) {
+            if ix.program_id == crate::ID
+                && ix.data.starts_with(instruction::FlashRepay::DISCRIMINATOR)
+                && ix.accounts.first().map(|meta| meta.pubkey) == Some(pool.key())
+            {
+                repay_found = true;
+                break;
+            }
+            index += 1;
+        }
+        require!(repay_found, DexError::FlashRepayMissing);
+
+        let fee_a = calculate_flash_loan_fee(amount_a, config.flash_loan_fee_bps)?;
+        let fee_b = calculate_flash_loan_fee(amount_b, config.flash_loan_fee_bps)?;
+
+        // lend from the vaults; the reserves keep counting the loan until it is repaid.
+        let pool_index = pool.pool_index.to_le_bytes();
+        let authority_seeds = &[
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool_index.as_ref(),
+            &[pool.authority_bump],
+        ];
+        if amount_a > 0 {
+            transfer_tokens(
+                &ctx.accounts.token_a_program,
+                &ctx.accounts.token_a_vault,
+                &ctx.accounts.user_token_a,
+                &ctx.accounts.token_a_mint,
+                ctx.accounts.pool_authority.to_account_info(),
+                &[authority_seeds],
+                ctx.remaining_accounts,
+                amount_a,
+            )?;
+        }
+        if amount_b > 0 {
+            transfer_tokens(
+                &ctx.accounts.token_b_program,
+                &ctx.accounts.token_b_vault,
+                &ctx.accounts.user_token_b,
+                &ctx.accounts.token_b_mint,
+                ctx.accounts.pool_authority.to_account_info(),
+                &[authority_seeds],
+                ctx.remaining_accounts,
+                amount_b,
+            )?;
+        }
+
+        pool.flash_loan_active = true;
+        pool.flash_loan_amount_a = amount_a;
+        pool.flash_loan_amount_b = amount_b;
+        pool.flash_loan_fee_a = fee_a;
+        pool.flash_loan_fee_b = fee_b;
+
+        emit!(FlashLoanTaken {
+            pool: pool.key(),
+            user: ctx.accounts.user.key(),
+            amount_a,
+            amount_b,
+            fee_a,
+            fee_b,
+        });
+
+        msg!("Flash loan: {} Token A, {} Token B", amount_a, amount_b);
+        Ok(())
+    }
+
+    /// Repay the pool's outstanding flash loan plus its fee and unlock the pool.
+    ///
+    /// The vaults receive the loan plus fee net of transfer fees, so Token-2022 transfer
+    /// fees are grossed up on top. The fee is added to the reserves.
+    /// Transfer hook accounts for Token-2022 mints go in `remaining_accounts`.
+    pub fn flash_repay<'info>(ctx: Context<'_, '_, 'info, 'info, FlashRepay<'info>>) -> Result<()> {
+        let pool = &mut ctx.accounts.pool;
+        require!(pool.flash_loan_active, DexError::NoFlashLoan);
+        let (amount_a, amount_b) = (pool.flash_loan_amount_a, pool.flash_loan_amount_b);
+        let (fee_a, fee_b) = (pool.flash_loan_fee_a, pool.flash_loan_fee_b);
+
+        let owed_a = amount_a.checked_add(fee_a).ok_or(DexError::MathOverflow)?;
+        let owed_b = amount_b.checked_add(fee_b).ok_or(DexError::MathOverflow)?;
+        if owed_a > 0 {
+            let mint = &ctx.accounts.token_a_mint;
+            transfer_tokens(
+                &ctx.accounts.token_a_program,
+                &ctx.accounts.user_token_a,
+                &ctx.accounts.token_a_vault,
+                mint,
+                ctx.accounts.user.to_account_info(),
+                &[],
+                ctx.remaining_accounts,
+                owed_a
+                    .checked_add(calculate_transfer_inverse_fee(mint, owed_a)?)
+                    .ok_or(DexError::MathOverflow)?,
+            )?;
+        }
+        if owed_b > 0 {
+            let mint = &ctx.accounts.token_b_mint;
+            transfer_tokens(
+                &ctx.accounts.token_b_program,
+                &ctx.accounts.user_token_b,
+                &ctx.accounts.token_b_vault,
+                mint,
+                ctx.accounts.user.to_account_info(),
+                &[],
+                ctx.remaining_accounts,
+                owed_b
+                    .checked_add(calculate_transfer_inverse_fee(mint, owed_b)?)
+                    .ok_or(DexError::MathOverflow)?,
+            )?;
+        }
+
+        // the fee goes to LPs
+        let now = Clock::get()?.unix_timestamp;
+        pool.update_price_accumulators(now);
+        if let Some(observations) = ctx.accounts.observations.as_mut() {
+            observations.write(pool, now);
+        }
+        pool.reserve_a = pool
+            .reserve_a
+            .checked_add(fee_a)
+            .ok_or(DexError::MathOverflow)?;
+        pool.reserve_b = pool
+            .reserve_b
+            .checked_add(fee_b)
+            .ok_or(DexError::MathOverflow)?;
+        pool.flash_loan_active = false;
+        pool.flash_loan_amount_a = 0;
+        pool.flash_loan_amount_b = 0;
+        pool.flash_loan_fee_a = 0;
+        pool.flash_loan_fee_b = 0;
+
+        emit!(FlashLoanRepaid {
+            pool: pool.key(),
+            user: ctx.accounts.user.key(),
+            amount_a,
+            amount_b,
+            fee_a,
+            fee_b,
+        });
+
+        msg!("Flash loan repaid with fees: {} Token A, {} Token B", fee_a, fee_b);
+        Ok(())
+    }
+
+    /// Create the pool's oracle observations account with a single slot.
+    /// Anyone can pay for it; swaps that pass it record a snapshot.
+    pub fn initialize_observations(ctx: Context<InitializeObservations>) -> Result<()> {
+        let now = Clock::get()?.unix_timestamp;
+        let observations = &mut ctx.accounts.observations;
+        observations.pool = ctx.accounts.pool.key();
+        observations.bump = ctx.bumps.observations;
+        observations.index = 0;
+        observations.cardinality = 1;
+        observations.cardinality_next = 1;
+        observations.observations = vec![ctx.accounts.pool.observation_at(now)];
+
+        msg!("Observations initialized for pool {}", observations.pool);
+        Ok(())
+    }
+
+    /// Grow the observations ring buffer to `cardinality_next` slots. Anyone can pay.
+    ///
+    /// One call can add at most 10 KiB (about 180 slots); grow in steps for more.
+    pub fn increase_observation_cardinality(
+        ctx: Context<IncreaseObservationCardinality>,
+        cardinality_next: u16,
+    ) -> Result<()> {
+        let observations = &mut ctx.accounts.observations;
+        require!(
+            cardinality_next > observations.cardinality_next
+                && cardinality_next <= Observations::MAX_CARDINALITY,
+            DexError::InvalidObservationCardinality
+        );
+        observations
+            .observations
+            .resize(cardinality_next as usize, Observation::default());
+        observations.cardinality_next = cardinality_next;
+
+        msg!("Observation cardinality increased to {}", cardinality_next);
+        Ok(())
+    }
+
+    /// Create the tick array starting at `start_tick_index` for a concentrated pool.
+    /// Anyone can pay for it.
+    ///
+    /// `start_tick_index` must be a multiple of `TICK_ARRAY_SIZE * tick_spacing`
+    /// (see `TickArray::start_index_for`).
+    pub fn initialize_tick_array(
+        ctx: Context<InitializeTickArray>,
+        start_tick_index: i32,
+    ) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        require!(
+            pool.curve_type == CurveType::Concentrated,
+            DexError::WrongCurveType
+        );
+        let spacing = pool.tick_spacing;
+        require!(
+            start_tick_index == TickArray::start_index_for(start_tick_index, spacing)
+                && start_tick_index >= TickArray::start_index_for(clmm::MIN_TICK, spacing)
+                && start_tick_index <= clmm::MAX_TICK,
+            DexError::InvalidTickArray
+        );
+
+        let tick_array = &mut ctx.accounts.tick_array;
+        tick_array.pool = pool.key();
+        tick_array.start_tick_index = start_tick_index;
+
+        msg!("Tick array initialized at tick {}", start_tick_index);
+        Ok(())
+    }
+
+    /// Open an empty position between `tick_lower` and `tick_upper` on a concentrated pool.
+    ///
+    /// Both ticks must be multiples of the pool's tick spacing within `MIN_TICK..=MAX_TICK`.
+    /// Fund it with `increase_liquidity`.
+    pub fn open_position(
+        ctx: Context<OpenPosition>,
+        tick_lower: i32,
+        tick_upper: i32,
+    ) -> Result<()> {
+        let pool = &ctx.accounts.pool;
+        require!(
+            pool.curve_type == CurveType::Concentrated,
+            DexError::WrongCurveType
+        );
+        let spacing = pool.tick_spacing as i32;
+        require!(
+            tick_lower < tick_upper
+                && tick_lower >= clmm::MIN_TICK
+                && tick_upper <= clmm::MAX_TICK
+                && tick_lower % spacing == 0
+                && tick_upper % spacing == 0,
+            DexError::InvalidTick
+        );
+
+        let position = &mut ctx.accounts.position;
+        position.pool = pool.key();
+        position.owner = ctx.accounts.owner.key();
+        position.tick_lower = tick_lower;
+        position.tick_upper = tick_upper;
+        position.bump = ctx.bumps.position;
+
+        emit!(PositionOpened {
+            pool: position.pool,
+            position: position.key(),
+            owner: position.owner,
+            tick_lower,
+            tick_upper,
+        });
+
+        msg!("Position opened: ticks {} to {}", tick_lower, tick_upper);
+        Ok(())
+    }
+
+    /// Add `liquidity` to a concentrated position, paying at most `max_amount_a` / `max_amount_b`.
+    ///
+    /// The amounts owed are rounded up and grossed up by any transfer fee, so the vaults
+    /// receive exactly what the range requires.
+    /// Param `deadline` is an optional unix timestamp after which the deposit fails.
+    /// Transfer hook accounts for Token-2022 mints go in `remaining_accounts`.
+    pub fn increase_liquidity<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ModifyPosition<'info>>,
+        liquidity: u128,
+        max_amount_a: u64,
+        max_amount_b: u64,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        require!(
+            !ctx.accounts.config.paused && !ctx.accounts.pool.paused,
+            DexError::Paused
+        );
+        let liquidity_delta = i128::try_from(liquidity)
+            .ok()
+            .filter(|&delta| delta > 0)
+            .ok_or(DexError::InvalidAmount)?;
+        let (amount_a, amount_b) = ctx.accounts.modify_position(liquidity_delta)?;
+
+        let amount_a_sent = amount_a
+            .checked_add(calculate_transfer_inverse_fee(&ctx.accounts.token_a_mint, amount_a)?)
+            .ok_or(DexError::MathOverflow)?;
+        let amount_b_sent = amount_b
+            .checked_add(calculate_transfer_inverse_fee(&ctx.accounts.token_b_mint, amount_b)?)
+            .ok_or(DexError::MathOverflow)?;
+        require!(
+            amount_a_sent <= max_amount_a && amount_b_sent <= max_amount_b,
+            DexError::SlippageExceeded
+        );
+
+        if amount_a_sent > 0 {
+            transfer_tokens(
+                &ctx.accounts.token_a_program,
+                &ctx.accounts.user_token_a,
+                &ctx.accounts.token_a_vault,
+                &ctx.accounts.token_a_mint,
+                ctx.accounts.owner.to_account_info(),
+                &[],
+                ctx.remaining_accounts,
+                amount_a_sent,
+            )?;
+        }
+        if amount_b_sent > 0 {
+            transfer_tokens(
+                &ctx.accounts.token_b_program,
+                &ctx.accounts.user_token_b,
+                &ctx.accounts.token_b_vault,
+                &ctx.accounts.token_b_mint,
+                ctx.accounts.owner.to_account_info(),
+                &[],
+                ctx.remaining_accounts,
+                amount_b_sent,
+            )?;
+        }
+
+        let pool = &mut ctx.accounts.pool;
+        pool.reserve_a = pool
+            .reserve_a
+            .checked_add(amount_a)
+            .ok_or(DexError::MathOverflow)?;
+        pool.reserve_b = pool
+            .reserve_b
+            .checked_add(amount_b)
+            .ok_or(DexError::MathOverflow)?;
+
+        emit!(PositionLiquidityChanged {
+            pool: pool.key(),
+            position: ctx.accounts.position.key(),
+            liquidity_delta,
+            amount_a,
+            amount_b,
+        });
+
+        msg!(
+            "Liquidity increased: {} Token A, {} Token B",
+            amount_a,
+            amount_b
+        );
+        Ok(())
+    }
+
+    /// Remove `liquidity` from a concentrated position and withdraw the tokens it held.
+    ///
+    /// Never paused, so LPs can always exit. Earned fees stay on the position until
+    /// `collect_fees`.
+    /// Param `deadline` is an optional unix timestamp after which the withdrawal fails.
+    /// Transfer hook accounts for Token-2022 mints go in `remaining_accounts`.
+    pub fn decrease_liquidity<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ModifyPosition<'info>>,
+        liquidity: u128,
+        min_amount_a: u64,
+        min_amount_b: u64,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        let liquidity_delta = i128::try_from(liquidity)
+            .ok()
+            .filter(|&delta| delta > 0)
+            .ok_or(DexError::InvalidAmount)?;
+        let (amount_a, amount_b) = ctx.accounts.modify_position(-liquidity_delta)?;
+
+        // ensure amount the user receives, net of transfer fees, is under slippage.
+        require!(
+            amount_after_transfer_fee(&ctx.accounts.token_a_mint, amount_a)? >= min_amount_a
+                && amount_after_transfer_fee(&ctx.accounts.token_b_mint, amount_b)? >= min_amount_b,
+            DexError::SlippageExceeded
+        );
+        ctx.accounts
+            .withdraw(amount_a, amount_b, ctx.remaining_accounts)?;
+
+        emit!(PositionLiquidityChanged {
+            pool: ctx.accounts.pool.key(),
+            position: ctx.accounts.position.key(),
+            liquidity_delta: -liquidity_delta,
+            amount_a,
+            amount_b,
+        });
+
+        msg!(
+            "Liquidity decreased: {} Token A, {} Token B",
+            amount_a,
+            amount_b
+        );
+        Ok(())
+    }
+
+    /// Send a concentrated position's earned swap fees to its owner.
+    ///
+    /// Never paused. Transfer hook accounts for Token-2022 mints go in `remaining_accounts`.
+    pub fn collect_fees<'info>(
+        ctx: Context<'_, '_, 'info, 'info, ModifyPosition<'info>>,
+    ) -> Result<()> {
+        // credit the fees earned since the position last changed
+        if ctx.accounts.position.liquidity > 0 {
+            ctx.accounts.modify_position(0)?;
+        }
+        let position = &mut ctx.accounts.position;
+        let amount_a = position.tokens_owed_a;
+        let amount_b = position.tokens_owed_b;
+        position.tokens_owed_a = 0;
+        position.tokens_owed_b = 0;
+        ctx.accounts
+            .withdraw(amount_a, amount_b, ctx.remaining_accounts)?;
+
+        emit!(PositionFeesCollected {
+            pool: ctx.accounts.pool.key(),
+            position: ctx.accounts.position.key(),
+            amount_a,
+            amount_b,
+        });
+
+        msg!(
+            "Position fees collected: {} Token A, {} Token B",
+            amount_a,
+            amount_b
+        );
+        Ok(())
+    }
+
+    /// Swap `amount_in` -> at least `min_amount_out` on a concentrated pool, crossing ticks.
+    ///
+    /// Pass the tick array holding the current tick as `tick_array_0`, followed by up
+    /// to two more in the swap direction (prices falling for A to B, rising otherwise).
+    /// Param `sqrt_price_limit` stops the swap at that Q64.64 sqrt price; the unused
+    /// input is then never pulled from the user. Defaults to the end of the price range.
+    /// Concentrated pools have no LP token, so the protocol's share of the fee is split
+    /// off every swap whatever the config's `protocol_fee_mode`.
+    /// Param `deadline` is an optional unix timestamp after which the swap fails.
+    /// Transfer hook accounts for Token-2022 mints go in `remaining_accounts`.
+    pub fn swap_concentrated<'info>(
+        ctx: Context<'_, '_, 'info, 'info, SwapConcentrated<'info>>,
+        amount_in: u64,
+        min_amount_out: u64,
+        is_a_to_b: bool,
+        sqrt_price_limit: Option<u128>,
+        deadline: Option<i64>,
+    ) -> Result<()> {
+        check_deadline(deadline)?;
+        let accounts = &mut *ctx.accounts;
+        require!(
+            !accounts.config.paused && !accounts.pool.paused,
+            DexError::Paused
+        );
+        require!(
+            accounts.pool.curve_type == CurveType::Concentrated,
+            DexError::WrongCurveType
+        );
+        // input amount cannot be zero.
+        require!(amount_in > 0, DexError::InvalidAmount);
+
+        let (
+            mint_in,
+            mint_out,
+            token_program_in,
+            token_program_out,
+            user_token_in,
+            user_token_out,
+            vault_in,
+            vault_out,
+        ) = if is_a_to_b {
+            (
+                &accounts.token_a_mint,
+                &accounts.token_b_mint,
+                &accounts.token_a_program,
+                &accounts.token_b_program,
+                &accounts.user_token_a,
+                &accounts.user_token_b,
+                &accounts.token_a_vault,
+                &accounts.token_b_vault,
+            )
+        } else {
+            (
+                &accounts.token_b_mint,
+                &accounts.token_a_mint,
+                &accounts.token_b_program,
+                &accounts.token_a_program,
+                &accounts.user_token_b,
+                &accounts.user_token_a,
+                &accounts.token_b_vault,
+                &accounts.token_a_vault,
+            )
+        };
+        let amount_in_received = amount_after_transfer_fee(mint_in, amount_in)?;
+
+        let pool = &mut accounts.pool;
+        let now = Clock::get()?.unix_timestamp;
+        pool.update_price_accumulators(now);
+        if let Some(observations) = accounts.observations.as_mut() {
+            observations.write(pool, now);
+        }
+
+        let mut tick_arrays: Vec<&mut TickArray> = vec![&mut **accounts.tick_array_0];
+        for tick_array in [&mut accounts.tick_array_1, &mut accounts.tick_array_2]
+            .into_iter()
+            .flatten()
+        {
+            tick_arrays.push(&mut ***tick_array);
+        }
+        let sqrt_price_limit = sqrt_price_limit.unwrap_or(if is_a_to_b {
+            clmm::MIN_SQRT_PRICE
+        } else {
+            clmm::MAX_SQRT_PRICE
+        });
+        let fee_bps = pool.fee_bps(now);
+        let result = clmm::swap(
+            pool,
+            &mut tick_arrays,
+            amount_in_received,
+            is_a_to_b,
+            sqrt_price_limit,
+            fee_bps,
+            accounts.config.protocol_fee_share_bps,
+        )?;
+
+        // ensure user can get `min_amount_out`, net of the output transfer fee, or fail.
+        let amount_out_received = amount_after_transfer_fee(mint_out, result.amount_out)?;
+        require!(amount_out_received >= min_amount_out, DexError::SlippageExceeded);
+        // ensure amount received is non-zero.
+        require!(amount_out_received > 0, DexError::InvalidAmount);
+
+        // a swap stopped by the price limit only pulls what it used.
+        let amount_in = if result.amount_in < amount_in_received {
+            result
+                .amount_in
+                .checked_add(calculate_transfer_inverse_fee(mint_in, result.amount_in)?)
+                .ok_or(DexError::MathOverflow)?
+        } else {
+            amount_in
+        };
+        let pool_index = pool.pool_index.to_le_bytes();
+        let authority_seeds = &[
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool_index.as_ref(),
+            &[pool.authority_bump],
+        ];
+        transfer_tokens(
+            token_program_in,
+            user_token_in,
+            vault_in,
+            mint_in,
+            accounts.user.to_account_info(),
+            &[],
+            ctx.remaining_accounts,
+            amount_in,
+        )?;
+        transfer_tokens(
+            token_program_out,
+            vault_out,
+            user_token_out,
+            mint_out,
+            accounts.pool_authority.to_account_info(),
+            &[authority_seeds],
+            ctx.remaining_accounts,
+            result.amount_out,
+        )?;
+
+        pool.book_swap(result.amount_in, result.amount_out, result.protocol_fee, is_a_to_b)?;
+
+        emit!(SwapExecuted {
+            pool: pool.key(),
+            user: accounts.user.key(),
+            amount_in,
+            amount_out: result.amount_out,
+            is_a_to_b,
+            protocol_fee: result.protocol_fee,
+        });
+
+        msg!(
+            "Concentrated swap executed: {} in -> {} out, tick {}",
+            amount_in,
+            result.amount_out,
+            pool.tick_current
+        );
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct InitializeConfig<'info> {
+    #[account(
+        init,
+        payer = admin,
+        space = Config::LEN,
+        seeds = [b"config"],
+        bump
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(constraint = program.programdata_address()? == Some(program_data.key()))]
+    pub program: Program<'info, crate::program::NjaSwap>,
+    #[account(
+        constraint = program_data.upgrade_authority_address == Some(admin.key())
+            @ DexError::Unauthorized
+    )]
+    pub program_data: Account<'info, ProgramData>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct UpdateConfig<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ DexError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetGlobalPause<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.is_pause_authority(&authority.key()) @ DexError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetPoolPause<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.is_pause_authority(&authority.key()) @ DexError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub pool: Account<'info, Pool>,
+    pub authority: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct AcceptAdmin<'info> {
+    #[account(
+        mut,
+        seeds = [b"config"],
+        bump = config.bump,
+        constraint = config.pending_admin == pending_admin.key() @ DexError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub pending_admin: Signer<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RampFee<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ DexError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+    #[account(mut)]
+    pub pool: Account<'info, Pool>,
+}
+
+#[derive(Accounts)]
+pub struct AllowMint<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ DexError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    pub mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = admin,
+        space = AllowedMint::LEN,
+        seeds = [b"allowed_mint", mint.key().as_ref()],
+        bump
+    )]
+    pub allowed_mint: Account<'info, AllowedMint>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DisallowMint<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ DexError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    #[account(mut)]
+    pub admin: Signer<'info>,
+    #[account(
+        mut,
+        close = admin,
+        seeds = [b"allowed_mint", allowed_mint.mint.as_ref()],
+        bump = allowed_mint.bump
+    )]
+    pub allowed_mint: Account<'info, AllowedMint>,
+}
+
+#[derive(Accounts)]
+#[instruction(pool_index: u16)]
+pub struct Initialize<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    /// Creating an account for the liquidity pool
+    #[account(
+        init,
+        payer = payer,
+        space = Pool::LEN,
+        seeds = [
+            b"pool",
+            token_a_mint.key().as_ref(),
+            token_b_mint.key().as_ref(),
+            pool_index.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub pool: Account<'info, Pool>,
+    /// CHECK: PDA authority controlled by program
+    #[account(
+        seeds = [
+            b"pool_authority",
+            token_a_mint.key().as_ref(),
+            token_b_mint.key().as_ref(),
+            pool_index.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(
+        init,
+        payer = payer,
+        token::mint = token_a_mint,
+        token::authority = pool_authority,
+        token::token_program = token_a_program,
+        seeds = [
+            b"token_a_vault",
+            token_a_mint.key().as_ref(),
+            token_b_mint.key().as_ref(),
+            pool_index.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = payer,
+        token::mint = token_b_mint,
+        token::authority = pool_authority,
+        token::token_program = token_b_program,
+        seeds = [
+            b"token_b_vault",
+            token_a_mint.key().as_ref(),
+            token_b_mint.key().as_ref(),
+            pool_index.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        init,
+        payer = payer,
+        seeds = [
+            b"lp_token_mint",
+            token_a_mint.key().as_ref(),
+            token_b_mint.key().as_ref(),
+            pool_index.to_le_bytes().as_ref()
+        ],
+        bump,
+        mint::decimals = 9,
+        mint::authority = pool_authority,
+        mint::token_program = token_program
+    )]
+    pub lp_token_mint: InterfaceAccount<'info, Mint>,
+    /// Holds the MINIMUM_LIQUIDITY LP tokens locked on the first deposit
+    /// Owned by the pool authority, which never transfers out of it
+    #[account(
+        init,
+        payer = payer,
+        token::mint = lp_token_mint,
+        token::authority = pool_authority,
+        token::token_program = token_program,
+        seeds = [b"locked_lp", pool.key().as_ref()],
+        bump
+    )]
+    pub locked_lp_token: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+    /// Token program of the LP mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    pub rent: Sysvar<'info, Rent>,
+    /// Allowlist entry for Token A; when passed, its extensions are not screened
+    #[account(
+        seeds = [b"allowed_mint", token_a_mint.key().as_ref()],
+        bump = allowed_mint_a.bump
+    )]
+    pub allowed_mint_a: Option<Account<'info, AllowedMint>>,
+    /// Allowlist entry for Token B; when passed, its extensions are not screened
+    #[account(
+        seeds = [b"allowed_mint", token_b_mint.key().as_ref()],
+        bump = allowed_mint_b.bump
+    )]
+    pub allowed_mint_b: Option<Account<'info, AllowedMint>>,
+}
+
+impl<'info> Initialize<'info> {
+    /// Runs the checks every curve shares, fills in the common pool fields and emits
+    /// `PoolInitialized`.
+    ///
+    /// Call after setting the curve-specific fields, which the event reports.
+    fn init_pool(
+        &mut self,
+        authority_bump: u8,
+        pool_index: u16,
+        fee_bps: Option<u64>,
+        curve_type: CurveType,
+    ) -> Result<()> {
+        let fee_bps = fee_bps.unwrap_or(self.config.default_fee_bps);
+
+        require!(
+            self.token_a_mint.key() != self.token_b_mint.key(),
+            DexError::InvalidTokenPair
+        );
+        // canonical mint order: reject (B, A) so it cannot shadow the (A, B) pools.
+        let (_, _, flipped) = sort_mints(self.token_a_mint.key(), self.token_b_mint.key());
+        require!(!flipped, DexError::MintsNotSorted);
+        // fee must leave a non-zero amount to swap.
+        require!((fee_bps as u128) < FEE_DEN, DexError::FeeOutOfRange);
+        // refuse mints whose extensions can drain or brick the pool, unless allowlisted.
+        if self.allowed_mint_a.is_none() {
+            screen_mint_extensions(&self.token_a_mint)?;
+        }
+        if self.allowed_mint_b.is_none() {
+            screen_mint_extensions(&self.token_b_mint)?;
+        }
+
+        let pool = &mut self.pool;
+        pool.authority = self.pool_authority.key();
+        pool.token_a_mint = self.token_a_mint.key();
+        pool.token_b_mint = self.token_b_mint.key();
+        pool.token_a_vault = self.token_a_vault.key();
+        pool.token_b_vault = self.token_b_vault.key();
+        pool.lp_token_mint = self.lp_token_mint.key();
+        pool.reserve_a = 0;
+        pool.reserve_b = 0;
+        pool.fee_numerator = fee_bps;
+        pool.authority_bump = authority_bump;
+        pool.pool_index = pool_index;
+        pool.paused = false;
+        pool.last_update_timestamp = Clock::get()?.unix_timestamp;
+        pool.curve_type = curve_type;
+
+        emit!(PoolInitialized {
+            pool: pool.key(),
+            token_a_mint: pool.token_a_mint,
+            token_b_mint: pool.token_b_mint,
+            lp_token_mint: pool.lp_token_mint,
+            pool_index: pool.pool_index,
+            fee_bps: pool.fee_numerator,
+            curve_type: pool.curve_type,
+            amp: pool.amp,
+            weight_a: pool.weight_a,
+            weight_b: pool.weight_b,
+            tick_spacing: pool.tick_spacing,
+            sqrt_price: pool.sqrt_price,
+        });
+
+        msg!("Pool initialized successfully");
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct Add<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut, has_one = token_a_mint, has_one = token_b_mint)]
+    pub pool: Account<'info, Pool>,
+    /// CHECK: PDA authority controlled by program
+    #[account(
+        seeds = [
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool.pool_index.to_le_bytes().as_ref()
+        ],
+        bump = pool.authority_bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, address = pool.token_a_vault)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = pool.token_b_vault)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = pool.lp_token_mint)]
+    pub lp_token_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, constraint = user_token_a.mint == pool.token_a_mint)]
+    pub user_token_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_b.mint == pool.token_b_mint)]
+    pub user_token_b: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = user_lp_token.mint == pool.lp_token_mint)]
+    pub user_lp_token: InterfaceAccount<'info, TokenAccount>,
+    /// Receives MINIMUM_LIQUIDITY on the first deposit
+    #[account(mut, seeds = [b"locked_lp", pool.key().as_ref()], bump)]
+    pub locked_lp_token: InterfaceAccount<'info, TokenAccount>,
+    /// Receives the protocol's LP fee; required in `ProtocolFeeMode::LpMint` only
+    #[account(
+        mut,
+        constraint = fee_recipient_lp_token.mint == pool.lp_token_mint,
+        constraint = fee_recipient_lp_token.owner == config.fee_recipient @ DexError::Unauthorized
+    )]
+    pub fee_recipient_lp_token: Option<InterfaceAccount<'info, TokenAccount>>,
+    pub user: Signer<'info>,
+    /// Token program of the LP mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    /// Oracle ring buffer, written when passed
+    #[account(
+        mut,
+        seeds = [b"observations", pool.key().as_ref()],
+        bump = observations.bump
+    )]
+    pub observations: Option<Account<'info, Observations>>,
+}
+
+impl<'info> Add<'info> {
+    /// Mints the protocol's LP fee into `lp_supply` (see `mint_protocol_lp_fee`).
+    fn mint_protocol_lp_fee(&self, lp_supply: &mut u64) -> Result<()> {
+        mint_protocol_lp_fee(
+            &self.config,
+            &self.pool,
+            &self.lp_token_mint,
+            self.fee_recipient_lp_token.as_ref(),
+            &self.pool_authority,
+            &self.token_program,
+            lp_supply,
+        )
+    }
+}
+
+/// In `ProtocolFeeMode::LpMint`, mints the protocol's share of sqrt(k) growth since
+/// `k_last` to the fee recipient and adds it to `lp_supply`. No-op in `PerSwap` mode.
+///
+/// Liquidity instructions call it before pricing their LP tokens, so the depositor or
+/// withdrawer is priced after the fee.
+fn mint_protocol_lp_fee<'info>(
+    config: &Config,
+    pool: &Pool,
+    lp_token_mint: &InterfaceAccount<'info, Mint>,
+    fee_recipient_lp_token: Option<&InterfaceAccount<'info, TokenAccount>>,
+    pool_authority: &UncheckedAccount<'info>,
+    token_program: &Interface<'info, TokenInterface>,
+    lp_supply: &mut u64,
+) -> Result<()> {
+    if config.protocol_fee_mode != ProtocolFeeMode::LpMint {
+        return Ok(());
+    }
+    let fee_recipient_lp_token =
+        fee_recipient_lp_token.ok_or(DexError::FeeRecipientLpTokenMissing)?;
+    let protocol_lp_fee = calculate_protocol_lp_fee(
+        pool.root_k()?,
+        pool.k_last,
+        *lp_supply,
+        config.protocol_fee_share_bps,
+    )?;
+    if protocol_lp_fee == 0 {
+        return Ok(());
+    }
+
+    let pool_index = pool.pool_index.to_le_bytes();
+    let authority_seeds = &[
+        b"pool_authority",
+        pool.token_a_mint.as_ref(),
+        pool.token_b_mint.as_ref(),
+        pool_index.as_ref(),
+        &[pool.authority_bump],
+    ];
+    token_interface::mint_to(
+        CpiContext::new_with_signer(
+            token_program.to_account_info(),
+            MintTo {
+                mint: lp_token_mint.to_account_info(),
+                to: fee_recipient_lp_token.to_account_info(),
+                authority: pool_authority.to_account_info(),
+            },
+            &[authority_seeds],
+        ),
+        protocol_lp_fee,
+    )?;
+    *lp_supply = lp_supply
+        .checked_add(protocol_lp_fee)
+        .ok_or(DexError::MathOverflow)?;
+    Ok(())
+}
+
+#[derive(Accounts)]
+pub struct Swap<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut, has_one = token_a_mint, has_one = token_b_mint)]
+    pub pool: Account<'info, Pool>,
+    /// CHECK: PDA authority controlled by program
+    #[account(
+        seeds = [
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool.pool_index.to_le_bytes().as_ref()
+        ],
+        bump = pool.authority_bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, address = pool.token_a_vault)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = pool.token_b_vault)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_a.mint == pool.token_a_mint)]
+    pub user_token_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_b.mint == pool.token_b_mint)]
+    pub user_token_b: InterfaceAccount<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    /// Oracle ring buffer, written when passed
+    #[account(
+        mut,
+        seeds = [b"observations", pool.key().as_ref()],
+        bump = observations.bump
+    )]
+    pub observations: Option<Account<'info, Observations>>,
+}
+
+impl<'info> Swap<'info> {
+    /// Moves a quoted swap: pulls `amount_in` into the input vault, pays `amount_out`
+    /// from the output vault, splits off the protocol fee and updates the reserves.
+    ///
+    /// The reserves are credited with `amount_in` net of the input mint's transfer fee.
+    /// `remaining_accounts` carries any transfer hook accounts (see `transfer_tokens`).
+    fn settle_swap(
+        &mut self,
+        amount_in: u64,
+        amount_out: u64,
+        is_a_to_b: bool,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        let pool = &mut self.pool;
+        let config = &self.config;
+        let now = Clock::get()?.unix_timestamp;
+        pool.update_price_accumulators(now);
+        if let Some(observations) = self.observations.as_mut() {
+            observations.write(pool, now);
+        }
+
+        let (
+            mint_in,
+            mint_out,
+            token_program_in,
+            token_program_out,
+            user_token_in,
+            user_token_out,
+            vault_in,
+            vault_out,
+        ) = if is_a_to_b {
+            (
+                &self.token_a_mint,
+                &self.token_b_mint,
+                &self.token_a_program,
+                &self.token_b_program,
+                &self.user_token_a,
+                &self.user_token_b,
+                &self.token_a_vault,
+                &self.token_b_vault,
+            )
+        } else {
+            (
+                &self.token_b_mint,
+                &self.token_a_mint,
+                &self.token_b_program,
+                &self.token_a_program,
+                &self.user_token_b,
+                &self.user_token_a,
+                &self.token_b_vault,
+                &self.token_a_vault,
+            )
+        };
+        let amount_in_received = amount_after_transfer_fee(mint_in, amount_in)?;
+
+        // split the protocol's share off the fee; it stays in the vault but not in the reserves.
+        let protocol_fee = config.swap_protocol_fee(amount_in_received, pool.fee_bps(now))?;
+
+        // transfer `amount_in` to vault, `amount_out` to user.
+        let pool_index = pool.pool_index.to_le_bytes();
+        let authority_seeds = &[
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool_index.as_ref(),
+            &[pool.authority_bump],
+        ];
+        transfer_tokens(
+            token_program_in,
+            user_token_in,
+            vault_in,
+            mint_in,
+            self.user.to_account_info(),
+            &[],
+            remaining_accounts,
+            amount_in,
+        )?;
+        transfer_tokens(
+            token_program_out,
+            vault_out,
+            user_token_out,
+            mint_out,
+            self.pool_authority.to_account_info(),
+            &[authority_seeds],
+            remaining_accounts,
+            amount_out,
+        )?;
+
+        pool.book_swap(amount_in_received, amount_out, protocol_fee, is_a_to_b)?;
+
+        emit!(SwapExecuted {
+            pool: pool.key(),
+            user: self.user.key(),
+            amount_in,
+            amount_out,
+            is_a_to_b,
+            protocol_fee,
+        });
+
+        msg!("Swap executed: {} in -> {} out", amount_in, amount_out);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct SwapRoute<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mint::token_program = token_in_program)]
+    pub token_in_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_out_program)]
+    pub token_out_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, constraint = user_token_in.mint == token_in_mint.key())]
+    pub user_token_in: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_out.mint == token_out_mint.key())]
+    pub user_token_out: InterfaceAccount<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    pub token_in_program: Interface<'info, TokenInterface>,
+    pub token_out_program: Interface<'info, TokenInterface>,
+}
+
+/// One pool of a `swap_route`, loaded from its `ROUTE_HOP_ACCOUNTS` remaining accounts.
+pub struct RouteHop<'info> {
+    pub pool: Account<'info, Pool>,
+    pub pool_authority: AccountInfo<'info>,
+    pub vault_in: InterfaceAccount<'info, TokenAccount>,
+    pub vault_out: InterfaceAccount<'info, TokenAccount>,
+    pub mint_out: InterfaceAccount<'info, Mint>,
+    pub token_program_out: Interface<'info, TokenInterface>,
+    pub observations: Option<Account<'info, Observations>>,
+    pub is_a_to_b: bool,
+}
+
+impl<'info> RouteHop<'info> {
+    /// Checks a hop's accounts against its `Pool`, with `mint_in` the token entering the hop.
+    fn load(accounts: &'info [AccountInfo<'info>], mint_in: Pubkey) -> Result<Self> {
+        let [pool, pool_authority, vault_in, vault_out, mint_out, token_program_out, observations] =
+            accounts
+        else {
+            return err!(DexError::InvalidRoute);
+        };
+        let pool = Account::<Pool>::try_from(pool)?;
+        require!(!pool.paused, DexError::Paused);
+        require!(!pool.flash_loan_active, DexError::FlashLoanActive);
+
+        let is_a_to_b = if mint_in == pool.token_a_mint {
+            true
+        } else {
+            require_keys_eq!(mint_in, pool.token_b_mint, DexError::InvalidRoute);
+            false
+        };
+        let (vault_in_key, vault_out_key, mint_out_key) = if is_a_to_b {
+            (pool.token_a_vault, pool.token_b_vault, pool.token_b_mint)
+        } else {
+            (pool.token_b_vault, pool.token_a_vault, pool.token_a_mint)
+        };
+        require_keys_eq!(pool_authority.key(), pool.authority, DexError::InvalidRoute);
+        require_keys_eq!(vault_in.key(), vault_in_key, DexError::InvalidRoute);
+        require_keys_eq!(vault_out.key(), vault_out_key, DexError::InvalidRoute);
+        require_keys_eq!(mint_out.key(), mint_out_key, DexError::InvalidRoute);
+        let token_program_out = Interface::<TokenInterface>::try_from(token_program_out)?;
+        require_keys_eq!(*mint_out.owner, token_program_out.key(), DexError::InvalidRoute);
+        // the program id stands in for a skipped oracle, as for Anchor's optional accounts.
+        let observations = if observations.key() == crate::ID {
+            None
+        } else {
+            let observations = Account::<Observations>::try_from(observations)?;
+            require_keys_eq!(observations.pool, pool.key(), DexError::InvalidRoute);
+            Some(observations)
+        };
+
+        Ok(Self {
+            pool,
+            pool_authority: pool_authority.clone(),
+            vault_in: InterfaceAccount::try_from(vault_in)?,
+            vault_out: InterfaceAccount::try_from(vault_out)?,
+            mint_out: InterfaceAccount::try_from(mint_out)?,
+            token_program_out,
+            observations,
+            is_a_to_b,
+        })
+    }
+
+    /// Prices `amount_in`, already in `vault_in`, and pays the output to `to`.
+    ///
+    /// Books the protocol fee and reserves like `Swap::settle_swap`, and writes the hop's
+    /// `observations` when passed.
+    /// Returns the amount `to` receives, net of the output mint's transfer fee.
+    fn swap(
+        &mut self,
+        config: &Config,
+        user: Pubkey,
+        to: &InterfaceAccount<'info, TokenAccount>,
+        amount_in: u64,
+        now: i64,
+        hook_accounts: &[AccountInfo<'info>],
+    ) -> Result<u64> {
+        let pool = &mut self.pool;
+        let is_a_to_b = self.is_a_to_b;
+        pool.update_price_accumulators(now);
+        if let Some(observations) = self.observations.as_mut() {
+            observations.write(pool, now);
+        }
+        let fee_bps = pool.fee_bps(now);
+        let amount_out = pool.quote_swap(amount_in, is_a_to_b, fee_bps)?;
+
+        // split the protocol's share off the fee; it stays in the vault but not in the reserves.
+        let protocol_fee = config.swap_protocol_fee(amount_in, fee_bps)?;
+
+        let pool_index = pool.pool_index.to_le_bytes();
+        let authority_seeds = &[
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool_index.as_ref(),
+            &[pool.authority_bump],
+        ];
+        transfer_tokens(
+            &self.token_program_out,
+            &self.vault_out,
+            to,
+            &self.mint_out,
+            self.pool_authority.clone(),
+            &[authority_seeds],
+            hook_accounts,
+            amount_out,
+        )?;
+
+        pool.book_swap(amount_in, amount_out, protocol_fee, is_a_to_b)?;
+
+        emit!(SwapExecuted {
+            pool: pool.key(),
+            user,
+            amount_in,
+            amount_out,
+            is_a_to_b,
+            protocol_fee,
+        });
+
+        amount_after_transfer_fee(&self.mint_out, amount_out)
+    }
 }
 
 #[derive(Accounts)]
 pub struct Remove<'info> {
-    #[account(mut)]
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut, has_one = token_a_mint, has_one = token_b_mint)]
     pub pool: Account<'info, Pool>,
     /// CHECK: PDA authority controlled by program
     #[account(
-        seeds = [b"pool_authority", pool.token_a_mint.as_ref(), pool.token_b_mint.as_ref()],
+        seeds = [
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool.pool_index.to_le_bytes().as_ref()
+        ],
         bump = pool.authority_bump
     )]
     pub pool_authority: UncheckedAccount<'info>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
     #[account(mut, address = pool.token_a_vault)]
-    pub token_a_vault: Account<'info, TokenAccount>,
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
     #[account(mut, address = pool.token_b_vault)]
-    pub token_b_vault: Account<'info, TokenAccount>,
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
     #[account(mut, address = pool.lp_token_mint)]
-    pub lp_token_mint: Account<'info, Mint>,
+    pub lp_token_mint: InterfaceAccount<'info, Mint>,
     #[account(mut, constraint = user_token_a.mint == pool.token_a_mint)]
-    pub user_token_a: Account<'info, TokenAccount>,
+    pub user_token_a: InterfaceAccount<'info, TokenAccount>,
     #[account(mut, constraint = user_token_b.mint == pool.token_b_mint)]
-    pub user_token_b: Account<'info, TokenAccount>,
+    pub user_token_b: InterfaceAccount<'info, TokenAccount>,
     #[account(mut, constraint = user_lp_token.mint == pool.lp_token_mint)]
-    pub user_lp_token: Account<'info, TokenAccount>,
+    pub user_lp_token: InterfaceAccount<'info, TokenAccount>,
+    /// Receives the protocol's LP fee; required in `ProtocolFeeMode::LpMint` only
+    #[account(
+        mut,
+        constraint = fee_recipient_lp_token.mint == pool.lp_token_mint,
+        constraint = fee_recipient_lp_token.owner == config.fee_recipient @ DexError::Unauthorized
+    )]
+    pub fee_recipient_lp_token: Option<InterfaceAccount<'info, TokenAccount>>,
+    pub user: Signer<'info>,
+    /// Token program of the LP mint
+    pub token_program: Interface<'info, TokenInterface>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    /// Oracle ring buffer, written when passed
+    #[account(
+        mut,
+        seeds = [b"observations", pool.key().as_ref()],
+        bump = observations.bump
+    )]
+    pub observations: Option<Account<'info, Observations>>,
+}
+
+impl<'info> Remove<'info> {
+    /// Mints the protocol's LP fee into `lp_supply` (see `mint_protocol_lp_fee`).
+    fn mint_protocol_lp_fee(&self, lp_supply: &mut u64) -> Result<()> {
+        mint_protocol_lp_fee(
+            &self.config,
+            &self.pool,
+            &self.lp_token_mint,
+            self.fee_recipient_lp_token.as_ref(),
+            &self.pool_authority,
+            &self.token_program,
+            lp_supply,
+        )
+    }
+}
+
+#[derive(Accounts)]
+pub struct CollectProtocolFees<'info> {
+    #[account(
+        seeds = [b"config"],
+        bump = config.bump,
+        has_one = admin @ DexError::Unauthorized
+    )]
+    pub config: Account<'info, Config>,
+    pub admin: Signer<'info>,
+    #[account(mut, has_one = token_a_mint, has_one = token_b_mint)]
+    pub pool: Account<'info, Pool>,
+    /// CHECK: PDA authority controlled by program
+    #[account(
+        seeds = [
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool.pool_index.to_le_bytes().as_ref()
+        ],
+        bump = pool.authority_bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, address = pool.token_a_vault)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = pool.token_b_vault)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = treasury_token_a.mint == pool.token_a_mint,
+        constraint = treasury_token_a.owner == config.fee_recipient @ DexError::Unauthorized
+    )]
+    pub treasury_token_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(
+        mut,
+        constraint = treasury_token_b.mint == pool.token_b_mint,
+        constraint = treasury_token_b.owner == config.fee_recipient @ DexError::Unauthorized
+    )]
+    pub treasury_token_b: InterfaceAccount<'info, TokenAccount>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct SyncReserves<'info> {
+    #[account(mut)]
+    pub pool: Account<'info, Pool>,
+    #[account(address = pool.token_a_vault)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(address = pool.token_b_vault)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    /// Oracle ring buffer, written when passed
+    #[account(
+        mut,
+        seeds = [b"observations", pool.key().as_ref()],
+        bump = observations.bump
+    )]
+    pub observations: Option<Account<'info, Observations>>,
+}
+
+#[derive(Accounts)]
+pub struct Skim<'info> {
+    #[account(has_one = token_a_mint, has_one = token_b_mint)]
+    pub pool: Account<'info, Pool>,
+    /// CHECK: PDA authority controlled by program
+    #[account(
+        seeds = [
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool.pool_index.to_le_bytes().as_ref()
+        ],
+        bump = pool.authority_bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, address = pool.token_a_vault)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = pool.token_b_vault)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = recipient_token_a.mint == pool.token_a_mint)]
+    pub recipient_token_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = recipient_token_b.mint == pool.token_b_mint)]
+    pub recipient_token_b: InterfaceAccount<'info, TokenAccount>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+}
+
+#[derive(Accounts)]
+pub struct FlashLoan<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut, has_one = token_a_mint, has_one = token_b_mint)]
+    pub pool: Account<'info, Pool>,
+    /// CHECK: PDA authority controlled by program
+    #[account(
+        seeds = [
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool.pool_index.to_le_bytes().as_ref()
+        ],
+        bump = pool.authority_bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, address = pool.token_a_vault)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = pool.token_b_vault)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_a.mint == pool.token_a_mint)]
+    pub user_token_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_b.mint == pool.token_b_mint)]
+    pub user_token_b: InterfaceAccount<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    /// CHECK: the instructions sysvar, read to find the matching `flash_repay`
+    #[account(address = anchor_lang::solana_program::sysvar::instructions::ID)]
+    pub instructions: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct FlashRepay<'info> {
+    /// Must stay the first account: `flash_loan` finds its repayment by it
+    #[account(mut, has_one = token_a_mint, has_one = token_b_mint)]
+    pub pool: Account<'info, Pool>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, address = pool.token_a_vault)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = pool.token_b_vault)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_a.mint == pool.token_a_mint)]
+    pub user_token_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_b.mint == pool.token_b_mint)]
+    pub user_token_b: InterfaceAccount<'info, TokenAccount>,
+    pub user: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    /// Oracle ring buffer, written when passed
+    #[account(
+        mut,
+        seeds = [b"observations", pool.key().as_ref()],
+        bump = observations.bump
+    )]
+    pub observations: Option<Account<'info, Observations>>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeObservations<'info> {
+    pub pool: Account<'info, Pool>,
+    #[account(
+        init,
+        payer = payer,
+        space = Observations::space(1),
+        seeds = [b"observations", pool.key().as_ref()],
+        bump
+    )]
+    pub observations: Account<'info, Observations>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(cardinality_next: u16)]
+pub struct IncreaseObservationCardinality<'info> {
+    #[account(
+        mut,
+        seeds = [b"observations", observations.pool.as_ref()],
+        bump = observations.bump,
+        realloc = Observations::space(cardinality_next),
+        realloc::payer = payer,
+        realloc::zero = false
+    )]
+    pub observations: Account<'info, Observations>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(start_tick_index: i32)]
+pub struct InitializeTickArray<'info> {
+    pub pool: Account<'info, Pool>,
+    #[account(
+        init,
+        payer = payer,
+        space = TickArray::LEN,
+        seeds = [
+            b"tick_array",
+            pool.key().as_ref(),
+            start_tick_index.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub tick_array: Box<Account<'info, TickArray>>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(tick_lower: i32, tick_upper: i32)]
+pub struct OpenPosition<'info> {
+    pub pool: Account<'info, Pool>,
+    #[account(
+        init,
+        payer = owner,
+        space = Position::LEN,
+        seeds = [
+            b"position",
+            pool.key().as_ref(),
+            owner.key().as_ref(),
+            tick_lower.to_le_bytes().as_ref(),
+            tick_upper.to_le_bytes().as_ref()
+        ],
+        bump
+    )]
+    pub position: Account<'info, Position>,
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct ModifyPosition<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut, has_one = token_a_mint, has_one = token_b_mint)]
+    pub pool: Account<'info, Pool>,
+    /// CHECK: PDA authority controlled by program
+    #[account(
+        seeds = [
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool.pool_index.to_le_bytes().as_ref()
+        ],
+        bump = pool.authority_bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, address = pool.token_a_vault)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = pool.token_b_vault)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, has_one = pool, has_one = owner @ DexError::Unauthorized)]
+    pub position: Account<'info, Position>,
+    /// Tick array holding `position.tick_lower`
+    #[account(mut, has_one = pool @ DexError::InvalidTickArray)]
+    pub tick_array_lower: Box<Account<'info, TickArray>>,
+    /// Tick array holding `position.tick_upper`; omit when it is `tick_array_lower`
+    #[account(
+        mut,
+        has_one = pool @ DexError::InvalidTickArray,
+        constraint = tick_array_upper.key() != tick_array_lower.key() @ DexError::InvalidTickArray
+    )]
+    pub tick_array_upper: Option<Box<Account<'info, TickArray>>>,
+    #[account(mut, constraint = user_token_a.mint == pool.token_a_mint)]
+    pub user_token_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_b.mint == pool.token_b_mint)]
+    pub user_token_b: InterfaceAccount<'info, TokenAccount>,
+    pub owner: Signer<'info>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    /// Oracle ring buffer, written when passed
+    #[account(
+        mut,
+        seeds = [b"observations", pool.key().as_ref()],
+        bump = observations.bump
+    )]
+    pub observations: Option<Account<'info, Observations>>,
+}
+
+impl<'info> ModifyPosition<'info> {
+    /// Brings the price accumulators and `observations` up to date, then applies
+    /// `liquidity_delta` to the position (see `clmm::modify_position`)
+    fn modify_position(&mut self, liquidity_delta: i128) -> Result<(u64, u64)> {
+        let now = Clock::get()?.unix_timestamp;
+        self.pool.update_price_accumulators(now);
+        if let Some(observations) = self.observations.as_mut() {
+            observations.write(&self.pool, now);
+        }
+        clmm::modify_position(
+            &mut self.pool,
+            &mut self.position,
+            &mut self.tick_array_lower,
+            self.tick_array_upper.as_deref_mut().map(|array| &mut **array),
+            liquidity_delta,
+        )
+    }
+
+    /// Pays `amount_a` / `amount_b` out of the vaults to the owner and takes them
+    /// off the reserves.
+    fn withdraw(
+        &mut self,
+        amount_a: u64,
+        amount_b: u64,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Result<()> {
+        let pool = &mut self.pool;
+        let pool_index = pool.pool_index.to_le_bytes();
+        let authority_seeds = &[
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool_index.as_ref(),
+            &[pool.authority_bump],
+        ];
+        if amount_a > 0 {
+            transfer_tokens(
+                &self.token_a_program,
+                &self.token_a_vault,
+                &self.user_token_a,
+                &self.token_a_mint,
+                self.pool_authority.to_account_info(),
+                &[authority_seeds],
+                remaining_accounts,
+                amount_a,
+            )?;
+        }
+        if amount_b > 0 {
+            transfer_tokens(
+                &self.token_b_program,
+                &self.token_b_vault,
+                &self.user_token_b,
+                &self.token_b_mint,
+                self.pool_authority.to_account_info(),
+                &[authority_seeds],
+                remaining_accounts,
+                amount_b,
+            )?;
+        }
+
+        pool.reserve_a = pool
+            .reserve_a
+            .checked_sub(amount_a)
+            .ok_or(DexError::MathOverflow)?;
+        pool.reserve_b = pool
+            .reserve_b
+            .checked_sub(amount_b)
+            .ok_or(DexError::MathOverflow)?;
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct SwapConcentrated<'info> {
+    #[account(seeds = [b"config"], bump = config.bump)]
+    pub config: Account<'info, Config>,
+    #[account(mut, has_one = token_a_mint, has_one = token_b_mint)]
+    pub pool: Account<'info, Pool>,
+    /// CHECK: PDA authority controlled by program
+    #[account(
+        seeds = [
+            b"pool_authority",
+            pool.token_a_mint.as_ref(),
+            pool.token_b_mint.as_ref(),
+            pool.pool_index.to_le_bytes().as_ref()
+        ],
+        bump = pool.authority_bump
+    )]
+    pub pool_authority: UncheckedAccount<'info>,
+    #[account(mint::token_program = token_a_program)]
+    pub token_a_mint: InterfaceAccount<'info, Mint>,
+    #[account(mint::token_program = token_b_program)]
+    pub token_b_mint: InterfaceAccount<'info, Mint>,
+    #[account(mut, address = pool.token_a_vault)]
+    pub token_a_vault: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, address = pool.token_b_vault)]
+    pub token_b_vault: InterfaceAccount<'info, TokenAccount>,
+    /// Tick array holding the pool's current tick
+    #[account(mut, has_one = pool @ DexError::InvalidTickArray)]
+    pub tick_array_0: Box<Account<'info, TickArray>>,
+    /// Next tick array in the swap direction, if the swap may reach it
+    #[account(mut, has_one = pool @ DexError::InvalidTickArray)]
+    pub tick_array_1: Option<Box<Account<'info, TickArray>>>,
+    /// Tick array after `tick_array_1`, if the swap may reach it
+    #[account(mut, has_one = pool @ DexError::InvalidTickArray)]
+    pub tick_array_2: Option<Box<Account<'info, TickArray>>>,
+    #[account(mut, constraint = user_token_a.mint == pool.token_a_mint)]
+    pub user_token_a: InterfaceAccount<'info, TokenAccount>,
+    #[account(mut, constraint = user_token_b.mint == pool.token_b_mint)]
+    pub user_token_b: InterfaceAccount<'info, TokenAccount>,
     pub user: Signer<'info>,
-    pub token_program: Program<'info, Token>,
+    pub token_a_program: Interface<'info, TokenInterface>,
+    pub token_b_program: Interface<'info, TokenInterface>,
+    /// Oracle ring buffer, written when passed
+    #[account(
+        mut,
+        seeds = [b"observations", pool.key().as_ref()],
+        bump = observations.bump
+    )]
+    pub observations: Option<Account<'info, Observations>>,
 }
@@ -0,0 +1,11 @@
+pub use u256::U256;
+
+// kept out of scope of the prelude, whose `Result` alias breaks the macro expansion
+#[allow(clippy::assign_op_pattern, clippy::manual_div_ceil)]
+mod u256 {
+    uint::construct_uint! {
+        /// 256-bit unsigned integer for curve intermediates that overflow u128
+        /// (StableSwap D^3, weighted powers, tick math, fee growth products)
+        pub struct U256(4);
+    }
+}
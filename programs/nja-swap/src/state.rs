@@ -1,12 +1,23 @@
 use anchor_lang::prelude::*;
 
+use crate::clmm;
+use crate::errors::DexError;
+use crate::math::U256;
+use crate::stable;
+use crate::utils::{
+    calculate_protocol_fee, calculate_swap_amount, calculate_swap_amount_in, spot_price_q64, sqrt,
+};
+use crate::weighted;
+
 /// Liquidity Pool Account
 ///
-/// This struct represents a constant product AMM pool (x * y = k).
+/// This struct represents an AMM pool priced by its `curve_type`: constant product
+/// (x * y = k), StableSwap for pegged assets, a Balancer-style weighted product, or
+/// concentrated liquidity (Uniswap v3 style ranges, see `clmm.rs`).
 /// The pool maintains reserves of two different SPL tokens and allows users to:
 /// - Add liquidity (receive LP tokens)
 /// - Remove liquidity (burn LP tokens)
-/// - Swap tokens using the constant product formula
+/// - Swap tokens along the pool's curve
 ///
 /// # Security Features
 /// - Minimum liquidity requirement prevents price manipulation
@@ -16,7 +27,7 @@ use anchor_lang::prelude::*;
 #[account]
 pub struct Pool {
     /// PDA Account that controls the pool vaults and LP mint
-    /// Derived from [b"pool_authority", token_a_mint, token_b_mint]
+    /// Derived from [b"pool_authority", token_a_mint, token_b_mint, pool_index]
     pub authority: Pubkey,
 
     /// Mint address of Token A (e.g., SOL, USDC)
@@ -49,16 +60,117 @@ pub struct Pool {
     /// Trading fee in basis points (e.g., 30 = 0.3%)
     /// Fee is deducted from input amount during swaps
     /// Denominator is 10,000 (defined in utils.rs)
+    /// During a fee ramp this is the target fee; read the live fee with `fee_bps`
     pub fee_numerator: u64,
 
     /// Bump seed for the authority PDA
     /// Used for signing CPIs from the pool authority
     pub authority_bump: u8,
+
+    /// Index of this pool within its token pair (little-endian in PDA seeds)
+    /// Lets one pair run several pools, e.g. one per fee tier
+    pub pool_index: u16,
+
+    /// Protocol's share of Token A swap fees, held in the vault until collected
+    /// Not part of reserve_a, so LPs never withdraw it
+    pub protocol_fees_a: u64,
+
+    /// Protocol's share of Token B swap fees, held in the vault until collected
+    /// Not part of reserve_b, so LPs never withdraw it
+    pub protocol_fees_b: u64,
+
+    /// sqrt(k) (see `Pool::root_k`) as of the last liquidity event
+    /// Only tracked in `ProtocolFeeMode::LpMint`, 0 otherwise
+    pub k_last: u128,
+
+    /// Set by the admin or guardian to halt swaps and deposits on this pool
+    /// Withdrawals stay open so LPs can always exit
+    pub paused: bool,
+
+    /// Sum of (reserve_b / reserve_a) in Q64.64 times the seconds it held
+    /// (each reserve divided by its weight for weighted pools, the invariant's
+    /// marginal price for stable pools)
+    /// Wraps on overflow; consumers difference two observations (see `compute_twap`)
+    pub price_a_cumulative: u128,
+
+    /// Sum of (reserve_a / reserve_b) in Q64.64 times the seconds it held
+    /// (each reserve divided by its weight for weighted pools, the invariant's
+    /// marginal price for stable pools)
+    /// Wraps on overflow; consumers difference two observations (see `compute_twap`)
+    pub price_b_cumulative: u128,
+
+    /// Unix timestamp of the last accumulator update
+    pub last_update_timestamp: i64,
+
+    /// Sum of sqrt(reserve_a * reserve_b) (in-range liquidity for concentrated pools)
+    /// times the seconds it held
+    /// Wraps on overflow, like the price accumulators
+    pub liquidity_cumulative: u128,
+
+    /// Pricing curve, fixed at initialization
+    pub curve_type: CurveType,
+
+    /// StableSwap amplification coefficient `A` (see `stable.rs`)
+    /// 0 for constant product pools
+    pub amp: u64,
+
+    /// Fee in basis points when the current fee ramp started
+    pub ramp_start_fee: u64,
+
+    /// Unix timestamp the current fee ramp started
+    pub ramp_start_time: i64,
+
+    /// Unix timestamp the fee reaches `fee_numerator`; no ramp is active once passed
+    pub ramp_end_time: i64,
+
+    /// Token A weight in basis points for weighted pools (e.g. 8,000 = 80%)
+    /// 0 for other curves
+    pub weight_a: u64,
+
+    /// Token B weight in basis points; weight_a + weight_b = 10,000 for weighted pools
+    /// 0 for other curves
+    pub weight_b: u64,
+
+    /// sqrt(price of A in B) in Q64.64, for concentrated pools; 0 for other curves
+    pub sqrt_price: u128,
+
+    /// Greatest tick at or below `sqrt_price`, for concentrated pools
+    pub tick_current: i32,
+
+    /// Ticks positions may use are multiples of this, for concentrated pools
+    pub tick_spacing: u16,
+
+    /// Liquidity of the positions in range at `tick_current`, for concentrated pools
+    pub liquidity: u128,
+
+    /// Token A fees earned per unit of liquidity over the pool's life, in Q64.64
+    /// Wraps on overflow; only differences are meaningful
+    pub fee_growth_global_a: u128,
+
+    /// Token B fees earned per unit of liquidity over the pool's life, in Q64.64
+    /// Wraps on overflow; only differences are meaningful
+    pub fee_growth_global_b: u128,
+
+    /// Reentrancy lock, set by `flash_loan` and cleared by `flash_repay`
+    /// Swaps and liquidity changes fail while a loan is outstanding
+    pub flash_loan_active: bool,
+
+    /// Token A lent by the outstanding flash loan
+    pub flash_loan_amount_a: u64,
+
+    /// Token B lent by the outstanding flash loan
+    pub flash_loan_amount_b: u64,
+
+    /// Fee owed on `flash_loan_amount_a`, added to reserve_a on repayment
+    pub flash_loan_fee_a: u64,
+
+    /// Fee owed on `flash_loan_amount_b`, added to reserve_b on repayment
+    pub flash_loan_fee_b: u64,
 }
 
 impl Pool {
     /// Pool Account size in bytes
-    /// Total: 233 bytes
+    /// Total: 468 bytes
     pub const LEN: usize = 8 + // discriminator
         32 + // authority
         32 + // token_a_mint
@@ -69,5 +181,753 @@ impl Pool {
         8 +  // reserve_a
         8 +  // reserve_b
         8 +  // fee_numerator
-        1;   // authority_bump
+        1 +  // authority_bump
+        2 +  // pool_index
+        8 +  // protocol_fees_a
+        8 +  // protocol_fees_b
+        16 + // k_last
+        1 +  // paused
+        16 + // price_a_cumulative
+        16 + // price_b_cumulative
+        8 +  // last_update_timestamp
+        16 + // liquidity_cumulative
+        1 +  // curve_type
+        8 +  // amp
+        8 +  // ramp_start_fee
+        8 +  // ramp_start_time
+        8 +  // ramp_end_time
+        8 +  // weight_a
+        8 +  // weight_b
+        16 + // sqrt_price
+        4 +  // tick_current
+        2 +  // tick_spacing
+        16 + // liquidity
+        16 + // fee_growth_global_a
+        16 + // fee_growth_global_b
+        1 +  // flash_loan_active
+        8 +  // flash_loan_amount_a
+        8 +  // flash_loan_amount_b
+        8 +  // flash_loan_fee_a
+        8;   // flash_loan_fee_b
+
+    /// Swap fee in effect at `now`
+    ///
+    /// Linearly interpolated from `ramp_start_fee` to `fee_numerator` while a ramp
+    /// is active, `fee_numerator` otherwise.
+    pub fn fee_bps(&self, now: i64) -> u64 {
+        if now >= self.ramp_end_time {
+            return self.fee_numerator;
+        }
+        let elapsed = now.saturating_sub(self.ramp_start_time).max(0) as u128;
+        let duration = (self.ramp_end_time - self.ramp_start_time) as u128;
+        let (start, target) = (self.ramp_start_fee as u128, self.fee_numerator as u128);
+        // both fees are below FEE_DEN, so neither product can overflow
+        let fee = if target >= start {
+            start + (target - start) * elapsed / duration
+        } else {
+            start - (start - target) * elapsed / duration
+        };
+        fee as u64
+    }
+
+    /// Square root of the invariant k, which measures the pool's liquidity
+    ///
+    /// sqrt(reserve_a * reserve_b) for constant product pools, D for stable pools and
+    /// V = reserve_a^weight_a * reserve_b^weight_b for weighted pools, so `k_last` and
+    /// `calculate_protocol_lp_fee` work for every curve. Kept as the root, since D
+    /// can exceed 2^64 and its square would not fit in u128.
+    pub fn root_k(&self) -> Result<u128> {
+        match self.curve_type {
+            CurveType::ConstantProduct => Ok(sqrt(self.reserve_a as u128 * self.reserve_b as u128)),
+            CurveType::Stable => {
+                stable::get_d(self.amp, self.reserve_a as u128, self.reserve_b as u128)
+            }
+            CurveType::Weighted => {
+                if self.reserve_a == 0 || self.reserve_b == 0 {
+                    return Ok(0);
+                }
+                let v = weighted::geometric_mean(
+                    self.reserve_a,
+                    self.weight_a,
+                    self.reserve_b,
+                    self.weight_b,
+                )?;
+                Ok(v as u128)
+            }
+            CurveType::Concentrated => err!(DexError::WrongCurveType),
+        }
+    }
+
+    /// `(reserve_in, weight_in, reserve_out, weight_out)` for a swap direction
+    fn sides(&self, is_a_to_b: bool) -> (u64, u64, u64, u64) {
+        if is_a_to_b {
+            (self.reserve_a, self.weight_a, self.reserve_b, self.weight_b)
+        } else {
+            (self.reserve_b, self.weight_b, self.reserve_a, self.weight_a)
+        }
+    }
+
+    /// Output of swapping `amount_in` (as received by the vault) at `fee_bps`, along the pool's curve
+    pub fn quote_swap(&self, amount_in: u64, is_a_to_b: bool, fee_bps: u64) -> Result<u64> {
+        let (reserve_in, weight_in, reserve_out, weight_out) = self.sides(is_a_to_b);
+        match self.curve_type {
+            CurveType::ConstantProduct => {
+                calculate_swap_amount(amount_in, reserve_in, reserve_out, fee_bps)
+            }
+            CurveType::Stable => {
+                stable::calculate_swap_amount(amount_in, reserve_in, reserve_out, fee_bps, self.amp)
+            }
+            CurveType::Weighted => weighted::calculate_swap_amount(
+                amount_in,
+                reserve_in,
+                weight_in,
+                reserve_out,
+                weight_out,
+                fee_bps,
+            ),
+            CurveType::Concentrated => err!(DexError::WrongCurveType),
+        }
+    }
+
+    /// Input the vault must receive to pay out exactly `amount_out` at `fee_bps`, along the pool's curve
+    pub fn quote_swap_in(&self, amount_out: u64, is_a_to_b: bool, fee_bps: u64) -> Result<u64> {
+        let (reserve_in, weight_in, reserve_out, weight_out) = self.sides(is_a_to_b);
+        match self.curve_type {
+            CurveType::ConstantProduct => {
+                calculate_swap_amount_in(amount_out, reserve_in, reserve_out, fee_bps)
+            }
+            CurveType::Stable => stable::calculate_swap_amount_in(
+                amount_out,
+                reserve_in,
+                reserve_out,
+                fee_bps,
+                self.amp,
+            ),
+            CurveType::Weighted => weighted::calculate_swap_amount_in(
+                amount_out,
+                reserve_in,
+                weight_in,
+                reserve_out,
+                weight_out,
+                fee_bps,
+            ),
+            CurveType::Concentrated => err!(DexError::WrongCurveType),
+        }
+    }
+
+    /// Accumulator values as they would read at `now` if the reserves stay unchanged
+    ///
+    /// `now` must not be earlier than `last_update_timestamp`.
+    pub fn observation_at(&self, now: i64) -> Observation {
+        let mut observation = Observation {
+            timestamp: now,
+            price_a_cumulative: self.price_a_cumulative,
+            price_b_cumulative: self.price_b_cumulative,
+            liquidity_cumulative: self.liquidity_cumulative,
+            initialized: true,
+        };
+        let elapsed = now.saturating_sub(self.last_update_timestamp);
+        let concentrated = self.curve_type == CurveType::Concentrated;
+        let priced = if concentrated {
+            self.sqrt_price > 0
+        } else {
+            self.reserve_a > 0 && self.reserve_b > 0
+        };
+        if elapsed > 0 && priced {
+            let elapsed = elapsed as u128;
+            let (price_a, price_b) = match self.curve_type {
+                CurveType::Weighted => (
+                    weighted::spot_price_q64(self.reserve_b, self.weight_b, self.reserve_a, self.weight_a),
+                    weighted::spot_price_q64(self.reserve_a, self.weight_a, self.reserve_b, self.weight_b),
+                ),
+                CurveType::Stable => (
+                    stable::spot_price_q64(self.amp, self.reserve_a, self.reserve_b),
+                    stable::spot_price_q64(self.amp, self.reserve_b, self.reserve_a),
+                ),
+                CurveType::Concentrated => clmm::spot_prices_q64(self.sqrt_price),
+                CurveType::ConstantProduct => (
+                    spot_price_q64(self.reserve_b, self.reserve_a),
+                    spot_price_q64(self.reserve_a, self.reserve_b),
+                ),
+            };
+            // in-range liquidity is already sqrt(x * y) of the virtual reserves
+            let liquidity = if concentrated {
+                self.liquidity
+            } else {
+                sqrt(self.reserve_a as u128 * self.reserve_b as u128)
+            };
+            observation.price_a_cumulative = observation
+                .price_a_cumulative
+                .wrapping_add(price_a.wrapping_mul(elapsed));
+            observation.price_b_cumulative = observation
+                .price_b_cumulative
+                .wrapping_add(price_b.wrapping_mul(elapsed));
+            observation.liquidity_cumulative = observation
+                .liquidity_cumulative
+                .wrapping_add(liquidity.wrapping_mul(elapsed));
+        }
+        observation
+    }
+
+    /// Books a swap priced on the reserves
+    ///
+    /// `amount_in` (as received by the vault) joins the input reserve less `protocol_fee`,
+    /// which stays in the vault on the input side's protocol fee counter, and
+    /// `amount_out` leaves the output reserve.
+    pub fn book_swap(
+        &mut self,
+        amount_in: u64,
+        amount_out: u64,
+        protocol_fee: u64,
+        is_a_to_b: bool,
+    ) -> Result<()> {
+        let amount_in_to_reserve = amount_in
+            .checked_sub(protocol_fee)
+            .ok_or(DexError::MathOverflow)?;
+        let (reserve_in, reserve_out, protocol_fees_in) = if is_a_to_b {
+            (&mut self.reserve_a, &mut self.reserve_b, &mut self.protocol_fees_a)
+        } else {
+            (&mut self.reserve_b, &mut self.reserve_a, &mut self.protocol_fees_b)
+        };
+        *reserve_in = reserve_in
+            .checked_add(amount_in_to_reserve)
+            .ok_or(DexError::MathOverflow)?;
+        *reserve_out = reserve_out
+            .checked_sub(amount_out)
+            .ok_or(DexError::MathOverflow)?;
+        *protocol_fees_in = protocol_fees_in
+            .checked_add(protocol_fee)
+            .ok_or(DexError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Adds the current spot prices and liquidity, weighted by the seconds since
+    /// the last update, to the TWAP accumulators.
+    ///
+    /// Call before reserves change, so each price is weighted by how long it held.
+    pub fn update_price_accumulators(&mut self, now: i64) {
+        let observation = self.observation_at(now);
+        self.price_a_cumulative = observation.price_a_cumulative;
+        self.price_b_cumulative = observation.price_b_cumulative;
+        self.liquidity_cumulative = observation.liquidity_cumulative;
+        self.last_update_timestamp = now;
+    }
+}
+
+/// Snapshot of a pool's accumulators at one point in time
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Observation {
+    /// Unix timestamp the snapshot was taken at
+    pub timestamp: i64,
+
+    /// `Pool.price_a_cumulative` at `timestamp`
+    pub price_a_cumulative: u128,
+
+    /// `Pool.price_b_cumulative` at `timestamp`
+    pub price_b_cumulative: u128,
+
+    /// `Pool.liquidity_cumulative` at `timestamp`
+    pub liquidity_cumulative: u128,
+
+    /// Whether this slot has been written yet
+    pub initialized: bool,
+}
+
+impl Observation {
+    /// Serialized Observation size in bytes
+    /// Total: 57 bytes
+    pub const LEN: usize = 8 + // timestamp
+        16 + // price_a_cumulative
+        16 + // price_b_cumulative
+        16 + // liquidity_cumulative
+        1;   // initialized
+
+    /// Linear interpolation between `self` and a later observation at `target`
+    ///
+    /// Exact as long as the reserves did not change between the two snapshots.
+    fn interpolate(&self, after: &Observation, target: i64) -> Observation {
+        let span = (after.timestamp - self.timestamp) as u128;
+        let offset = (target - self.timestamp) as u128;
+        let lerp = |before: u128, after: u128| {
+            before.wrapping_add(after.wrapping_sub(before) / span * offset)
+        };
+        Observation {
+            timestamp: target,
+            price_a_cumulative: lerp(self.price_a_cumulative, after.price_a_cumulative),
+            price_b_cumulative: lerp(self.price_b_cumulative, after.price_b_cumulative),
+            liquidity_cumulative: lerp(self.liquidity_cumulative, after.liquidity_cumulative),
+            initialized: true,
+        }
+    }
+}
+
+/// Oracle Observations Account
+///
+/// Optional per-pool PDA derived from [b"observations", pool] holding a ring
+/// buffer of accumulator snapshots, written by `swap` (at most one per second).
+/// Consumers read it directly and call `observe` to get TWAPs over past windows
+/// without snapshotting `Pool` themselves.
+///
+/// The ring starts with one slot; `increase_observation_cardinality` allocates more.
+/// New slots join the ring once the write index wraps around to them.
+#[account]
+pub struct Observations {
+    /// Pool these observations belong to
+    pub pool: Pubkey,
+
+    /// Bump seed for the observations PDA
+    pub bump: u8,
+
+    /// Slot holding the most recent observation
+    pub index: u16,
+
+    /// Number of slots currently in the ring
+    pub cardinality: u16,
+
+    /// Allocated slots; `observations.len()` always equals `cardinality_next`
+    pub cardinality_next: u16,
+
+    /// The ring buffer
+    pub observations: Vec<Observation>,
+}
+
+impl Observations {
+    /// Upper bound on `cardinality_next`, keeping the account small enough to
+    /// deserialize on the 32 KiB heap
+    pub const MAX_CARDINALITY: u16 = 256;
+
+    /// Observations Account size in bytes for `cardinality` slots
+    pub fn space(cardinality: u16) -> usize {
+        8 + // discriminator
+        32 + // pool
+        1 +  // bump
+        2 +  // index
+        2 +  // cardinality
+        2 +  // cardinality_next
+        4 + cardinality as usize * Observation::LEN // observations
+    }
+
+    /// Records the pool's accumulators at `now`
+    ///
+    /// Call after `Pool::update_price_accumulators`. Writes at most once per second.
+    pub fn write(&mut self, pool: &Pool, now: i64) {
+        let last = self.observations[self.index as usize];
+        if last.timestamp == now {
+            return;
+        }
+        // grow the ring once the write index reaches its end
+        if self.cardinality_next > self.cardinality && self.index == self.cardinality - 1 {
+            self.cardinality = self.cardinality_next;
+        }
+        self.index = (self.index + 1) % self.cardinality;
+        self.observations[self.index as usize] = pool.observation_at(now);
+    }
+
+    /// Accumulator values `seconds_ago` seconds before `now`, one per entry
+    ///
+    /// Targets newer than the pool's last update are extrapolated from the pool
+    /// itself; older ones are interpolated between the surrounding snapshots.
+    /// Interpolation assumes the reserves held between snapshots, so it is exact only
+    /// if every reserve change wrote one: swaps, liquidity and position changes, `sync`
+    /// and `flash_repay` do when passed this account, and are approximated otherwise.
+    /// A TWAP over the last N seconds is
+    /// `compute_twap(result[0].price_a_cumulative, result[1].price_a_cumulative, result[0].timestamp, result[1].timestamp)`
+    /// with `seconds_ago = [N, 0]`.
+    ///
+    /// # Errors
+    /// - `ObservationTooOld`: If a target predates the oldest stored observation
+    pub fn observe(&self, pool: &Pool, now: i64, seconds_ago: &[u32]) -> Result<Vec<Observation>> {
+        seconds_ago
+            .iter()
+            .map(|&ago| self.observe_single(pool, now - ago as i64))
+            .collect()
+    }
+
+    fn observe_single(&self, pool: &Pool, target: i64) -> Result<Observation> {
+        if target >= pool.last_update_timestamp {
+            return Ok(pool.observation_at(target));
+        }
+
+        // the pool's own state is the newest point, past any written slot
+        let newest = pool.observation_at(pool.last_update_timestamp);
+        let latest = self.observations[self.index as usize];
+        if target >= latest.timestamp {
+            return Ok(latest.interpolate(&newest, target));
+        }
+
+        // oldest slot is the one after `index`, unless the ring has not filled yet
+        let cardinality = self.cardinality as usize;
+        let mut oldest = (self.index as usize + 1) % cardinality;
+        if !self.observations[oldest].initialized {
+            oldest = 0;
+        }
+        require!(
+            target >= self.observations[oldest].timestamp,
+            DexError::ObservationTooOld
+        );
+
+        // binary search the ring in chronological order for the last slot at or before target
+        let len = (self.index as usize + cardinality - oldest) % cardinality + 1;
+        let at = |i: usize| &self.observations[(oldest + i) % cardinality];
+        let (mut lo, mut hi) = (0, len - 1);
+        while lo < hi {
+            let mid = (lo + hi).div_ceil(2);
+            if at(mid).timestamp <= target {
+                lo = mid;
+            } else {
+                hi = mid - 1;
+            }
+        }
+        let before = at(lo);
+        if before.timestamp == target {
+            return Ok(*before);
+        }
+        Ok(before.interpolate(at(lo + 1), target))
+    }
+}
+
+/// How the protocol takes its share of swap fees
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ProtocolFeeMode {
+    /// Split the share off every swap into `Pool.protocol_fees_a/b`
+    PerSwap,
+    /// Uniswap v2 style: on add/remove liquidity, mint the share of sqrt(k)
+    /// growth over `Pool.k_last` as LP tokens to the fee recipient.
+    /// Swaps pay no extra compute.
+    LpMint,
+}
+
+/// Invariant a pool prices swaps and deposits with
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum CurveType {
+    /// x * y = k, for uncorrelated pairs
+    ConstantProduct,
+    /// Curve StableSwap with amplification `Pool.amp`, for pegged pairs (e.g. USDC/USDT)
+    Stable,
+    /// Balancer weighted product reserve_a^weight_a * reserve_b^weight_b = V,
+    /// for non-50/50 pools (e.g. 80/20)
+    Weighted,
+    /// Uniswap v3 style: LPs provide x * y = L^2 over chosen price ranges (positions),
+    /// priced from `Pool.sqrt_price` with liquidity changing as ticks are crossed
+    Concentrated,
+}
+
+/// Global Protocol Config Account
+///
+/// Singleton PDA derived from [b"config"] holding the protocol-wide settings.
+/// Only the admin can change it, and admin rights move in two steps
+/// (`transfer_admin` then `accept_admin`) so a typo cannot lock the protocol.
+#[account]
+pub struct Config {
+    /// Account allowed to update the config
+    pub admin: Pubkey,
+
+    /// Admin nominated by `transfer_admin`, waiting to call `accept_admin`
+    /// Pubkey::default() when no transfer is in progress
+    pub pending_admin: Pubkey,
+
+    /// Swap fee in basis points used by `initialize_pool` when no fee is given
+    pub default_fee_bps: u64,
+
+    /// Share of every swap fee owed to the protocol, in basis points of the fee
+    /// (e.g. 1,667 = 1/6 of the fee); the rest goes to LPs
+    pub protocol_fee_share_bps: u64,
+
+    /// How `protocol_fee_share_bps` is collected
+    pub protocol_fee_mode: ProtocolFeeMode,
+
+    /// Owner of the treasury token accounts that receive protocol fees
+    pub fee_recipient: Pubkey,
+
+    /// Bump seed for the config PDA
+    pub bump: u8,
+
+    /// Account allowed, besides the admin, to pause and unpause
+    pub guardian: Pubkey,
+
+    /// Halts swaps and deposits on every pool
+    pub paused: bool,
+
+    /// Fee on flash loans in basis points of the amount borrowed; paid to LPs
+    pub flash_loan_fee_bps: u64,
+}
+
+impl Config {
+    /// Whether `key` may pause or unpause pools
+    pub fn is_pause_authority(&self, key: &Pubkey) -> bool {
+        *key == self.admin || *key == self.guardian
+    }
+
+    /// Protocol's cut of the swap fee on `amount_in` in `ProtocolFeeMode::PerSwap`,
+    /// 0 in `LpMint` mode (where it is minted as LP tokens instead)
+    pub fn swap_protocol_fee(&self, amount_in: u64, fee_bps: u64) -> Result<u64> {
+        if self.protocol_fee_mode == ProtocolFeeMode::PerSwap {
+            calculate_protocol_fee(amount_in, fee_bps, self.protocol_fee_share_bps)
+        } else {
+            Ok(0)
+        }
+    }
+
+    /// Config Account size in bytes
+    /// Total: 163 bytes
+    pub const LEN: usize = 8 + // discriminator
+        32 + // admin
+        32 + // pending_admin
+        8 +  // default_fee_bps
+        8 +  // protocol_fee_share_bps
+        1 +  // protocol_fee_mode
+        32 + // fee_recipient
+        1 +  // bump
+        32 + // guardian
+        1 +  // paused
+        8;   // flash_loan_fee_bps
+}
+
+/// Mint Allowlist Entry
+///
+/// PDA derived from [b"allowed_mint", mint], created by the admin with `allow_mint`.
+/// Its presence lets `initialize_pool` accept the mint without screening its
+/// Token-2022 extensions (see `screen_mint_extensions`), e.g. for vetted partner tokens.
+#[account]
+pub struct AllowedMint {
+    /// The allowlisted mint
+    pub mint: Pubkey,
+
+    /// Bump seed for the allowlist PDA
+    pub bump: u8,
+}
+
+impl AllowedMint {
+    /// AllowedMint Account size in bytes
+    /// Total: 41 bytes
+    pub const LEN: usize = 8 + // discriminator
+        32 + // mint
+        1;   // bump
+}
+
+/// Number of ticks in one `TickArray`
+pub const TICK_ARRAY_SIZE: usize = 32;
+
+/// One initializable price boundary of a concentrated pool
+///
+/// Tick `i` sits at price 1.0001^i. Positions reference their lower and upper tick;
+/// swaps crossing a tick add or remove its `liquidity_net` from the pool's liquidity.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default, PartialEq, Eq, Debug)]
+pub struct Tick {
+    /// Whether any position references this tick
+    pub initialized: bool,
+
+    /// Liquidity added to the pool when the price crosses this tick upwards
+    /// (removed when crossing downwards)
+    pub liquidity_net: i128,
+
+    /// Total liquidity of the positions referencing this tick
+    pub liquidity_gross: u128,
+
+    /// Token A fee growth on the other side of this tick from the current price, in Q64.64
+    pub fee_growth_outside_a: u128,
+
+    /// Token B fee growth on the other side of this tick from the current price, in Q64.64
+    pub fee_growth_outside_b: u128,
+}
+
+impl Tick {
+    /// Serialized Tick size in bytes
+    /// Total: 65 bytes
+    pub const LEN: usize = 1 + // initialized
+        16 + // liquidity_net
+        16 + // liquidity_gross
+        16 + // fee_growth_outside_a
+        16;  // fee_growth_outside_b
+
+    /// Adds a position's `liquidity_delta` at `tick`, as its lower or `upper` bound
+    ///
+    /// A tick initialized at or below the current tick assumes all fee growth so far
+    /// happened below it (Uniswap v3 convention); only differences are ever used.
+    pub fn update(
+        &mut self,
+        tick: i32,
+        tick_current: i32,
+        liquidity_delta: i128,
+        fee_growth_global_a: u128,
+        fee_growth_global_b: u128,
+        upper: bool,
+    ) -> Result<()> {
+        if self.liquidity_gross == 0 && liquidity_delta != 0 {
+            if tick <= tick_current {
+                self.fee_growth_outside_a = fee_growth_global_a;
+                self.fee_growth_outside_b = fee_growth_global_b;
+            }
+            self.initialized = true;
+        }
+        self.liquidity_gross = clmm::add_liquidity_delta(self.liquidity_gross, liquidity_delta)?;
+        self.liquidity_net = if upper {
+            self.liquidity_net.checked_sub(liquidity_delta)
+        } else {
+            self.liquidity_net.checked_add(liquidity_delta)
+        }
+        .ok_or(DexError::MathOverflow)?;
+        Ok(())
+    }
+
+    /// Flips the fee growth outside as the price crosses this tick
+    ///
+    /// # Returns
+    /// `liquidity_net`, to apply to the pool's liquidity
+    pub fn cross(&mut self, fee_growth_global_a: u128, fee_growth_global_b: u128) -> i128 {
+        self.fee_growth_outside_a = fee_growth_global_a.wrapping_sub(self.fee_growth_outside_a);
+        self.fee_growth_outside_b = fee_growth_global_b.wrapping_sub(self.fee_growth_outside_b);
+        self.liquidity_net
+    }
+}
+
+/// Tick Array Account
+///
+/// PDA derived from [b"tick_array", pool, start_tick_index] holding `TICK_ARRAY_SIZE`
+/// consecutive initializable ticks, `start_tick_index + i * tick_spacing`.
+/// Anyone can create one with `initialize_tick_array`; positions need the arrays of
+/// both their ticks and swaps pass the arrays they will walk through.
+#[account]
+pub struct TickArray {
+    /// Pool these ticks belong to
+    pub pool: Pubkey,
+
+    /// First tick of the array, a multiple of `TICK_ARRAY_SIZE * tick_spacing`
+    pub start_tick_index: i32,
+
+    /// The ticks; the length is `TICK_ARRAY_SIZE`, spelled out for the IDL
+    pub ticks: [Tick; 32],
+}
+
+impl TickArray {
+    /// TickArray Account size in bytes
+    /// Total: 2124 bytes
+    pub const LEN: usize = 8 + // discriminator
+        32 + // pool
+        4 +  // start_tick_index
+        TICK_ARRAY_SIZE * Tick::LEN; // ticks
+
+    /// Start index of the array holding `tick`
+    pub fn start_index_for(tick: i32, tick_spacing: u16) -> i32 {
+        let span = TICK_ARRAY_SIZE as i32 * tick_spacing as i32;
+        tick.div_euclid(span) * span
+    }
+
+    /// Whether `tick` falls in the price range this array covers
+    pub fn contains(&self, tick: i32, tick_spacing: u16) -> bool {
+        Self::start_index_for(tick, tick_spacing) == self.start_tick_index
+    }
+
+    /// The tick at `tick`, which must be initializable and in this array
+    ///
+    /// # Errors
+    /// - `InvalidTick`: If tick is not a multiple of the tick spacing
+    /// - `InvalidTickArray`: If tick is outside this array
+    pub fn tick_mut(&mut self, tick: i32, tick_spacing: u16) -> Result<&mut Tick> {
+        require!(tick % tick_spacing as i32 == 0, DexError::InvalidTick);
+        require!(self.contains(tick, tick_spacing), DexError::InvalidTickArray);
+        let offset = (tick - self.start_tick_index) / tick_spacing as i32;
+        Ok(&mut self.ticks[offset as usize])
+    }
+
+    /// Nearest initialized tick in this array at or below `tick_current` when `a_to_b`
+    /// (price falling), or above it otherwise
+    pub fn next_initialized_tick(&self, tick_current: i32, tick_spacing: u16, a_to_b: bool) -> Option<i32> {
+        let tick_at = |i: usize| self.start_tick_index + i as i32 * tick_spacing as i32;
+        let indices = (0..TICK_ARRAY_SIZE).filter(|&i| self.ticks[i].initialized);
+        if a_to_b {
+            indices.rev().map(tick_at).find(|&tick| tick <= tick_current)
+        } else {
+            indices.map(tick_at).find(|&tick| tick > tick_current)
+        }
+    }
+}
+
+/// Concentrated Liquidity Position Account
+///
+/// PDA derived from [b"position", pool, owner, tick_lower, tick_upper] holding one
+/// owner's liquidity between two ticks. It earns fees only while the price is
+/// within its range, and holds token A only above the range, token B only below.
+#[account]
+pub struct Position {
+    /// Pool this position belongs to
+    pub pool: Pubkey,
+
+    /// Account allowed to change the position and collect its fees
+    pub owner: Pubkey,
+
+    /// Lower tick of the range (inclusive)
+    pub tick_lower: i32,
+
+    /// Upper tick of the range (exclusive)
+    pub tick_upper: i32,
+
+    /// Liquidity the position provides while in range
+    pub liquidity: u128,
+
+    /// Token A fee growth inside the range as of the last update, in Q64.64
+    pub fee_growth_inside_last_a: u128,
+
+    /// Token B fee growth inside the range as of the last update, in Q64.64
+    pub fee_growth_inside_last_b: u128,
+
+    /// Token A fees earned and not yet collected
+    pub tokens_owed_a: u64,
+
+    /// Token B fees earned and not yet collected
+    pub tokens_owed_b: u64,
+
+    /// Bump seed for the position PDA
+    pub bump: u8,
+}
+
+impl Position {
+    /// Position Account size in bytes
+    /// Total: 145 bytes
+    pub const LEN: usize = 8 + // discriminator
+        32 + // pool
+        32 + // owner
+        4 +  // tick_lower
+        4 +  // tick_upper
+        16 + // liquidity
+        16 + // fee_growth_inside_last_a
+        16 + // fee_growth_inside_last_b
+        8 +  // tokens_owed_a
+        8 +  // tokens_owed_b
+        1;   // bump
+
+    /// Credits the fees earned since the last update, then applies `liquidity_delta`
+    ///
+    /// # Errors
+    /// - `InsufficientLiquidity`: If the delta is zero on an empty position, or removes
+    ///   more than the position holds
+    pub fn update(
+        &mut self,
+        liquidity_delta: i128,
+        fee_growth_inside_a: u128,
+        fee_growth_inside_b: u128,
+    ) -> Result<()> {
+        require!(
+            liquidity_delta != 0 || self.liquidity > 0,
+            DexError::InsufficientLiquidity
+        );
+        require!(
+            liquidity_delta >= 0 || liquidity_delta.unsigned_abs() <= self.liquidity,
+            DexError::InsufficientLiquidity
+        );
+        let owed = |inside: u128, last: u128| {
+            let fees = (U256::from(inside.wrapping_sub(last)) * U256::from(self.liquidity))
+                >> crate::utils::Q64_SHIFT;
+            // fees past u64 cannot be paid out anyway; cap instead of bricking the position
+            fees.min(U256::from(u64::MAX)).as_u64()
+        };
+        let owed_a = owed(fee_growth_inside_a, self.fee_growth_inside_last_a);
+        let owed_b = owed(fee_growth_inside_b, self.fee_growth_inside_last_b);
+        self.tokens_owed_a = self.tokens_owed_a.saturating_add(owed_a);
+        self.tokens_owed_b = self.tokens_owed_b.saturating_add(owed_b);
+        self.fee_growth_inside_last_a = fee_growth_inside_a;
+        self.fee_growth_inside_last_b = fee_growth_inside_b;
+        self.liquidity = clmm::add_liquidity_delta(self.liquidity, liquidity_delta)?;
+        Ok(())
+    }
 }
@@ -1,12 +1,39 @@
 use anchor_lang::prelude::*;
+use anchor_spl::token_2022::spl_token_2022::{
+    self,
+    extension::{
+        default_account_state::DefaultAccountState, transfer_fee::TransferFeeConfig,
+        BaseStateWithExtensions, ExtensionType, StateWithExtensions,
+    },
+    state::AccountState,
+};
+use anchor_spl::token_interface::{Mint, TokenAccount, TokenInterface};
 use crate::errors::DexError;
+use crate::math::U256;
+use crate::weighted::geometric_mean;
 
 /// Fee denominator for calculating fees in basis points
-const FEE_DEN: u128 = 10_000;
+pub const FEE_DEN: u128 = 10_000;
+
+/// Number of fractional bits in Q64.64 fixed-point prices
+pub const Q64_SHIFT: u32 = 64;
 
 /// Minimum liquidity locked forever on first deposit to prevent inflation attacks
 pub const MINIMUM_LIQUIDITY: u64 = 1000;
 
+/// Shortest fee ramp `schedule_fee_ramp` accepts, in seconds (1 day)
+pub const MIN_RAMP_DURATION: i64 = 86_400;
+
+/// Largest factor a fee ramp may raise or lower the fee by
+pub const MAX_FEE_CHANGE: u64 = 10;
+
+/// Most pools a `swap_route` may pass through
+pub const MAX_ROUTE_HOPS: usize = 4;
+
+/// Remaining accounts per `swap_route` hop:
+/// `[pool, pool_authority, vault_in, vault_out, mint_out, token_program_out, observations]`
+pub const ROUTE_HOP_ACCOUNTS: usize = 7;
+
 /// Integer square root using Newton's method
 /// Returns the largest integer x where x * x <= n
 pub fn sqrt(n: u128) -> u128 {
@@ -16,7 +43,7 @@ pub fn sqrt(n: u128) -> u128 {
 
     // Initial guess: start with n/2
     let mut x = n;
-    let mut y = (x + 1) / 2;
+    let mut y = x.div_ceil(2);
 
     // Newton's method iteration
     while y < x {
@@ -27,11 +54,217 @@ pub fn sqrt(n: u128) -> u128 {
     x
 }
 
+/// Spot price `numerator_reserve / denominator_reserve` in Q64.64 fixed point.
+///
+/// Any u64 reserve shifted by 64 bits fits in u128, so this never overflows.
+/// Returns 0 if `denominator_reserve` is zero.
+pub fn spot_price_q64(numerator_reserve: u64, denominator_reserve: u64) -> u128 {
+    if denominator_reserve == 0 {
+        return 0;
+    }
+    ((numerator_reserve as u128) << Q64_SHIFT) / denominator_reserve as u128
+}
+
+/// Computes the time-weighted average price between two observations of a
+/// cumulative price (e.g. `Pool.price_a_cumulative`).
+///
+/// ```text
+/// twap = (cumulative_end - cumulative_start) / (timestamp_end - timestamp_start)
+/// ```
+/// The subtraction wraps, so the result stays correct across accumulator overflow.
+///
+/// # Returns
+/// Average price over the window in Q64.64 fixed point
+///
+/// # Errors
+/// - `InvalidTimeWindow`: If `timestamp_end <= timestamp_start`
+pub fn compute_twap(
+    cumulative_start: u128,
+    cumulative_end: u128,
+    timestamp_start: i64,
+    timestamp_end: i64,
+) -> Result<u128> {
+    require!(timestamp_end > timestamp_start, DexError::InvalidTimeWindow);
+    let elapsed = (timestamp_end - timestamp_start) as u128;
+    Ok(cumulative_end.wrapping_sub(cumulative_start) / elapsed)
+}
+
+/// Rejects the instruction if the cluster clock is past `deadline`.
+///
+/// # Parameters
+/// - `deadline`: Unix timestamp after which the caller no longer wants the trade, or `None`
+///
+/// # Errors
+/// - `DeadlineExceeded`: If `Clock::unix_timestamp > deadline`
+pub fn check_deadline(deadline: Option<i64>) -> Result<()> {
+    if let Some(deadline) = deadline {
+        require!(
+            Clock::get()?.unix_timestamp <= deadline,
+            DexError::DeadlineExceeded
+        );
+    }
+    Ok(())
+}
+
+/// Tokens in a vault that neither the reserve nor the unclaimed protocol fees account for,
+/// e.g. tokens sent to the vault directly.
+///
+/// # Errors
+/// - `VaultBelowReserve`: If the vault holds less than `reserve + protocol_fees`
+pub fn calculate_vault_excess(vault_balance: u64, reserve: u64, protocol_fees: u64) -> Result<u64> {
+    let owed = reserve
+        .checked_add(protocol_fees)
+        .ok_or(DexError::MathOverflow)?;
+    Ok(vault_balance
+        .checked_sub(owed)
+        .ok_or(DexError::VaultBelowReserve)?)
+}
+
+/// Fee withheld by a Token-2022 transfer-fee mint when `amount` is sent.
+///
+/// Returns 0 for legacy SPL mints and Token-2022 mints without the extension.
+/// Uses the fee schedule of the current epoch.
+pub fn calculate_transfer_fee(mint: &InterfaceAccount<Mint>, amount: u64) -> Result<u64> {
+    let mint_info = mint.to_account_info();
+    if *mint_info.owner != spl_token_2022::ID {
+        return Ok(0);
+    }
+    let mint_data = mint_info.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    let Ok(fee_config) = mint_state.get_extension::<TransferFeeConfig>() else {
+        return Ok(0);
+    };
+    Ok(fee_config
+        .calculate_epoch_fee(Clock::get()?.epoch, amount)
+        .ok_or(DexError::MathOverflow)?)
+}
+
+/// Fee to add on top of `post_fee_amount` so that exactly `post_fee_amount` arrives.
+///
+/// The inverse of `calculate_transfer_fee`; 0 for mints without the extension.
+pub fn calculate_transfer_inverse_fee(
+    mint: &InterfaceAccount<Mint>,
+    post_fee_amount: u64,
+) -> Result<u64> {
+    let mint_info = mint.to_account_info();
+    if *mint_info.owner != spl_token_2022::ID {
+        return Ok(0);
+    }
+    let mint_data = mint_info.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    let Ok(fee_config) = mint_state.get_extension::<TransferFeeConfig>() else {
+        return Ok(0);
+    };
+    Ok(fee_config
+        .calculate_inverse_epoch_fee(Clock::get()?.epoch, post_fee_amount)
+        .ok_or(DexError::MathOverflow)?)
+}
+
+/// Amount that arrives when `amount` is sent, after the mint's transfer fee.
+pub fn amount_after_transfer_fee(mint: &InterfaceAccount<Mint>, amount: u64) -> Result<u64> {
+    Ok(amount
+        .checked_sub(calculate_transfer_fee(mint, amount)?)
+        .ok_or(DexError::MathOverflow)?)
+}
+
+/// Rejects Token-2022 mints whose extensions can drain or brick a pool.
+///
+/// Refused extensions:
+/// - Permanent delegate: can transfer or burn vault balances at will
+/// - Non-transferable: tokens can never leave the vaults
+/// - Transfer hook: runs arbitrary code on every transfer and can block it
+/// - Default account state (frozen): new vaults start frozen
+/// - Confidential transfers: balances can move outside the visible amount
+///
+/// Legacy SPL mints have no extensions and always pass.
+/// `initialize_pool` skips this check for mints on the admin allowlist (`AllowedMint`).
+pub fn screen_mint_extensions(mint: &InterfaceAccount<Mint>) -> Result<()> {
+    let mint_info = mint.to_account_info();
+    if *mint_info.owner != spl_token_2022::ID {
+        return Ok(());
+    }
+    let mint_data = mint_info.try_borrow_data()?;
+    let mint_state = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&mint_data)?;
+    for extension in mint_state.get_extension_types()? {
+        match extension {
+            ExtensionType::PermanentDelegate => {
+                return err!(DexError::PermanentDelegateNotAllowed)
+            }
+            ExtensionType::NonTransferable => return err!(DexError::NonTransferableNotAllowed),
+            ExtensionType::TransferHook => return err!(DexError::TransferHookNotAllowed),
+            ExtensionType::DefaultAccountState => {
+                let default_state = mint_state.get_extension::<DefaultAccountState>()?;
+                require!(
+                    default_state.state != AccountState::Frozen as u8,
+                    DexError::DefaultFrozenNotAllowed
+                );
+            }
+            ExtensionType::ConfidentialTransferMint | ExtensionType::ConfidentialMintBurn => {
+                return err!(DexError::ConfidentialTransferNotAllowed)
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// `transfer_checked` CPI that works for both SPL Token and Token-2022 mints.
+///
+/// Pass empty `signer_seeds` when `authority` signs the transaction itself,
+/// or the pool authority seeds for transfers out of the vaults.
+/// For mints with a transfer hook, `hook_accounts` must hold the hook program,
+/// its extra-account-metas PDA and every extra account it lists; the instruction's
+/// `remaining_accounts` are forwarded as is, and mints without a hook ignore them.
+#[allow(clippy::too_many_arguments)]
+pub fn transfer_tokens<'info>(
+    token_program: &Interface<'info, TokenInterface>,
+    from: &InterfaceAccount<'info, TokenAccount>,
+    to: &InterfaceAccount<'info, TokenAccount>,
+    mint: &InterfaceAccount<'info, Mint>,
+    authority: AccountInfo<'info>,
+    signer_seeds: &[&[&[u8]]],
+    hook_accounts: &[AccountInfo<'info>],
+    amount: u64,
+) -> Result<()> {
+    spl_token_2022::onchain::invoke_transfer_checked(
+        token_program.key,
+        from.to_account_info(),
+        mint.to_account_info(),
+        to.to_account_info(),
+        authority,
+        hook_accounts,
+        amount,
+        mint.decimals,
+        signer_seeds,
+    )?;
+    Ok(())
+}
+
+/// Sorts a token pair into canonical pool order (`token_a_mint < token_b_mint`).
+///
+/// Pools only exist for the sorted pair, so callers holding `(mint_x, mint_y)` in
+/// any order can derive the pool PDAs from the sorted mints and use the `flipped`
+/// flag to map their amounts and swap direction onto the pool's A/B sides.
+///
+/// # Returns
+/// `(token_a_mint, token_b_mint, flipped)` where `flipped` is true if the caller
+/// passed the pair as (B, A)
+pub fn sort_mints(mint_x: Pubkey, mint_y: Pubkey) -> (Pubkey, Pubkey, bool) {
+    if mint_x <= mint_y {
+        (mint_x, mint_y, false)
+    } else {
+        (mint_y, mint_x, true)
+    }
+}
+
 /// Calculates the amount of LP tokens to mint when liquidity is added.
 ///
 /// # First Liquidity Addition (lp_supply == 0)
-/// - LP tokens = sqrt(amount_a * amount_b) - MINIMUM_LIQUIDITY
+/// - LP tokens = amount_a^weight_a * amount_b^weight_b - MINIMUM_LIQUIDITY
+///   (the weighted product invariant; equal weights, including the 0/0 of
+///   constant product pools, take the exact sqrt(amount_a * amount_b))
 /// - The MINIMUM_LIQUIDITY is permanently locked to prevent inflation attacks
+///   (`add_liquidity` mints it to the pool's `locked_lp` account)
 /// - This ensures the first LP cannot manipulate the pool price by withdrawing almost all liquidity
 ///
 /// # Subsequent Liquidity Additions
@@ -45,6 +278,7 @@ pub fn sqrt(n: u128) -> u128 {
 /// - `reserve_a`: Current reserve of Token A in pool
 /// - `reserve_b`: Current reserve of Token B in pool
 /// - `lp_supply`: Current total supply of LP tokens
+/// - `weight_a` / `weight_b`: Token weights in basis points (`Pool.weight_a/b`)
 ///
 /// # Returns
 /// Amount of LP tokens to mint to the liquidity provider
@@ -58,15 +292,21 @@ pub fn calculate_lp_tokens(
     reserve_a: u64,
     reserve_b: u64,
     lp_supply: u64,
+    weight_a: u64,
+    weight_b: u64,
 ) -> Result<u64> {
     if lp_supply == 0 {
-        // First time adding liquidity: sqrt(a * b) - MINIMUM_LIQUIDITY
-        let product = (amount_a as u128)
-            .checked_mul(amount_b as u128)
-            .ok_or(DexError::MathOverflow)?;
+        // First time adding liquidity: invariant(a, b) - MINIMUM_LIQUIDITY
+        let liquidity = if weight_a == weight_b {
+            let product = (amount_a as u128)
+                .checked_mul(amount_b as u128)
+                .ok_or(DexError::MathOverflow)?;
 
-        // Integer square root
-        let liquidity = sqrt(product);
+            // Integer square root
+            sqrt(product)
+        } else {
+            geometric_mean(amount_a, weight_a, amount_b, weight_b)? as u128
+        };
 
         // Ensure liquidity is greater than minimum
         require!(
@@ -95,6 +335,177 @@ pub fn calculate_lp_tokens(
     }
 }
 
+/// Calculates the largest deposit within `max_amount_a` / `max_amount_b` that matches
+/// the pool's reserve ratio (the Uniswap v2 router's `_addLiquidity`).
+///
+/// ```text
+/// amount_b_optimal = max_amount_a * reserve_b / reserve_a
+/// ```
+/// If that fits within `max_amount_b` the deposit is `(max_amount_a, amount_b_optimal)`,
+/// otherwise `(max_amount_b * reserve_a / reserve_b, max_amount_b)`. Both round down,
+/// and a quote past u64::MAX saturates, so it never fits `max_amount_b`.
+/// An empty pool takes both maximums, which set its initial price.
+///
+/// # Returns
+/// `(amount_a, amount_b)` to deposit
+pub fn calculate_deposit_amounts(
+    max_amount_a: u64,
+    max_amount_b: u64,
+    reserve_a: u64,
+    reserve_b: u64,
+) -> Result<(u64, u64)> {
+    if reserve_a == 0 || reserve_b == 0 {
+        return Ok((max_amount_a, max_amount_b));
+    }
+    // max_amount * reserve fits u128, but a skewed ratio can quote past u64::MAX;
+    // saturating sends such a quote to the `max_amount_b` branch.
+    let quote = |amount: u64, reserve_from: u64, reserve_to: u64| {
+        u64::try_from(amount as u128 * reserve_to as u128 / reserve_from as u128)
+            .unwrap_or(u64::MAX)
+    };
+    let amount_b_optimal = quote(max_amount_a, reserve_a, reserve_b);
+    if amount_b_optimal <= max_amount_b {
+        Ok((max_amount_a, amount_b_optimal))
+    } else {
+        Ok((quote(max_amount_b, reserve_b, reserve_a), max_amount_b))
+    }
+}
+
+/// Calculates how much of a single-sided deposit to swap so the rest matches the
+/// pool's ratio after the swap (constant product pools).
+///
+/// Swapping `s` of `amount_in` moves `s` into reserve_in and pays out
+/// `s * (1 - f) * reserve_out / (reserve_in + s * (1 - f))`; requiring
+/// `(amount_in - s) / (reserve_in + s) = amount_out / (reserve_out - amount_out)`
+/// leaves a quadratic in `s` whose positive root, with `f = fee_bps / FEE_DEN`, is
+/// ```text
+/// s = (sqrt(reserve_in^2 * (2 - f)^2 + 4 * (1 - f) * amount_in * reserve_in) - reserve_in * (2 - f)) / (2 * (1 - f))
+/// ```
+/// Computed in 256-bit math with both sides scaled by FEE_DEN, rounding down.
+///
+/// # Errors
+/// - `InsufficientLiquidity`: If reserve_in is zero
+/// - `InvalidFee`: If fee >= 100%
+pub fn calculate_zap_swap_amount(amount_in: u64, reserve_in: u64, fee_bps: u64) -> Result<u64> {
+    require!(reserve_in > 0, DexError::InsufficientLiquidity);
+    require!((fee_bps as u128) < FEE_DEN, DexError::InvalidFee);
+
+    let reserve_in = U256::from(reserve_in);
+    let two_minus_fee = U256::from(2 * FEE_DEN - fee_bps as u128);
+    let one_minus_fee = U256::from(FEE_DEN - fee_bps as u128);
+    // both terms stay below 2^160
+    let discriminant = reserve_in * reserve_in * two_minus_fee * two_minus_fee
+        + U256::from(4) * one_minus_fee * U256::from(FEE_DEN) * U256::from(amount_in) * reserve_in;
+    let swap_amount =
+        (discriminant.integer_sqrt() - reserve_in * two_minus_fee) / (U256::from(2) * one_minus_fee);
+    Ok(swap_amount.as_u64())
+}
+
+/// Calculates the protocol's cut of the swap fee charged on `amount_in`.
+///
+/// ```text
+/// protocol_fee = amount_in * fee_bps * protocol_fee_share_bps / FEE_DEN^2
+/// ```
+/// Rounds down, so LPs keep any dust.
+///
+/// # Parameters
+/// - `amount_in`: Amount of input token being swapped
+/// - `fee_bps`: Trading fee in basis points
+/// - `protocol_fee_share_bps`: Protocol's share of the fee in basis points of the fee
+///
+/// # Errors
+/// - `InvalidFee`: If the fee or the share is above 100%
+/// - `MathOverflow`: If calculations overflow u128
+pub fn calculate_protocol_fee(
+    amount_in: u64,
+    fee_bps: u64,
+    protocol_fee_share_bps: u64,
+) -> Result<u64> {
+    require!(
+        fee_bps as u128 <= FEE_DEN && protocol_fee_share_bps as u128 <= FEE_DEN,
+        DexError::InvalidFee
+    );
+
+    let protocol_fee = (amount_in as u128)
+        .checked_mul(fee_bps as u128)
+        .ok_or(DexError::MathOverflow)?
+        .checked_mul(protocol_fee_share_bps as u128)
+        .ok_or(DexError::MathOverflow)?
+        .checked_div(FEE_DEN * FEE_DEN)
+        .ok_or(DexError::MathOverflow)?;
+
+    Ok(protocol_fee as u64)
+}
+
+/// Calculates the fee owed on a flash loan of `amount`.
+///
+/// ```text
+/// fee = ceil(amount * fee_bps / FEE_DEN)
+/// ```
+/// Rounds up, so any nonzero fee rate charges at least 1 unit.
+///
+/// # Errors
+/// - `InvalidFee`: If the fee is 100% or more
+/// - `MathOverflow`: If calculations overflow u128
+pub fn calculate_flash_loan_fee(amount: u64, fee_bps: u64) -> Result<u64> {
+    require!((fee_bps as u128) < FEE_DEN, DexError::InvalidFee);
+
+    let fee = (amount as u128)
+        .checked_mul(fee_bps as u128)
+        .ok_or(DexError::MathOverflow)?
+        .div_ceil(FEE_DEN);
+
+    u64::try_from(fee).map_err(|_| error!(DexError::MathOverflow))
+}
+
+/// Calculates the LP tokens to mint to the protocol for fee growth since `k_last`
+/// (Uniswap v2 `_mintFee`, generalized to any share).
+///
+/// Swap fees grow the invariant k, so sqrt(k) growth (`Pool::root_k`) is fee income.
+/// Minting `liquidity` new LP tokens hands the protocol `protocol_fee_share_bps`
+/// of that growth:
+/// ```text
+/// liquidity = lp_supply * (root_k - root_k_last) * share /
+///             (root_k * (FEE_DEN - share) + root_k_last * share)
+/// ```
+/// With share = 1/6 this is v2's `supply * (rootK - rootKLast) / (5 * rootK + rootKLast)`.
+///
+/// Both roots are taken as given (`Pool::root_k`), and the products run in 256 bits.
+///
+/// # Returns
+/// LP tokens to mint, 0 if there is no previous k or no growth
+///
+/// # Errors
+/// - `InvalidFee`: If the share is above 100%
+/// - `MathOverflow`: If the result exceeds u64
+pub fn calculate_protocol_lp_fee(
+    root_k: u128,
+    root_k_last: u128,
+    lp_supply: u64,
+    protocol_fee_share_bps: u64,
+) -> Result<u64> {
+    require!(
+        protocol_fee_share_bps as u128 <= FEE_DEN,
+        DexError::InvalidFee
+    );
+    if root_k_last == 0 || lp_supply == 0 || protocol_fee_share_bps == 0 {
+        return Ok(0);
+    }
+    if root_k <= root_k_last {
+        return Ok(0);
+    }
+
+    // lp_supply * growth * share reaches 2^206 and the denominator 2^142
+    let share = U256::from(protocol_fee_share_bps);
+    let numerator = U256::from(lp_supply) * U256::from(root_k - root_k_last) * share;
+    let denominator = U256::from(root_k) * (U256::from(FEE_DEN) - share)
+        + U256::from(root_k_last) * share;
+
+    let liquidity = numerator / denominator;
+    require!(liquidity <= U256::from(u64::MAX), DexError::MathOverflow);
+    Ok(liquidity.as_u64())
+}
+
 /// Calculate the output of a swap using constant product formula with fees.
 ///
 /// # Constant Product Formula (x * y = k)
@@ -102,7 +513,7 @@ pub fn calculate_lp_tokens(
 /// After a swap: (reserve_in + amount_in) * (reserve_out - amount_out) = k
 ///
 /// # Formula with Fees
-/// ```
+/// ```text
 /// amount_out = (reserve_out * amount_in * (FEE_DEN - fee_bps)) /
 ///              (reserve_in * FEE_DEN + amount_in * (FEE_DEN - fee_bps))
 /// ```
@@ -167,3 +578,58 @@ pub fn calculate_swap_amount(
 
     Ok(amount_out as u64)
 }
+
+/// Calculate the input required to receive exactly `amount_out` from a swap.
+///
+/// Inverse of `calculate_swap_amount`, solved for the input:
+/// ```text
+/// amount_in = ceil(reserve_in * amount_out * FEE_DEN /
+///                  ((reserve_out - amount_out) * (FEE_DEN - fee_bps)))
+/// ```
+/// Rounding up guarantees `calculate_swap_amount(amount_in, ..) >= amount_out`,
+/// so the constant product never decreases.
+///
+/// # Parameters
+/// - `amount_out`: Exact amount of output token the user wants
+/// - `reserve_in`: Current reserve of input token
+/// - `reserve_out`: Current reserve of output token
+/// - `fee_bps`: Trading fee in basis points
+///
+/// # Returns
+/// Amount of input token the user must pay
+///
+/// # Errors
+/// - `InvalidAmount`: If amount_out is zero
+/// - `InsufficientLiquidity`: If either reserve is zero or amount_out drains the pool
+/// - `InvalidFee`: If fee >= 100%
+/// - `MathOverflow`: If calculations overflow u128 or the input exceeds u64
+pub fn calculate_swap_amount_in(
+    amount_out: u64,
+    reserve_in: u64,
+    reserve_out: u64,
+    fee_bps: u64,
+) -> Result<u64> {
+    require!(amount_out > 0, DexError::InvalidAmount);
+    require!(reserve_in > 0 && reserve_out > 0, DexError::InsufficientLiquidity);
+    require!(amount_out < reserve_out, DexError::InsufficientLiquidity);
+    // a 100% fee can never buy anything.
+    require!((fee_bps as u128) < FEE_DEN, DexError::InvalidFee);
+
+    let numerator = (reserve_in as u128)
+        .checked_mul(amount_out as u128)
+        .ok_or(DexError::MathOverflow)?
+        .checked_mul(FEE_DEN)
+        .ok_or(DexError::MathOverflow)?;
+
+    let denominator = ((reserve_out - amount_out) as u128)
+        .checked_mul(FEE_DEN - fee_bps as u128)
+        .ok_or(DexError::MathOverflow)?;
+
+    let amount_in = numerator
+        .checked_add(denominator - 1)
+        .ok_or(DexError::MathOverflow)?
+        .checked_div(denominator)
+        .ok_or(DexError::MathOverflow)?;
+
+    u64::try_from(amount_in).map_err(|_| error!(DexError::MathOverflow))
+}
@@ -0,0 +1,377 @@
+use anchor_lang::prelude::*;
+use crate::errors::DexError;
+use crate::math::U256;
+use crate::utils::{FEE_DEN, Q64_SHIFT};
+
+/// 1.0 in the 18-decimal fixed point used by the weighted math
+pub const WAD: u128 = 1_000_000_000_000_000_000;
+
+/// ln(2) in WAD
+const LN_2: u128 = 693_147_180_559_945_309;
+
+/// Smallest token weight a weighted pool accepts, in basis points (1%)
+pub const MIN_WEIGHT: u64 = 100;
+
+/// Largest share of a reserve one swap may put in or take out, in basis points (30%)
+/// Keeps `pow` inside the range where its error bound holds
+pub const MAX_SWAP_RATIO: u128 = 3_000;
+
+/// Relative error bound of `pow` in WAD (1e-14), on top of 1 wei of truncation; results are
+/// widened by both in the pool's favor
+const MAX_POW_RELATIVE_ERROR: u128 = 10_000;
+
+/// exp() of anything below this rounds to 0 in WAD
+const MIN_EXP: i128 = -42 * WAD as i128;
+
+/// exp() of anything above this overflows u128 in WAD
+const MAX_EXP: i128 = 46 * WAD as i128;
+
+/// Natural logarithm of a WAD value.
+///
+/// Normalizes x = m * 2^k with m in [1, 2), then sums the atanh series
+/// ```text
+/// ln(m) = 2 * (z + z^3/3 + z^5/5 + ...),  z = (m - 1) / (m + 1) < 1/3
+/// ```
+///
+/// # Errors
+/// - `MathOverflow`: If x is zero
+pub fn ln(x: u128) -> Result<i128> {
+    require!(x > 0, DexError::MathOverflow);
+    let mut k: i128 = 0;
+    let mut m = x;
+    while m >= 2 * WAD {
+        m /= 2;
+        k += 1;
+    }
+    while m < WAD {
+        m *= 2;
+        k -= 1;
+    }
+
+    let z = (m - WAD) * WAD / (m + WAD);
+    let z_squared = z * z / WAD;
+    let mut sum = z;
+    let mut term = z;
+    let mut i = 1;
+    loop {
+        term = term * z_squared / WAD;
+        if term == 0 {
+            break;
+        }
+        sum += term / (2 * i + 1);
+        i += 1;
+    }
+    Ok(k * LN_2 as i128 + 2 * sum as i128)
+}
+
+/// e^x for a signed WAD exponent, as a WAD value.
+///
+/// Splits x = k * ln(2) + r with r in [0, ln(2)), sums the Taylor series of e^r
+/// and shifts by k.
+///
+/// # Errors
+/// - `MathOverflow`: If x is above `MAX_EXP`
+pub fn exp(x: i128) -> Result<u128> {
+    if x < MIN_EXP {
+        return Ok(0);
+    }
+    require!(x <= MAX_EXP, DexError::MathOverflow);
+    let k = x.div_euclid(LN_2 as i128);
+    let r = (x - k * LN_2 as i128) as u128;
+
+    let mut sum = WAD;
+    let mut term = WAD;
+    let mut i = 1;
+    loop {
+        term = term * r / (WAD * i);
+        if term == 0 {
+            break;
+        }
+        sum += term;
+        i += 1;
+    }
+    if k >= 0 {
+        sum.checked_shl(k as u32)
+            .filter(|shifted| shifted >> k == sum)
+            .ok_or(error!(DexError::MathOverflow))
+    } else {
+        Ok(sum >> (-k).min(127))
+    }
+}
+
+/// base^exponent for WAD values, as e^(exponent * ln(base)).
+///
+/// # Errors
+/// - `MathOverflow`: If base is zero or the result overflows
+pub fn pow(base: u128, exponent: u128) -> Result<u128> {
+    if exponent == 0 {
+        return Ok(WAD);
+    }
+    let exponent = i128::try_from(exponent).map_err(|_| error!(DexError::MathOverflow))?;
+    let product = ln(base)?
+        .checked_mul(exponent)
+        .ok_or(DexError::MathOverflow)?;
+    exp(product / WAD as i128)
+}
+
+/// `pow` rounded up past its error bound
+fn pow_up(base: u128, exponent: u128) -> Result<u128> {
+    let raw = pow(base, exponent)?;
+    let max_error = raw
+        .checked_mul(MAX_POW_RELATIVE_ERROR)
+        .ok_or(DexError::MathOverflow)?
+        / WAD
+        + 1;
+    raw.checked_add(max_error).ok_or(error!(DexError::MathOverflow))
+}
+
+/// Weighted geometric mean `a^(weight_a) * b^(weight_b)` with weights in basis points.
+///
+/// This is the weighted product invariant; for 50/50 weights it is sqrt(a * b).
+/// Rounds down.
+///
+/// # Errors
+/// - `MathOverflow`: If either amount is zero or the result overflows
+pub fn geometric_mean(a: u64, weight_a: u64, b: u64, weight_b: u64) -> Result<u64> {
+    let ln_a = ln(a as u128 * WAD)?;
+    let ln_b = ln(b as u128 * WAD)?;
+    let exponent = (ln_a * weight_a as i128 + ln_b * weight_b as i128)
+        / (weight_a as i128 + weight_b as i128);
+    let mean = exp(exponent)? / WAD;
+    u64::try_from(mean).map_err(|_| error!(DexError::MathOverflow))
+}
+
+/// Spot price `(numerator_reserve / numerator_weight) / (denominator_reserve / denominator_weight)`
+/// in Q64.64 fixed point.
+///
+/// The price of the denominator token in the numerator token, ignoring fees.
+/// Returns 0 if `denominator_reserve` or `numerator_weight` is zero.
+pub fn spot_price_q64(
+    numerator_reserve: u64,
+    numerator_weight: u64,
+    denominator_reserve: u64,
+    denominator_weight: u64,
+) -> u128 {
+    if denominator_reserve == 0 || numerator_weight == 0 {
+        return 0;
+    }
+    let numerator = (U256::from(numerator_reserve) * U256::from(denominator_weight)) << Q64_SHIFT;
+    let denominator = U256::from(denominator_reserve) * U256::from(numerator_weight);
+    let price = numerator / denominator;
+    if price > U256::from(u128::MAX) {
+        u128::MAX
+    } else {
+        price.as_u128()
+    }
+}
+
+/// Calculate the output of a swap on the weighted product curve with fees (Balancer out-given-in).
+///
+/// ```text
+/// amount_out = reserve_out * (1 - (reserve_in / (reserve_in + amount_in_after_fee))^(weight_in / weight_out))
+/// ```
+/// Base, exponent and power are each rounded so the output rounds down.
+///
+/// # Errors
+/// - `InvalidAmount`: If amount_in is zero
+/// - `InsufficientLiquidity`: If either reserve is zero
+/// - `InvalidFee`: If fee > 100%
+/// - `MaxSwapRatioExceeded`: If amount_in is above `MAX_SWAP_RATIO` of reserve_in
+/// - `MathOverflow`: If calculations overflow
+pub fn calculate_swap_amount(
+    amount_in: u64,
+    reserve_in: u64,
+    weight_in: u64,
+    reserve_out: u64,
+    weight_out: u64,
+    fee_bps: u64,
+) -> Result<u64> {
+    require!(amount_in > 0, DexError::InvalidAmount);
+    require!(reserve_in > 0 && reserve_out > 0, DexError::InsufficientLiquidity);
+    require!(fee_bps as u128 <= FEE_DEN, DexError::InvalidFee);
+    require!(
+        amount_in as u128 * FEE_DEN <= reserve_in as u128 * MAX_SWAP_RATIO,
+        DexError::MaxSwapRatioExceeded
+    );
+
+    let amount_in_after_fee = amount_in as u128 * (FEE_DEN - fee_bps as u128) / FEE_DEN;
+    let denominator = reserve_in as u128 + amount_in_after_fee;
+    // base rounded up, exponent down: both shrink the output
+    let base = (reserve_in as u128 * WAD).div_ceil(denominator);
+    let exponent = weight_in as u128 * WAD / weight_out as u128;
+    let power = pow_up(base, exponent)?;
+    if power >= WAD {
+        return Ok(0);
+    }
+
+    let amount_out = U256::from(reserve_out) * U256::from(WAD - power) / U256::from(WAD);
+    Ok(amount_out.as_u64())
+}
+
+/// Calculate the input required to receive exactly `amount_out` on the weighted product
+/// curve (Balancer in-given-out).
+///
+/// ```text
+/// amount_in_after_fee = reserve_in * ((reserve_out / (reserve_out - amount_out))^(weight_out / weight_in) - 1)
+/// ```
+/// then grossed up by the fee. Every step rounds up.
+///
+/// # Errors
+/// - `InvalidAmount`: If amount_out is zero
+/// - `InsufficientLiquidity`: If either reserve is zero
+/// - `InvalidFee`: If fee >= 100%
+/// - `MaxSwapRatioExceeded`: If amount_out is above `MAX_SWAP_RATIO` of reserve_out
+/// - `MathOverflow`: If calculations overflow or the input exceeds u64
+pub fn calculate_swap_amount_in(
+    amount_out: u64,
+    reserve_in: u64,
+    weight_in: u64,
+    reserve_out: u64,
+    weight_out: u64,
+    fee_bps: u64,
+) -> Result<u64> {
+    require!(amount_out > 0, DexError::InvalidAmount);
+    require!(reserve_in > 0 && reserve_out > 0, DexError::InsufficientLiquidity);
+    require!((fee_bps as u128) < FEE_DEN, DexError::InvalidFee);
+    require!(
+        amount_out as u128 * FEE_DEN <= reserve_out as u128 * MAX_SWAP_RATIO,
+        DexError::MaxSwapRatioExceeded
+    );
+
+    let base = (reserve_out as u128 * WAD).div_ceil((reserve_out - amount_out) as u128);
+    let exponent = (weight_out as u128 * WAD).div_ceil(weight_in as u128);
+    let ratio = pow_up(base, exponent)?.saturating_sub(WAD);
+
+    let amount_in_after_fee = (U256::from(reserve_in) * U256::from(ratio) + U256::from(WAD - 1))
+        / U256::from(WAD);
+    let denominator = U256::from(FEE_DEN - fee_bps as u128);
+    let amount_in = (amount_in_after_fee * U256::from(FEE_DEN) + denominator - 1) / denominator;
+    require!(amount_in <= U256::from(u64::MAX), DexError::MathOverflow);
+    Ok(amount_in.as_u64())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Heaviest weight a pool can give a token, opposite a `MIN_WEIGHT` one
+    const MAX_WEIGHT: u64 = 10_000 - MIN_WEIGHT;
+
+    /// Largest relative gap, in WAD, a swap quote may leave to the exact value
+    const MAX_QUOTE_SLACK: u128 = 10_000_000_000;
+
+    /// Reserves, and the `MAX_SWAP_RATIO` share of each
+    const TRILLION: u64 = 1_000_000_000_000;
+    const TRILLION_30: u64 = 300_000_000_000;
+    const SHALLOW: u64 = 5_000_000;
+    const SHALLOW_30: u64 = 1_500_000;
+    const DEEP: u64 = 7_000_000_000_000_000_000;
+    const DEEP_30: u64 = 2_100_000_000_000_000_000;
+
+    #[test]
+    fn pow_stays_within_its_error_bound() {
+        // (base, exponent, floor of the exact power), references to 100 digits.
+        // Bases are the furthest a swap moves a reserve ratio, 1 / (1 + 30%) in and
+        // 1 / (1 - 30%) out, rounded against the trader; exponents are the weight ratios
+        // at `MIN_WEIGHT`.
+        let cases: [(u128, u128, u128); 4] = [
+            (
+                769_230_769_230_769_231,
+                10_101_010_101_010_101,
+                997_353_364_446_250_879,
+            ),
+            (769_230_769_230_769_231, 99 * WAD, 5_243_341),
+            (
+                1_428_571_428_571_428_572,
+                10_101_010_101_010_102,
+                1_003_609_275_014_355_507,
+            ),
+            (
+                1_428_571_428_571_428_572,
+                99 * WAD,
+                2_164_183_285_663_154_425_075_908_812_958_096,
+            ),
+        ];
+        for (base, exponent, reference) in cases {
+            let power = pow(base, exponent).unwrap();
+            let bound = reference * MAX_POW_RELATIVE_ERROR / WAD + 1;
+            assert!(
+                power.abs_diff(reference) <= bound,
+                "{base}^{exponent}: {power} vs {reference}"
+            );
+            assert!(
+                pow_up(base, exponent).unwrap() > reference,
+                "pow_up below {base}^{exponent}"
+            );
+        }
+    }
+
+    #[test]
+    fn out_given_in_rounds_down() {
+        // (reserve_in, weight_in, reserve_out, weight_out, amount_in, floor of the exact output)
+        let cases: [(u64, u64, u64, u64, u64, u64); 5] = [
+            (TRILLION, MIN_WEIGHT, TRILLION, MAX_WEIGHT, TRILLION_30, 2_646_635_553),
+            (TRILLION, MAX_WEIGHT, TRILLION, MIN_WEIGHT, TRILLION_30, 999_999_999_994),
+            (SHALLOW, MIN_WEIGHT, DEEP, MAX_WEIGHT, SHALLOW_30, 18_526_448_876_243_846),
+            (DEEP, MAX_WEIGHT, SHALLOW, MIN_WEIGHT, DEEP_30, 4_999_999),
+            (TRILLION, 8_000, TRILLION, 2_000, 1_000_000_000, 3_990_019_965),
+        ];
+        for (reserve_in, weight_in, reserve_out, weight_out, amount_in, reference) in cases {
+            let amount_out =
+                calculate_swap_amount(amount_in, reserve_in, weight_in, reserve_out, weight_out, 0)
+                    .unwrap();
+            assert!(amount_out <= reference, "{amount_out} out above {reference}");
+            let slack = reference as u128 * MAX_QUOTE_SLACK / WAD + 1;
+            assert!(
+                (reference - amount_out) as u128 <= slack,
+                "{amount_out} out, {reference} owed"
+            );
+        }
+    }
+
+    #[test]
+    fn in_given_out_rounds_up() {
+        // (reserve_in, weight_in, reserve_out, weight_out, amount_out, ceiling of the exact input)
+        let cases: [(u64, u64, u64, u64, u64, u64); 5] = [
+            (TRILLION, MAX_WEIGHT, TRILLION, MIN_WEIGHT, TRILLION_30, 3_609_275_015),
+            (TRILLION, MIN_WEIGHT, TRILLION, MAX_WEIGHT, 1_000_000_000, 104_120_988_657),
+            (SHALLOW, MAX_WEIGHT, DEEP, MIN_WEIGHT, DEEP_30, 18_047),
+            (DEEP, MIN_WEIGHT, SHALLOW, MAX_WEIGHT, 5_000, 728_846_920_596_847_555),
+            (TRILLION, 8_000, TRILLION, 2_000, TRILLION_30, 93_265_113_930),
+        ];
+        for (reserve_in, weight_in, reserve_out, weight_out, amount_out, reference) in cases {
+            let amount_in = calculate_swap_amount_in(
+                amount_out,
+                reserve_in,
+                weight_in,
+                reserve_out,
+                weight_out,
+                0,
+            )
+            .unwrap();
+            assert!(amount_in >= reference, "{amount_in} in below {reference}");
+            let slack = reference as u128 * MAX_QUOTE_SLACK / WAD + 1;
+            assert!((amount_in - reference) as u128 <= slack, "{amount_in} in, {reference} owed");
+        }
+    }
+
+    #[test]
+    fn swaps_stop_at_max_swap_ratio() {
+        assert_eq!(
+            calculate_swap_amount(TRILLION_30 + 1, TRILLION, 5_000, TRILLION, 5_000, 0)
+                .unwrap_err(),
+            DexError::MaxSwapRatioExceeded.into()
+        );
+        assert_eq!(
+            calculate_swap_amount_in(TRILLION_30 + 1, TRILLION, 5_000, TRILLION, 5_000, 0)
+                .unwrap_err(),
+            DexError::MaxSwapRatioExceeded.into()
+        );
+        // taking 30% of the light side costs 0.7^-99 times the heavy reserve, past u64
+        assert_eq!(
+            calculate_swap_amount_in(TRILLION_30, TRILLION, MIN_WEIGHT, TRILLION, MAX_WEIGHT, 0)
+                .unwrap_err(),
+            DexError::MathOverflow.into()
+        );
+    }
+}
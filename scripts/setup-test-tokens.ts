@@ -1,6 +1,7 @@
 import * as anchor from "@coral-xyz/anchor";
 import { PublicKey, SystemProgram, Transaction, sendAndConfirmTransaction } from "@solana/web3.js";
 import { createMint, mintTo, getOrCreateAssociatedTokenAccount, transfer } from "@solana/spl-token";
+import { NjaSwap } from "../target/types/nja_swap";
 
 /**
  * Creates two test SPL tokens and mints them to your wallet.
@@ -49,6 +50,29 @@ async function main() {
     }
   }
 
+  // Pools read their default fee from the protocol config, so create it once per cluster.
+  // The CLI wallet deployed the program, which makes it the upgrade authority allowed to do this.
+  const program = anchor.workspace.NjaSwap as anchor.Program<NjaSwap>;
+  const [config] = PublicKey.findProgramAddressSync([Buffer.from("config")], program.programId);
+  if (!(await provider.connection.getAccountInfo(config))) {
+    console.log("Initializing protocol config...");
+    const [programData] = PublicKey.findProgramAddressSync(
+      [program.programId.toBuffer()],
+      new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+    );
+    await program.methods
+      .initializeConfig(new anchor.BN(30), new anchor.BN(0), { perSwap: {} }, payer.publicKey)
+      .accounts({
+        config,
+        admin: payer.publicKey,
+        program: program.programId,
+        programData,
+        systemProgram: SystemProgram.programId,
+      })
+      .rpc();
+    console.log("✅ Config initialized (default fee 0.3%)\n");
+  }
+
   // Create Token A
   console.log("Creating Token A...");
   const tokenA = await createMint(
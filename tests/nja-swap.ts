@@ -1,13 +1,22 @@
 import * as anchor from "@coral-xyz/anchor";
 import {Program} from "@coral-xyz/anchor";
 import {NjaSwap} from "../target/types/nja_swap";
-import {PublicKey, Keypair, SystemProgram} from "@solana/web3.js";
+import {DummyTransferHook} from "../target/types/dummy_transfer_hook";
+import {PublicKey, Keypair, SystemProgram, Transaction, TransactionInstruction} from "@solana/web3.js";
 import {
     TOKEN_PROGRAM_ID,
+    TOKEN_2022_PROGRAM_ID,
+    ExtensionType,
+    getMintLen,
+    createInitializeMintInstruction,
+    createInitializeTransferFeeConfigInstruction,
+    createInitializePermanentDelegateInstruction,
+    createInitializeTransferHookInstruction,
     createMint,
     createAccount,
     mintTo,
     getAccount,
+    getMint,
 } from "@solana/spl-token";
 import {assert} from "chai";
 
@@ -15,6 +24,7 @@ describe("nja-swap", () => {
     const provider = anchor.AnchorProvider.env();
     anchor.setProvider(provider);
     const program = anchor.workspace.NjaSwap as Program<NjaSwap>;
+    const hookProgram = anchor.workspace.DummyTransferHook as Program<DummyTransferHook>;
     const payer = provider.wallet as anchor.Wallet;
     const user = Keypair.generate();
     let tokenAMint: PublicKey;
@@ -27,13 +37,76 @@ describe("nja-swap", () => {
     let tokenAVault: PublicKey;
     let tokenBVault: PublicKey;
     let lpTokenMint: PublicKey;
+    let lockedLpToken: PublicKey;
+    let feeRecipientLpToken: PublicKey;
+    const POOL_INDEX = 0;
+    const PER_SWAP = {perSwap: {}};
+    const LP_MINT = {lpMint: {}};
+    const CONSTANT_PRODUCT = {constantProduct: {}};
+    const STABLE = {stable: {}};
+    const WEIGHTED = {weighted: {}};
+    const CONCENTRATED = {concentrated: {}};
+    // Token A and B each name their own token program, so legacy and Token-2022 mints can share a pool.
+    const TOKEN_PROGRAMS = {tokenAProgram: TOKEN_PROGRAM_ID, tokenBProgram: TOKEN_PROGRAM_ID};
+    const [config] = PublicKey.findProgramAddressSync([Buffer.from("config")], program.programId);
+    const [programData] = PublicKey.findProgramAddressSync(
+        [program.programId.toBuffer()],
+        new PublicKey("BPFLoaderUpgradeab1e11111111111111111111111")
+    );
+
+    // Pools only exist for the canonically ordered pair (token_a_mint < token_b_mint).
+    const sortMints = (mintX: PublicKey, mintY: PublicKey): [PublicKey, PublicKey] =>
+        Buffer.compare(mintX.toBuffer(), mintY.toBuffer()) <= 0 ? [mintX, mintY] : [mintY, mintX];
+
+    // Derives the pool PDAs for a pair; `poolIndex` namespaces several pools of the same pair.
+    const derivePoolPdas = (mintA: PublicKey, mintB: PublicKey, poolIndex: number) => {
+        const index = Buffer.alloc(2);
+        index.writeUInt16LE(poolIndex);
+        const seeds = [mintA.toBuffer(), mintB.toBuffer(), index];
+        const find = (prefix: string) =>
+            PublicKey.findProgramAddressSync([Buffer.from(prefix), ...seeds], program.programId)[0];
+        const pool = find("pool");
+        const [lockedLpToken] = PublicKey.findProgramAddressSync(
+            [Buffer.from("locked_lp"), pool.toBuffer()],
+            program.programId
+        );
+        return {
+            pool,
+            poolAuthority: find("pool_authority"),
+            tokenAVault: find("token_a_vault"),
+            tokenBVault: find("token_b_vault"),
+            lpTokenMint: find("lp_token_mint"),
+            lockedLpToken,
+        };
+    };
+
+    // Creates a Token-2022 mint; `extensionIxs` must initialize every extension in `extensions`.
+    const createToken2022Mint = async (
+        extensions: ExtensionType[],
+        extensionIxs: (mint: PublicKey) => TransactionInstruction[]
+    ): Promise<PublicKey> => {
+        const mint = Keypair.generate();
+        const mintLen = getMintLen(extensions);
+        const lamports = await provider.connection.getMinimumBalanceForRentExemption(mintLen);
+        await provider.sendAndConfirm(new Transaction().add(
+            SystemProgram.createAccount({
+                fromPubkey: payer.publicKey, newAccountPubkey: mint.publicKey,
+                space: mintLen, lamports, programId: TOKEN_2022_PROGRAM_ID,
+            }),
+            ...extensionIxs(mint.publicKey),
+            createInitializeMintInstruction(mint.publicKey, 9, payer.publicKey, null, TOKEN_2022_PROGRAM_ID),
+        ), [mint]);
+        return mint.publicKey;
+    };
 
     before(async () => {
         console.log("🚀 Setting up test environment...\n");
         const airdropSig = await provider.connection.requestAirdrop(user.publicKey, 10 * anchor.web3.LAMPORTS_PER_SOL);
         await provider.connection.confirmTransaction(airdropSig);
-        tokenAMint = await createMint(provider.connection, payer.payer, payer.publicKey, null, 9);
-        tokenBMint = await createMint(provider.connection, payer.payer, payer.publicKey, null, 9);
+        [tokenAMint, tokenBMint] = sortMints(
+            await createMint(provider.connection, payer.payer, payer.publicKey, null, 9),
+            await createMint(provider.connection, payer.payer, payer.publicKey, null, 9),
+        );
         console.log("✅ Token A Mint:", tokenAMint.toString());
         console.log("✅ Token B Mint:", tokenBMint.toString());
         userTokenA = await createAccount(provider.connection, payer.payer, tokenAMint, user.publicKey);
@@ -42,29 +115,41 @@ describe("nja-swap", () => {
         await mintTo(provider.connection, payer.payer, tokenBMint, userTokenB, payer.publicKey, 100_000 * 1e9);
         console.log("✅ User received 100,000 Token A");
         console.log("✅ User received 100,000 Token B\n");
-        [pool] = PublicKey.findProgramAddressSync([Buffer.from("pool"), tokenAMint.toBuffer(), tokenBMint.toBuffer()], program.programId);
-        [poolAuthority] = PublicKey.findProgramAddressSync([Buffer.from("pool_authority"), tokenAMint.toBuffer(), tokenBMint.toBuffer()], program.programId);
-        [tokenAVault] = PublicKey.findProgramAddressSync([Buffer.from("token_a_vault"), tokenAMint.toBuffer(), tokenBMint.toBuffer()], program.programId);
-        [tokenBVault] = PublicKey.findProgramAddressSync([Buffer.from("token_b_vault"), tokenAMint.toBuffer(), tokenBMint.toBuffer()], program.programId);
-        [lpTokenMint] = PublicKey.findProgramAddressSync([Buffer.from("lp_token_mint"), tokenAMint.toBuffer(), tokenBMint.toBuffer()], program.programId);
+        ({pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, lockedLpToken} = derivePoolPdas(tokenAMint, tokenBMint, POOL_INDEX));
         console.log("📍 Pool:", pool.toString());
         console.log("📍 Pool Authority:", poolAuthority.toString());
         console.log("📍 LP Token Mint:", lpTokenMint.toString() + "\n");
     });
 
+    it("Initializes the protocol config", async () => {
+        console.log("🔧 TEST 0: Initialize Config\n");
+        const tx = await program.methods.initializeConfig(new anchor.BN(30), new anchor.BN(0), PER_SWAP, payer.publicKey).accounts({
+            config, admin: payer.publicKey, program: program.programId, programData,
+            systemProgram: SystemProgram.programId,
+        }).rpc();
+        console.log("✅ Config initialized. TX:", tx);
+        const configAccount = await program.account.config.fetch(config);
+        assert.equal(configAccount.admin.toString(), payer.publicKey.toString());
+        assert.equal(configAccount.defaultFeeBps.toNumber(), 30);
+        console.log("✅ Config verified successfully\n");
+    });
+
     it("Initializes the pool", async () => {
         console.log("🔧 TEST 1: Initialize Pool\n");
-        const tx = await program.methods.initializePool().accounts({
-            pool, poolAuthority, tokenAMint, tokenBMint, tokenAVault, tokenBVault, lpTokenMint,
+        const tx = await program.methods.initializePool(POOL_INDEX, new anchor.BN(30), CONSTANT_PRODUCT, new anchor.BN(0), new anchor.BN(0)).accounts({
+            config, pool, poolAuthority, tokenAMint, tokenBMint, tokenAVault, tokenBVault, lpTokenMint, lockedLpToken,
             payer: payer.publicKey, systemProgram: SystemProgram.programId,
-            tokenProgram: TOKEN_PROGRAM_ID, rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+            tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS, rent: anchor.web3.SYSVAR_RENT_PUBKEY,
         }).rpc();
         console.log("✅ Pool initialized. TX:", tx);
+        feeRecipientLpToken = await createAccount(provider.connection, payer.payer, lpTokenMint, payer.publicKey);
         const poolAccount = await program.account.pool.fetch(pool);
         assert.equal(poolAccount.tokenAMint.toString(), tokenAMint.toString());
         assert.equal(poolAccount.tokenBMint.toString(), tokenBMint.toString());
         assert.equal(poolAccount.reserveA.toNumber(), 0);
         assert.equal(poolAccount.reserveB.toNumber(), 0);
+        assert.equal(poolAccount.feeNumerator.toNumber(), 30);
+        assert.equal(poolAccount.poolIndex, POOL_INDEX);
         console.log("✅ Pool verified successfully\n");
     });
 
@@ -73,9 +158,9 @@ describe("nja-swap", () => {
         userLpToken = await createAccount(provider.connection, payer.payer, lpTokenMint, user.publicKey);
         const amountA = new anchor.BN(10_000 * 1e9);
         const amountB = new anchor.BN(10_000 * 1e9);
-        const tx = await program.methods.addLiquidity(amountA, amountB, new anchor.BN(0)).accounts({
-            pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint,
-            userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+        const tx = await program.methods.addLiquidity(amountA, amountB, new anchor.BN(0), new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken, lockedLpToken,
+            userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
         }).signers([user]).rpc();
         console.log("✅ Liquidity added. TX:", tx);
         const poolAccount = await program.account.pool.fetch(pool);
@@ -88,9 +173,9 @@ describe("nja-swap", () => {
         console.log("🔧 TEST 3: Swap Token A → Token B\n");
         const amountIn = new anchor.BN(1_000 * 1e9);
         const minAmountOut = new anchor.BN(900 * 1e9);
-        const tx = await program.methods.swap(amountIn, minAmountOut, true).accounts({
-            pool, poolAuthority, tokenAVault, tokenBVault,
-            userTokenA, userTokenB, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+        const tx = await program.methods.swap(amountIn, minAmountOut, true, null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault,
+            userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
         }).signers([user]).rpc();
         console.log("✅ Swap executed. TX:", tx, "\n");
     });
@@ -99,9 +184,9 @@ describe("nja-swap", () => {
         console.log("🔧 TEST 4: Swap Token B → Token A\n");
         const amountIn = new anchor.BN(500 * 1e9);
         const minAmountOut = new anchor.BN(400 * 1e9);
-        const tx = await program.methods.swap(amountIn, minAmountOut, false).accounts({
-            pool, poolAuthority, tokenAVault, tokenBVault,
-            userTokenA, userTokenB, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+        const tx = await program.methods.swap(amountIn, minAmountOut, false, null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault,
+            userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
         }).signers([user]).rpc();
         console.log("✅ Swap executed. TX:", tx, "\n");
     });
@@ -110,48 +195,26 @@ describe("nja-swap", () => {
         console.log("🔧 TEST 5: Remove Liquidity\n");
         const lpTokenAccount = await getAccount(provider.connection, userLpToken);
         const lpTokenAmount = new anchor.BN(Number(lpTokenAccount.amount) / 2);
-        const tx = await program.methods.removeLiquidity(lpTokenAmount, new anchor.BN(0), new anchor.BN(0)).accounts({
-            pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint,
-            userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+        const tx = await program.methods.removeLiquidity(lpTokenAmount, new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken,
+            userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
         }).signers([user]).rpc();
         console.log("✅ Liquidity removed. TX:", tx, "\n");
     });
 
     it("Fails to initialize pool with same token", async () => {
         console.log("🔧 TEST 6: Reject same token pair\n");
-        const [badPool] = PublicKey.findProgramAddressSync(
-            [Buffer.from("pool"), tokenAMint.toBuffer(), tokenAMint.toBuffer()],
-            program.programId
-        );
-        const [badPoolAuthority] = PublicKey.findProgramAddressSync(
-            [Buffer.from("pool_authority"), tokenAMint.toBuffer(), tokenAMint.toBuffer()],
-            program.programId
-        );
-        const [badTokenAVault] = PublicKey.findProgramAddressSync(
-            [Buffer.from("token_a_vault"), tokenAMint.toBuffer(), tokenAMint.toBuffer()],
-            program.programId
-        );
-        const [badTokenBVault] = PublicKey.findProgramAddressSync(
-            [Buffer.from("token_b_vault"), tokenAMint.toBuffer(), tokenAMint.toBuffer()],
-            program.programId
-        );
-        const [badLpTokenMint] = PublicKey.findProgramAddressSync(
-            [Buffer.from("lp_token_mint"), tokenAMint.toBuffer(), tokenAMint.toBuffer()],
-            program.programId
-        );
+        const bad = derivePoolPdas(tokenAMint, tokenAMint, POOL_INDEX);
 
         try {
-            await program.methods.initializePool().accounts({
-                pool: badPool,
-                poolAuthority: badPoolAuthority,
+            await program.methods.initializePool(POOL_INDEX, new anchor.BN(30), CONSTANT_PRODUCT, new anchor.BN(0), new anchor.BN(0)).accounts({
+                config,
+                ...bad,
                 tokenAMint: tokenAMint,
                 tokenBMint: tokenAMint,
-                tokenAVault: badTokenAVault,
-                tokenBVault: badTokenBVault,
-                lpTokenMint: badLpTokenMint,
                 payer: payer.publicKey,
                 systemProgram: SystemProgram.programId,
-                tokenProgram: TOKEN_PROGRAM_ID,
+                tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
                 rent: anchor.web3.SYSVAR_RENT_PUBKEY,
             }).rpc();
             assert.fail("Should have failed with InvalidTokenPair");
@@ -161,13 +224,153 @@ describe("nja-swap", () => {
         }
     });
 
+    it("Fails to initialize pool with fee out of range", async () => {
+        console.log("🔧 TEST 6b: Reject fee >= 100%\n");
+        const [mintX, mintY] = sortMints(
+            await createMint(provider.connection, payer.payer, payer.publicKey, null, 9),
+            await createMint(provider.connection, payer.payer, payer.publicKey, null, 9),
+        );
+        const feePdas = derivePoolPdas(mintX, mintY, POOL_INDEX);
+
+        try {
+            await program.methods.initializePool(POOL_INDEX, new anchor.BN(10_000), CONSTANT_PRODUCT, new anchor.BN(0), new anchor.BN(0)).accounts({
+                config,
+                ...feePdas,
+                tokenAMint: mintX,
+                tokenBMint: mintY,
+                payer: payer.publicKey,
+                systemProgram: SystemProgram.programId,
+                tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+                rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+            }).rpc();
+            assert.fail("Should have failed with FeeOutOfRange");
+        } catch (err) {
+            assert.include(err.toString(), "FeeOutOfRange");
+            console.log("✅ Correctly rejected out-of-range fee\n");
+        }
+    });
+
+    it("Fails to initialize pool with mints in reverse order", async () => {
+        console.log("🔧 TEST 6c: Reject B/A duplicate of the A/B pool\n");
+        const reversed = derivePoolPdas(tokenBMint, tokenAMint, POOL_INDEX);
+
+        try {
+            await program.methods.initializePool(POOL_INDEX, new anchor.BN(30), CONSTANT_PRODUCT, new anchor.BN(0), new anchor.BN(0)).accounts({
+                config,
+                ...reversed,
+                tokenAMint: tokenBMint,
+                tokenBMint: tokenAMint,
+                payer: payer.publicKey,
+                systemProgram: SystemProgram.programId,
+                tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+                rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+            }).rpc();
+            assert.fail("Should have failed with MintsNotSorted");
+        } catch (err) {
+            assert.include(err.toString(), "MintsNotSorted");
+            console.log("✅ Correctly rejected reversed mint order\n");
+        }
+    });
+
+    it("Initializes a second pool for the same pair at another fee tier", async () => {
+        console.log("🔧 TEST 6d: Second pool for the same pair\n");
+        const tier = derivePoolPdas(tokenAMint, tokenBMint, 1);
+        await program.methods.initializePool(1, new anchor.BN(5), CONSTANT_PRODUCT, new anchor.BN(0), new anchor.BN(0)).accounts({
+            config, ...tier, tokenAMint, tokenBMint,
+            payer: payer.publicKey, systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS, rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        }).rpc();
+        const tierAccount = await program.account.pool.fetch(tier.pool);
+        assert.equal(tierAccount.poolIndex, 1);
+        assert.equal(tierAccount.feeNumerator.toNumber(), 5);
+        assert.notEqual(tier.pool.toString(), pool.toString());
+        console.log("✅ Pair now has pools at 30 bp and 5 bp\n");
+    });
+
+    it("Fails to update config from a non-admin", async () => {
+        console.log("🔧 TEST 6e: Reject non-admin config update\n");
+        try {
+            await program.methods.updateConfig(new anchor.BN(1), new anchor.BN(0), PER_SWAP, user.publicKey)
+                .accounts({config, admin: user.publicKey}).signers([user]).rpc();
+            assert.fail("Should have failed with Unauthorized");
+        } catch (err) {
+            assert.include(err.toString(), "Unauthorized");
+            console.log("✅ Correctly rejected non-admin update\n");
+        }
+    });
+
+    it("Transfers admin in two steps", async () => {
+        console.log("🔧 TEST 6f: Two-step admin transfer\n");
+        await program.methods.transferAdmin(user.publicKey).accounts({config, admin: payer.publicKey}).rpc();
+        let configAccount = await program.account.config.fetch(config);
+        assert.equal(configAccount.admin.toString(), payer.publicKey.toString(), "Admin must not change before acceptance");
+
+        await program.methods.acceptAdmin().accounts({config, pendingAdmin: user.publicKey}).signers([user]).rpc();
+        configAccount = await program.account.config.fetch(config);
+        assert.equal(configAccount.admin.toString(), user.publicKey.toString());
+
+        // hand admin back for the remaining tests
+        await program.methods.transferAdmin(payer.publicKey).accounts({config, admin: user.publicKey}).signers([user]).rpc();
+        await program.methods.acceptAdmin().accounts({config, pendingAdmin: payer.publicKey}).rpc();
+        console.log("✅ Admin transferred and returned\n");
+    });
+
+    it("Locks MINIMUM_LIQUIDITY on first deposit (inflation attack regression)", async () => {
+        console.log("🔧 TEST 6g: Minimum liquidity lock\n");
+        const MINIMUM_LIQUIDITY = 1000;
+        const [mintX, mintY] = sortMints(
+            await createMint(provider.connection, payer.payer, payer.publicKey, null, 9),
+            await createMint(provider.connection, payer.payer, payer.publicKey, null, 9),
+        );
+        const fresh = derivePoolPdas(mintX, mintY, POOL_INDEX);
+        await program.methods.initializePool(POOL_INDEX, null, CONSTANT_PRODUCT, new anchor.BN(0), new anchor.BN(0)).accounts({
+            config, ...fresh, tokenAMint: mintX, tokenBMint: mintY,
+            payer: payer.publicKey, systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS, rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        }).rpc();
+        const attackerX = await createAccount(provider.connection, payer.payer, mintX, user.publicKey);
+        const attackerY = await createAccount(provider.connection, payer.payer, mintY, user.publicKey);
+        const attackerLp = await createAccount(provider.connection, payer.payer, fresh.lpTokenMint, user.publicKey);
+        const freshFeeLp = await createAccount(provider.connection, payer.payer, fresh.lpTokenMint, payer.publicKey);
+        await mintTo(provider.connection, payer.payer, mintX, attackerX, payer.publicKey, 1_000_000 * 1e9);
+        await mintTo(provider.connection, payer.payer, mintY, attackerY, payer.publicKey, 1_000_000 * 1e9);
+        const accounts = {
+            config, ...fresh, feeRecipientLpToken: freshFeeLp,
+            userTokenA: attackerX, userTokenB: attackerY, userLpToken: attackerLp,
+            user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+        };
+
+        // attacker seeds the pool with the smallest possible deposit
+        await program.methods.addLiquidity(new anchor.BN(1001), new anchor.BN(1001), new anchor.BN(0), new anchor.BN(0), new anchor.BN(0), null)
+            .accounts(accounts).signers([user]).rpc();
+        const locked = await getAccount(provider.connection, fresh.lockedLpToken);
+        assert.equal(Number(locked.amount), MINIMUM_LIQUIDITY, "MINIMUM_LIQUIDITY must be minted to the locked account");
+        assert.equal(Number((await getAccount(provider.connection, attackerLp)).amount), 1);
+
+        // withdrawing every attacker LP token leaves the locked share of the reserves behind
+        await program.methods.removeLiquidity(new anchor.BN(1), new anchor.BN(0), new anchor.BN(0), null)
+            .accounts(accounts).signers([user]).rpc();
+        const drained = await program.account.pool.fetch(fresh.pool);
+        assert.equal(drained.reserveA.toNumber(), 1000, "First LP must not withdraw 100% of reserves");
+        assert.equal(drained.reserveB.toNumber(), 1000);
+
+        // a direct donation to the vault does not reprice LP tokens, so a victim still gets a fair share
+        await mintTo(provider.connection, payer.payer, mintX, fresh.tokenAVault, payer.publicKey, 100_000 * 1e9);
+        const lpBefore = Number((await getAccount(provider.connection, attackerLp)).amount);
+        await program.methods.addLiquidity(new anchor.BN(10 * 1e9), new anchor.BN(10 * 1e9), new anchor.BN(0), new anchor.BN(0), new anchor.BN(1), null)
+            .accounts(accounts).signers([user]).rpc();
+        const lpMinted = Number((await getAccount(provider.connection, attackerLp)).amount) - lpBefore;
+        assert.equal(lpMinted, 10 * 1e9, "Deposit should be priced off tracked reserves, not the vault balance");
+        console.log("✅ Minimum liquidity locked, donation has no effect\n");
+    });
+
     it("Fails to add zero liquidity", async () => {
         console.log("🔧 TEST 7: Reject zero amounts\n");
         try {
-            await program.methods.addLiquidity(new anchor.BN(0), new anchor.BN(1000), new anchor.BN(0))
+            await program.methods.addLiquidity(new anchor.BN(0), new anchor.BN(1000), new anchor.BN(0), new anchor.BN(0), new anchor.BN(0), null)
                 .accounts({
-                    pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint,
-                    userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+                    config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken, lockedLpToken,
+                    userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
                 }).signers([user]).rpc();
             assert.fail("Should have failed with InvalidAmount");
         } catch (err) {
@@ -181,9 +384,9 @@ describe("nja-swap", () => {
         const amountIn = new anchor.BN(100 * 1e9);
         const minAmountOut = new anchor.BN(1_000_000 * 1e9); // Unrealistically high
         try {
-            await program.methods.swap(amountIn, minAmountOut, true).accounts({
-                pool, poolAuthority, tokenAVault, tokenBVault,
-                userTokenA, userTokenB, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+            await program.methods.swap(amountIn, minAmountOut, true, null).accounts({
+                config, pool, poolAuthority, tokenAVault, tokenBVault,
+                userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
             }).signers([user]).rpc();
             assert.fail("Should have failed with SlippageExceeded");
         } catch (err) {
@@ -195,9 +398,9 @@ describe("nja-swap", () => {
     it("Fails to swap zero amount", async () => {
         console.log("🔧 TEST 9: Reject zero swap amount\n");
         try {
-            await program.methods.swap(new anchor.BN(0), new anchor.BN(0), true).accounts({
-                pool, poolAuthority, tokenAVault, tokenBVault,
-                userTokenA, userTokenB, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+            await program.methods.swap(new anchor.BN(0), new anchor.BN(0), true, null).accounts({
+                config, pool, poolAuthority, tokenAVault, tokenBVault,
+                userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
             }).signers([user]).rpc();
             assert.fail("Should have failed with InvalidAmount");
         } catch (err) {
@@ -209,10 +412,10 @@ describe("nja-swap", () => {
     it("Fails to remove zero liquidity", async () => {
         console.log("🔧 TEST 10: Reject zero liquidity removal\n");
         try {
-            await program.methods.removeLiquidity(new anchor.BN(0), new anchor.BN(0), new anchor.BN(0))
+            await program.methods.removeLiquidity(new anchor.BN(0), new anchor.BN(0), new anchor.BN(0), null)
                 .accounts({
-                    pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint,
-                    userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+                    config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken,
+                    userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
                 }).signers([user]).rpc();
             assert.fail("Should have failed with InvalidAmount");
         } catch (err) {
@@ -229,10 +432,10 @@ describe("nja-swap", () => {
         const unrealisticMinB = new anchor.BN(1_000_000 * 1e9);
 
         try {
-            await program.methods.removeLiquidity(lpTokenAmount, unrealisticMinA, unrealisticMinB)
+            await program.methods.removeLiquidity(lpTokenAmount, unrealisticMinA, unrealisticMinB, null)
                 .accounts({
-                    pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint,
-                    userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+                    config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken,
+                    userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
                 }).signers([user]).rpc();
             assert.fail("Should have failed with SlippageExceeded");
         } catch (err) {
@@ -241,6 +444,21 @@ describe("nja-swap", () => {
         }
     });
 
+    it("Fails swap past its deadline", async () => {
+        console.log("🔧 TEST 11b: Reject expired transaction\n");
+        const expired = new anchor.BN(Math.floor(Date.now() / 1000) - 3600);
+        try {
+            await program.methods.swap(new anchor.BN(1e9), new anchor.BN(0), true, expired).accounts({
+                config, pool, poolAuthority, tokenAVault, tokenBVault,
+                userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
+            }).signers([user]).rpc();
+            assert.fail("Should have failed with DeadlineExceeded");
+        } catch (err) {
+            assert.include(err.toString(), "DeadlineExceeded");
+            console.log("✅ Correctly rejected expired swap\n");
+        }
+    });
+
     it("Multiple sequential swaps A→B→A maintain invariant", async () => {
         console.log("🔧 TEST 12: Sequential swaps\n");
 
@@ -250,16 +468,16 @@ describe("nja-swap", () => {
 
         // Swap A→B
         const amountIn1 = new anchor.BN(100 * 1e9);
-        await program.methods.swap(amountIn1, new anchor.BN(0), true).accounts({
-            pool, poolAuthority, tokenAVault, tokenBVault,
-            userTokenA, userTokenB, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+        await program.methods.swap(amountIn1, new anchor.BN(0), true, null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault,
+            userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
         }).signers([user]).rpc();
 
         // Swap B→A
         const amountIn2 = new anchor.BN(100 * 1e9);
-        await program.methods.swap(amountIn2, new anchor.BN(0), false).accounts({
-            pool, poolAuthority, tokenAVault, tokenBVault,
-            userTokenA, userTokenB, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+        await program.methods.swap(amountIn2, new anchor.BN(0), false, null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault,
+            userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
         }).signers([user]).rpc();
 
         const poolAfter = await program.account.pool.fetch(pool);
@@ -288,9 +506,9 @@ describe("nja-swap", () => {
         console.log("📥 Adding Token A:", addAmount / 1e9);
         console.log("📥 Adding Token B:", amountB.toNumber() / 1e9);
 
-        await program.methods.addLiquidity(amountA, amountB, new anchor.BN(0)).accounts({
-            pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint,
-            userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+        await program.methods.addLiquidity(amountA, amountB, new anchor.BN(0), new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken, lockedLpToken,
+            userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
         }).signers([user]).rpc();
 
         const poolAfter = await program.account.pool.fetch(pool);
@@ -316,9 +534,9 @@ describe("nja-swap", () => {
         // Large swap (10% of pool)
         const largeAmountIn = new anchor.BN(poolBefore.reserveA.toNumber() / 10);
 
-        await program.methods.swap(largeAmountIn, new anchor.BN(0), true).accounts({
-            pool, poolAuthority, tokenAVault, tokenBVault,
-            userTokenA, userTokenB, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+        await program.methods.swap(largeAmountIn, new anchor.BN(0), true, null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault,
+            userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
         }).signers([user]).rpc();
 
         const poolAfter = await program.account.pool.fetch(pool);
@@ -333,6 +551,978 @@ describe("nja-swap", () => {
         console.log("✅ Large swap shows expected price impact\n");
     });
     
+    it("Swap exact out pays exactly amount_out and respects max_amount_in", async () => {
+        console.log("🔧 TEST 14a: Exact-output swap\n");
+        const amountOut = new anchor.BN(250 * 1e9);
+        const poolBefore = await program.account.pool.fetch(pool);
+        const userBBefore = Number((await getAccount(provider.connection, userTokenB)).amount);
+
+        await program.methods.swapExactOut(amountOut, new anchor.BN(1_000 * 1e9), true, null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault,
+            userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
+        }).signers([user]).rpc();
+
+        const userBAfter = Number((await getAccount(provider.connection, userTokenB)).amount);
+        assert.equal(userBAfter - userBBefore, 250 * 1e9, "User must receive exactly amount_out");
+        const poolAfter = await program.account.pool.fetch(pool);
+        const kBefore = BigInt(poolBefore.reserveA.toString()) * BigInt(poolBefore.reserveB.toString());
+        const kAfter = BigInt(poolAfter.reserveA.toString()) * BigInt(poolAfter.reserveB.toString());
+        assert(kAfter >= kBefore, "Rounded-up input must never decrease k");
+
+        try {
+            await program.methods.swapExactOut(amountOut, new anchor.BN(1), true, null).accounts({
+                config, pool, poolAuthority, tokenAVault, tokenBVault,
+                userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
+            }).signers([user]).rpc();
+            assert.fail("Should have failed with SlippageExceeded");
+        } catch (err) {
+            assert.include(err.toString(), "SlippageExceeded");
+        }
+        console.log("✅ Exact-output swap verified\n");
+    });
+
+    it("Accrues the protocol fee share outside the reserves and collects it", async () => {
+        console.log("🔧 TEST 14b: Protocol fee share\n");
+        // 1/6 of the fee goes to the protocol, fee recipient is the admin wallet
+        await program.methods.updateConfig(new anchor.BN(30), new anchor.BN(1_667), PER_SWAP, payer.publicKey)
+            .accounts({config, admin: payer.publicKey}).rpc();
+
+        const poolBefore = await program.account.pool.fetch(pool);
+        const amountIn = new anchor.BN(1_000 * 1e9);
+        await program.methods.swap(amountIn, new anchor.BN(0), true, null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault,
+            userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
+        }).signers([user]).rpc();
+
+        const poolAfter = await program.account.pool.fetch(pool);
+        const expectedFee = Math.floor((1_000 * 1e9 * 30 * 1_667) / 1e8);
+        assert.equal(poolAfter.protocolFeesA.toNumber() - poolBefore.protocolFeesA.toNumber(), expectedFee);
+        assert.equal(
+            poolAfter.reserveA.toNumber() - poolBefore.reserveA.toNumber(),
+            1_000 * 1e9 - expectedFee,
+            "Protocol fee must not count toward reserve_a"
+        );
+
+        const treasuryA = await createAccount(provider.connection, payer.payer, tokenAMint, payer.publicKey, Keypair.generate());
+        const treasuryB = await createAccount(provider.connection, payer.payer, tokenBMint, payer.publicKey, Keypair.generate());
+        await program.methods.collectProtocolFees().accounts({
+            config, admin: payer.publicKey, pool, poolAuthority, tokenAVault, tokenBVault,
+            treasuryTokenA: treasuryA, treasuryTokenB: treasuryB, ...TOKEN_PROGRAMS,
+        }).rpc();
+
+        const treasuryAccount = await getAccount(provider.connection, treasuryA);
+        assert.equal(Number(treasuryAccount.amount), poolAfter.protocolFeesA.toNumber());
+        const poolCollected = await program.account.pool.fetch(pool);
+        assert.equal(poolCollected.protocolFeesA.toNumber(), 0);
+        assert.equal(poolCollected.protocolFeesB.toNumber(), 0);
+
+        await program.methods.updateConfig(new anchor.BN(30), new anchor.BN(0), PER_SWAP, payer.publicKey)
+            .accounts({config, admin: payer.publicKey}).rpc();
+        console.log("✅ Protocol fees accrued and collected\n");
+    });
+
+    it("Mints the protocol's share of k growth as LP tokens in LpMint mode", async () => {
+        console.log("🔧 TEST 14c: kLast protocol fee\n");
+        await program.methods.updateConfig(new anchor.BN(30), new anchor.BN(1_667), LP_MINT, payer.publicKey)
+            .accounts({config, admin: payer.publicKey}).rpc();
+
+        // a liquidity event records k_last
+        const lpTokenAccount = await getAccount(provider.connection, userLpToken);
+        const lpTokenAmount = new anchor.BN(Number(lpTokenAccount.amount) / 100);
+        await program.methods.removeLiquidity(lpTokenAmount, new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken,
+            userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+        }).signers([user]).rpc();
+        const poolAfterSnapshot = await program.account.pool.fetch(pool);
+        assert(!poolAfterSnapshot.kLast.isZero(), "k_last should be tracked in LpMint mode");
+        assert.equal(poolAfterSnapshot.protocolFeesA.toNumber(), 0);
+
+        // swaps grow k without touching the protocol fee counters
+        for (const isAToB of [true, false]) {
+            await program.methods.swap(new anchor.BN(500 * 1e9), new anchor.BN(0), isAToB, null).accounts({
+                config, pool, poolAuthority, tokenAVault, tokenBVault,
+                userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
+            }).signers([user]).rpc();
+        }
+        const poolAfterSwaps = await program.account.pool.fetch(pool);
+        assert.equal(poolAfterSwaps.protocolFeesA.toNumber(), 0);
+        assert.equal(poolAfterSwaps.protocolFeesB.toNumber(), 0);
+
+        // LpMint mode needs the fee recipient's LP account
+        try {
+            await program.methods.removeLiquidity(lpTokenAmount, new anchor.BN(0), new anchor.BN(0), null).accounts({
+                config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken: null,
+                userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+            }).signers([user]).rpc();
+            assert.fail("Should have failed with FeeRecipientLpTokenMissing");
+        } catch (err) {
+            assert.include(err.toString(), "FeeRecipientLpTokenMissing");
+        }
+
+        // the next liquidity event mints the fee
+        await program.methods.removeLiquidity(lpTokenAmount, new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken,
+            userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+        }).signers([user]).rpc();
+        const feeAccount = await getAccount(provider.connection, feeRecipientLpToken);
+        assert(Number(feeAccount.amount) > 0, "Fee recipient should receive LP tokens");
+
+        await program.methods.updateConfig(new anchor.BN(30), new anchor.BN(0), PER_SWAP, payer.publicKey)
+            .accounts({config, admin: payer.publicKey}).rpc();
+        // PerSwap mode never touches it, so it can be left out
+        await program.methods.removeLiquidity(lpTokenAmount, new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken: null,
+            userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+        }).signers([user]).rpc();
+        console.log("✅ Protocol LP fee minted:", Number(feeAccount.amount), "\n");
+    });
+
+    it("Pausing blocks swaps and deposits but not withdrawals", async () => {
+        console.log("🔧 TEST 14d: Emergency pause\n");
+        // the guardian can pause without admin rights
+        await program.methods.setGuardian(user.publicKey).accounts({config, admin: payer.publicKey}).rpc();
+        await program.methods.setPoolPause(true).accounts({config, pool, authority: user.publicKey}).signers([user]).rpc();
+
+        try {
+            await program.methods.swap(new anchor.BN(1e9), new anchor.BN(0), true, null).accounts({
+                config, pool, poolAuthority, tokenAVault, tokenBVault,
+                userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
+            }).signers([user]).rpc();
+            assert.fail("Should have failed with Paused");
+        } catch (err) {
+            assert.include(err.toString(), "Paused");
+        }
+        try {
+            await program.methods.addLiquidity(new anchor.BN(1e9), new anchor.BN(1e9), new anchor.BN(0), new anchor.BN(0), new anchor.BN(0), null).accounts({
+                config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken, lockedLpToken,
+                userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+            }).signers([user]).rpc();
+            assert.fail("Should have failed with Paused");
+        } catch (err) {
+            assert.include(err.toString(), "Paused");
+        }
+        await program.methods.removeLiquidity(new anchor.BN(1e9), new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken,
+            userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+        }).signers([user]).rpc();
+        await program.methods.setPoolPause(false).accounts({config, pool, authority: user.publicKey}).signers([user]).rpc();
+
+        // the global flag covers every pool
+        await program.methods.setGlobalPause(true).accounts({config, authority: payer.publicKey}).rpc();
+        try {
+            await program.methods.swap(new anchor.BN(1e9), new anchor.BN(0), true, null).accounts({
+                config, pool, poolAuthority, tokenAVault, tokenBVault,
+                userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
+            }).signers([user]).rpc();
+            assert.fail("Should have failed with Paused");
+        } catch (err) {
+            assert.include(err.toString(), "Paused");
+        }
+        await program.methods.setGlobalPause(false).accounts({config, authority: payer.publicKey}).rpc();
+        await program.methods.setGuardian(payer.publicKey).accounts({config, admin: payer.publicKey}).rpc();
+        console.log("✅ Pause blocks swaps/deposits, withdrawals stay open\n");
+    });
+
+    it("Accumulates TWAP prices across swaps", async () => {
+        console.log("🔧 TEST 14e: TWAP accumulators\n");
+        const poolBefore = await program.account.pool.fetch(pool);
+        // let at least one second pass so the accumulators advance
+        await new Promise((resolve) => setTimeout(resolve, 2_000));
+
+        await program.methods.swap(new anchor.BN(1e9), new anchor.BN(0), true, null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault,
+            userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
+        }).signers([user]).rpc();
+
+        const poolAfter = await program.account.pool.fetch(pool);
+        const elapsed = poolAfter.lastUpdateTimestamp.toNumber() - poolBefore.lastUpdateTimestamp.toNumber();
+        assert(elapsed > 0, "Timestamp must advance");
+
+        // the window before the swap held the pre-swap spot price
+        const Q64 = BigInt(1) << BigInt(64);
+        const twapA = (BigInt(poolAfter.priceACumulative.toString()) - BigInt(poolBefore.priceACumulative.toString())) / BigInt(elapsed);
+        const spotA = (BigInt(poolBefore.reserveB.toString()) * Q64) / BigInt(poolBefore.reserveA.toString());
+        assert.equal(twapA.toString(), spotA.toString(), "TWAP over a flat window equals the spot price");
+        const twapB = (BigInt(poolAfter.priceBCumulative.toString()) - BigInt(poolBefore.priceBCumulative.toString())) / BigInt(elapsed);
+        const spotB = (BigInt(poolBefore.reserveA.toString()) * Q64) / BigInt(poolBefore.reserveB.toString());
+        assert.equal(twapB.toString(), spotB.toString());
+        console.log("✅ TWAP accumulators verified over", elapsed, "seconds\n");
+    });
+
+    it("Records swaps into the observations ring buffer", async () => {
+        console.log("🔧 TEST 14f: Observation ring buffer\n");
+        const [observations] = PublicKey.findProgramAddressSync(
+            [Buffer.from("observations"), pool.toBuffer()],
+            program.programId
+        );
+        await program.methods.initializeObservations().accounts({pool, payer: payer.publicKey}).rpc();
+        await program.methods.increaseObservationCardinality(3).accounts({observations, payer: payer.publicKey}).rpc();
+
+        try {
+            await program.methods.increaseObservationCardinality(2).accounts({observations, payer: payer.publicKey}).rpc();
+            assert.fail("Should have failed with InvalidObservationCardinality");
+        } catch (err) {
+            assert.include(err.toString(), "InvalidObservationCardinality");
+        }
+
+        // four swaps a second apart wrap the three-slot ring once
+        for (let i = 0; i < 4; i++) {
+            await new Promise((resolve) => setTimeout(resolve, 1_100));
+            await program.methods.swap(new anchor.BN(1e9), new anchor.BN(0), i % 2 === 0, null).accounts({
+                config, pool, poolAuthority, tokenAVault, tokenBVault, observations,
+                userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
+            }).signers([user]).rpc();
+        }
+
+        const account = await program.account.observations.fetch(observations);
+        const poolState = await program.account.pool.fetch(pool);
+        assert.equal(account.cardinality, 3, "Ring grows to the allocated size");
+        assert(account.observations.every((o) => o.initialized), "Every slot written");
+        const latest = account.observations[account.index];
+        assert.equal(latest.timestamp.toNumber(), poolState.lastUpdateTimestamp.toNumber());
+        assert.equal(latest.priceACumulative.toString(), poolState.priceACumulative.toString());
+
+        // reserve changes outside swaps record a slot too
+        await new Promise((resolve) => setTimeout(resolve, 1_100));
+        await program.methods.sync().accounts({pool, tokenAVault, tokenBVault, observations}).rpc();
+        const afterSync = await program.account.observations.fetch(observations);
+        assert.equal(afterSync.index, (account.index + 1) % 3, "sync writes the next slot");
+        console.log("✅ Observations written, latest slot:", afterSync.index, "\n");
+    });
+
+    it("Skims and syncs tokens sent straight to the vaults", async () => {
+        console.log("🔧 TEST 14g: Skim and sync\n");
+        const donation = 5 * 1e9;
+        await mintTo(provider.connection, payer.payer, tokenAMint, tokenAVault, payer.publicKey, donation);
+
+        // skim sends the excess out and leaves the reserves alone
+        const userABefore = Number((await getAccount(provider.connection, userTokenA)).amount);
+        const poolBefore = await program.account.pool.fetch(pool);
+        await program.methods.skim().accounts({
+            pool, poolAuthority, tokenAVault, tokenBVault,
+            recipientTokenA: userTokenA, recipientTokenB: userTokenB, ...TOKEN_PROGRAMS,
+        }).rpc();
+        const userAAfter = Number((await getAccount(provider.connection, userTokenA)).amount);
+        assert.equal(userAAfter - userABefore, donation, "Skim must send exactly the donation");
+        const poolSkimmed = await program.account.pool.fetch(pool);
+        assert.equal(poolSkimmed.reserveA.toString(), poolBefore.reserveA.toString());
+
+        // sync folds the excess into the reserves
+        await mintTo(provider.connection, payer.payer, tokenAMint, tokenAVault, payer.publicKey, donation);
+        await program.methods.sync().accounts({pool, tokenAVault, tokenBVault}).rpc();
+        const poolSynced = await program.account.pool.fetch(pool);
+        assert.equal(poolSynced.reserveA.toNumber() - poolBefore.reserveA.toNumber(), donation);
+        const vaultA = await getAccount(provider.connection, tokenAVault);
+        assert.equal(
+            Number(vaultA.amount),
+            poolSynced.reserveA.toNumber() + poolSynced.protocolFeesA.toNumber(),
+            "Vault must match reserve plus unclaimed protocol fees"
+        );
+        console.log("✅ Skim and sync verified\n");
+    });
+
+    it("Credits reserves net of Token-2022 transfer fees", async () => {
+        console.log("🔧 TEST 14h: Token-2022 transfer fee\n");
+        // a 1% transfer-fee Token-2022 mint paired with a legacy SPL mint
+        const feeMint = await createToken2022Mint([ExtensionType.TransferFeeConfig], (mint) => [
+            createInitializeTransferFeeConfigInstruction(
+                mint, payer.publicKey, payer.publicKey, 100, BigInt(1e18), TOKEN_2022_PROGRAM_ID
+            ),
+        ]);
+        const legacyMint = await createMint(provider.connection, payer.payer, payer.publicKey, null, 9);
+
+        const [mintX, mintY] = sortMints(feeMint, legacyMint);
+        const programOf = (mint: PublicKey) => mint.equals(legacyMint) ? TOKEN_PROGRAM_ID : TOKEN_2022_PROGRAM_ID;
+        const tokenPrograms = {tokenAProgram: programOf(mintX), tokenBProgram: programOf(mintY)};
+        const mixed = derivePoolPdas(mintX, mintY, POOL_INDEX);
+        await program.methods.initializePool(POOL_INDEX, null, CONSTANT_PRODUCT, new anchor.BN(0), new anchor.BN(0)).accounts({
+            config, ...mixed, tokenAMint: mintX, tokenBMint: mintY,
+            payer: payer.publicKey, systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID, ...tokenPrograms, rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        }).rpc();
+
+        const userX = await createAccount(provider.connection, payer.payer, mintX, user.publicKey, undefined, undefined, programOf(mintX));
+        const userY = await createAccount(provider.connection, payer.payer, mintY, user.publicKey, undefined, undefined, programOf(mintY));
+        await mintTo(provider.connection, payer.payer, mintX, userX, payer.publicKey, 10_000 * 1e9, [], undefined, programOf(mintX));
+        await mintTo(provider.connection, payer.payer, mintY, userY, payer.publicKey, 10_000 * 1e9, [], undefined, programOf(mintY));
+        const mixedLp = await createAccount(provider.connection, payer.payer, mixed.lpTokenMint, user.publicKey);
+        const mixedFeeLp = await createAccount(provider.connection, payer.payer, mixed.lpTokenMint, payer.publicKey);
+
+        // reserves only count what reached the vaults
+        await program.methods.addLiquidity(new anchor.BN(1_000 * 1e9), new anchor.BN(1_000 * 1e9), new anchor.BN(0), new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, ...mixed, feeRecipientLpToken: mixedFeeLp,
+            userTokenA: userX, userTokenB: userY, userLpToken: mixedLp,
+            user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...tokenPrograms,
+        }).signers([user]).rpc();
+        const feeIsA = mintX.equals(feeMint);
+        const afterAdd = await program.account.pool.fetch(mixed.pool);
+        const feeReserve = (p: typeof afterAdd) => (feeIsA ? p.reserveA : p.reserveB).toNumber();
+        assert.equal(feeReserve(afterAdd), 990 * 1e9, "1% transfer fee must not count toward the reserve");
+
+        // a swap in from the fee side is priced on the post-fee input
+        await program.methods.swap(new anchor.BN(100 * 1e9), new anchor.BN(0), feeIsA, null).accounts({
+            config, pool: mixed.pool, poolAuthority: mixed.poolAuthority,
+            tokenAVault: mixed.tokenAVault, tokenBVault: mixed.tokenBVault,
+            userTokenA: userX, userTokenB: userY, user: user.publicKey, ...tokenPrograms,
+        }).signers([user]).rpc();
+        const afterSwap = await program.account.pool.fetch(mixed.pool);
+        assert.equal(feeReserve(afterSwap) - feeReserve(afterAdd), 99 * 1e9);
+        const feeVault = await getAccount(
+            provider.connection, feeIsA ? mixed.tokenAVault : mixed.tokenBVault, undefined, TOKEN_2022_PROGRAM_ID
+        );
+        assert.equal(Number(feeVault.amount), feeReserve(afterSwap), "Vault balance must match the reserve");
+        console.log("✅ Transfer-fee mint pooled, reserves track post-fee amounts\n");
+    });
+
+    it("Refuses unsafe Token-2022 extensions unless the mint is allowlisted", async () => {
+        console.log("🔧 TEST 14i: Mint extension screening\n");
+        const delegateMint = await createToken2022Mint([ExtensionType.PermanentDelegate], (mint) => [
+            createInitializePermanentDelegateInstruction(mint, payer.publicKey, TOKEN_2022_PROGRAM_ID),
+        ]);
+        const legacyMint = await createMint(provider.connection, payer.payer, payer.publicKey, null, 9);
+        const [mintX, mintY] = sortMints(delegateMint, legacyMint);
+        const programOf = (mint: PublicKey) => mint.equals(legacyMint) ? TOKEN_PROGRAM_ID : TOKEN_2022_PROGRAM_ID;
+        const risky = derivePoolPdas(mintX, mintY, POOL_INDEX);
+        const [allowedMint] = PublicKey.findProgramAddressSync(
+            [Buffer.from("allowed_mint"), delegateMint.toBuffer()],
+            program.programId
+        );
+        const initAccounts = {
+            config, ...risky, tokenAMint: mintX, tokenBMint: mintY,
+            payer: payer.publicKey, systemProgram: SystemProgram.programId, tokenProgram: TOKEN_PROGRAM_ID,
+            tokenAProgram: programOf(mintX), tokenBProgram: programOf(mintY), rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        };
+
+        try {
+            await program.methods.initializePool(POOL_INDEX, null, CONSTANT_PRODUCT, new anchor.BN(0), new anchor.BN(0)).accounts(initAccounts).rpc();
+            assert.fail("Should have failed with PermanentDelegateNotAllowed");
+        } catch (err) {
+            assert.include(err.toString(), "PermanentDelegateNotAllowed");
+        }
+
+        // only the admin manages the allowlist
+        try {
+            await program.methods.allowMint().accounts({config, admin: user.publicKey, mint: delegateMint})
+                .signers([user]).rpc();
+            assert.fail("Should have failed with Unauthorized");
+        } catch (err) {
+            assert.include(err.toString(), "Unauthorized");
+        }
+        await program.methods.allowMint().accounts({config, admin: payer.publicKey, mint: delegateMint}).rpc();
+        const delegateIsA = mintX.equals(delegateMint);
+        await program.methods.initializePool(POOL_INDEX, null, CONSTANT_PRODUCT, new anchor.BN(0), new anchor.BN(0)).accounts({
+            ...initAccounts,
+            allowedMintA: delegateIsA ? allowedMint : null,
+            allowedMintB: delegateIsA ? null : allowedMint,
+        }).rpc();
+        const poolAccount = await program.account.pool.fetch(risky.pool);
+        assert.equal(poolAccount.tokenAMint.toString(), mintX.toString());
+
+        await program.methods.disallowMint().accounts({config, admin: payer.publicKey, allowedMint}).rpc();
+        assert.isNull(await provider.connection.getAccountInfo(allowedMint), "Allowlist entry must be closed");
+        console.log("✅ Permanent delegate refused, allowlisted mint accepted\n");
+    });
+
+    it("Forwards transfer hook accounts to Token-2022", async () => {
+        console.log("🔧 TEST 14j: Transfer hook passthrough\n");
+        const hookMint = await createToken2022Mint([ExtensionType.TransferHook], (mint) => [
+            createInitializeTransferHookInstruction(mint, payer.publicKey, hookProgram.programId, TOKEN_2022_PROGRAM_ID),
+        ]);
+        const [extraAccountMetaList] = PublicKey.findProgramAddressSync(
+            [Buffer.from("extra-account-metas"), hookMint.toBuffer()],
+            hookProgram.programId
+        );
+        const [counter] = PublicKey.findProgramAddressSync(
+            [Buffer.from("counter"), hookMint.toBuffer()],
+            hookProgram.programId
+        );
+        await hookProgram.methods.initializeExtraAccountMetaList().accounts({
+            payer: payer.publicKey, extraAccountMetaList, mint: hookMint, counter,
+            systemProgram: SystemProgram.programId,
+        }).rpc();
+        // Token-2022 resolves the hook's extra accounts by key from whatever the caller forwards
+        const hookAccounts = [
+            {pubkey: extraAccountMetaList, isSigner: false, isWritable: false},
+            {pubkey: counter, isSigner: false, isWritable: true},
+            {pubkey: hookProgram.programId, isSigner: false, isWritable: false},
+        ];
+        const transfers = async () => (await hookProgram.account.counter.fetch(counter)).transfers.toNumber();
+
+        const legacyMint = await createMint(provider.connection, payer.payer, payer.publicKey, null, 9);
+        const [mintX, mintY] = sortMints(hookMint, legacyMint);
+        const programOf = (mint: PublicKey) => mint.equals(legacyMint) ? TOKEN_PROGRAM_ID : TOKEN_2022_PROGRAM_ID;
+        const tokenPrograms = {tokenAProgram: programOf(mintX), tokenBProgram: programOf(mintY)};
+        const hooked = derivePoolPdas(mintX, mintY, POOL_INDEX);
+        const [allowedMint] = PublicKey.findProgramAddressSync(
+            [Buffer.from("allowed_mint"), hookMint.toBuffer()],
+            program.programId
+        );
+        await program.methods.allowMint().accounts({config, admin: payer.publicKey, mint: hookMint}).rpc();
+        const hookIsA = mintX.equals(hookMint);
+        await program.methods.initializePool(POOL_INDEX, null, CONSTANT_PRODUCT, new anchor.BN(0), new anchor.BN(0)).accounts({
+            config, ...hooked, tokenAMint: mintX, tokenBMint: mintY,
+            payer: payer.publicKey, systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID, ...tokenPrograms, rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+            allowedMintA: hookIsA ? allowedMint : null,
+            allowedMintB: hookIsA ? null : allowedMint,
+        }).rpc();
+
+        const userX = await createAccount(provider.connection, payer.payer, mintX, user.publicKey, undefined, undefined, programOf(mintX));
+        const userY = await createAccount(provider.connection, payer.payer, mintY, user.publicKey, undefined, undefined, programOf(mintY));
+        await mintTo(provider.connection, payer.payer, mintX, userX, payer.publicKey, 10_000 * 1e9, [], undefined, programOf(mintX));
+        await mintTo(provider.connection, payer.payer, mintY, userY, payer.publicKey, 10_000 * 1e9, [], undefined, programOf(mintY));
+        const hookedLp = await createAccount(provider.connection, payer.payer, hooked.lpTokenMint, user.publicKey);
+        const hookedFeeLp = await createAccount(provider.connection, payer.payer, hooked.lpTokenMint, payer.publicKey);
+
+        await program.methods.addLiquidity(new anchor.BN(1_000 * 1e9), new anchor.BN(1_000 * 1e9), new anchor.BN(0), new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, ...hooked, feeRecipientLpToken: hookedFeeLp,
+            userTokenA: userX, userTokenB: userY, userLpToken: hookedLp,
+            user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...tokenPrograms,
+        }).remainingAccounts(hookAccounts).signers([user]).rpc();
+        assert.equal(await transfers(), 1, "Deposit must run the hook");
+
+        // the hooked side is paid out by the pool authority
+        await program.methods.swap(new anchor.BN(10 * 1e9), new anchor.BN(0), !hookIsA, null).accounts({
+            config, pool: hooked.pool, poolAuthority: hooked.poolAuthority,
+            tokenAVault: hooked.tokenAVault, tokenBVault: hooked.tokenBVault,
+            userTokenA: userX, userTokenB: userY, user: user.publicKey, ...tokenPrograms,
+        }).remainingAccounts(hookAccounts).signers([user]).rpc();
+        assert.equal(await transfers(), 2, "Vault payout must run the hook");
+
+        await program.methods.removeLiquidity(new anchor.BN(10 * 1e9), new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, ...hooked, feeRecipientLpToken: hookedFeeLp,
+            userTokenA: userX, userTokenB: userY, userLpToken: hookedLp,
+            user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...tokenPrograms,
+        }).remainingAccounts(hookAccounts).signers([user]).rpc();
+        assert.equal(await transfers(), 3, "Withdrawal must run the hook");
+
+        try {
+            await program.methods.swap(new anchor.BN(10 * 1e9), new anchor.BN(0), hookIsA, null).accounts({
+                config, pool: hooked.pool, poolAuthority: hooked.poolAuthority,
+                tokenAVault: hooked.tokenAVault, tokenBVault: hooked.tokenBVault,
+                userTokenA: userX, userTokenB: userY, user: user.publicKey, ...tokenPrograms,
+            }).signers([user]).rpc();
+            assert.fail("Should have failed without the hook accounts");
+        } catch (err) {
+            assert.notInclude(err.toString(), "Should have failed");
+        }
+        console.log("✅ Hook ran on deposit, vault payout and withdrawal\n");
+    });
+
+    it("Prices a stable pool on the StableSwap curve", async () => {
+        console.log("🔧 TEST 14k: StableSwap pool\n");
+        const [mintX, mintY] = sortMints(
+            await createMint(provider.connection, payer.payer, payer.publicKey, null, 6),
+            await createMint(provider.connection, payer.payer, payer.publicKey, null, 6),
+        );
+        const stable = derivePoolPdas(mintX, mintY, POOL_INDEX);
+        const initAccounts = {
+            config, ...stable, tokenAMint: mintX, tokenBMint: mintY,
+            payer: payer.publicKey, systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS, rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        };
+        try {
+            await program.methods.initializePool(POOL_INDEX, new anchor.BN(4), STABLE, new anchor.BN(0), new anchor.BN(0)).accounts(initAccounts).rpc();
+            assert.fail("Should have failed with InvalidAmp");
+        } catch (err) {
+            assert.include(err.toString(), "InvalidAmp");
+        }
+        await program.methods.initializePool(POOL_INDEX, new anchor.BN(4), STABLE, new anchor.BN(100), new anchor.BN(0)).accounts(initAccounts).rpc();
+        const poolAccount = await program.account.pool.fetch(stable.pool);
+        assert.deepEqual(poolAccount.curveType, STABLE);
+        assert.equal(poolAccount.amp.toNumber(), 100);
+
+        const userX = await createAccount(provider.connection, payer.payer, mintX, user.publicKey);
+        const userY = await createAccount(provider.connection, payer.payer, mintY, user.publicKey);
+        const stableLp = await createAccount(provider.connection, payer.payer, stable.lpTokenMint, user.publicKey);
+        const stableFeeLp = await createAccount(provider.connection, payer.payer, stable.lpTokenMint, payer.publicKey);
+        await mintTo(provider.connection, payer.payer, mintX, userX, payer.publicKey, 2_000_000 * 1e6);
+        await mintTo(provider.connection, payer.payer, mintY, userY, payer.publicKey, 2_000_000 * 1e6);
+        await program.methods.addLiquidity(new anchor.BN(1_000_000 * 1e6), new anchor.BN(1_000_000 * 1e6), new anchor.BN(0), new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, ...stable, feeRecipientLpToken: stableFeeLp,
+            userTokenA: userX, userTokenB: userY, userLpToken: stableLp,
+            user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+        }).signers([user]).rpc();
+        // a balanced first deposit mints D = a + b, less the locked minimum
+        const lpAccount = await getAccount(provider.connection, stableLp);
+        assert.equal(Number(lpAccount.amount), 2_000_000 * 1e6 - 1000);
+
+        // 10% of the pool moves at close to 1:1, where x*y=k would give ~90.9%
+        const beforeY = Number((await getAccount(provider.connection, userY)).amount);
+        await program.methods.swap(new anchor.BN(100_000 * 1e6), new anchor.BN(99_000 * 1e6), true, null).accounts({
+            config, pool: stable.pool, poolAuthority: stable.poolAuthority,
+            tokenAVault: stable.tokenAVault, tokenBVault: stable.tokenBVault,
+            userTokenA: userX, userTokenB: userY, user: user.publicKey, ...TOKEN_PROGRAMS,
+        }).signers([user]).rpc();
+        const received = Number((await getAccount(provider.connection, userY)).amount) - beforeY;
+        console.log("💱 Received for 100,000:", received / 1e6);
+        assert.isAbove(received, 99_800 * 1e6);
+        assert.isBelow(received, 100_000 * 1e6);
+
+        const afterSwap = await program.account.pool.fetch(stable.pool);
+        await program.methods.removeLiquidity(new anchor.BN(Number(lpAccount.amount) / 2), new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, ...stable, feeRecipientLpToken: stableFeeLp,
+            userTokenA: userX, userTokenB: userY, userLpToken: stableLp,
+            user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+        }).signers([user]).rpc();
+        const afterRemove = await program.account.pool.fetch(stable.pool);
+        assert.approximately(afterRemove.reserveA.toNumber() / afterSwap.reserveA.toNumber(), 0.5, 0.001, "Withdrawals stay proportional");
+        console.log("✅ Stable pool swapped near the peg\n");
+    });
+
+    it("Ramps the swap fee over time and stops the ramp", async () => {
+        console.log("🔧 TEST 14l: Fee ramp\n");
+        // the 5 bp fee tier pool from TEST 6d
+        const tier = derivePoolPdas(tokenAMint, tokenBMint, 1);
+        const now = Math.floor(Date.now() / 1000);
+        const DAY = 86_400;
+        const expectError = async (call: Promise<unknown>, error: string) => {
+            try {
+                await call;
+                assert.fail(`Should have failed with ${error}`);
+            } catch (err) {
+                assert.include(err.toString(), error);
+            }
+        };
+
+        await expectError(program.methods.scheduleFeeRamp(new anchor.BN(40), new anchor.BN(now + DAY))
+            .accounts({config, admin: user.publicKey, pool: tier.pool}).signers([user]).rpc(), "Unauthorized");
+        await expectError(program.methods.scheduleFeeRamp(new anchor.BN(40), new anchor.BN(now + 60))
+            .accounts({config, admin: payer.publicKey, pool: tier.pool}).rpc(), "RampTooShort");
+        await expectError(program.methods.scheduleFeeRamp(new anchor.BN(100), new anchor.BN(now + 2 * DAY))
+            .accounts({config, admin: payer.publicKey, pool: tier.pool}).rpc(), "FeeChangeTooLarge");
+
+        await program.methods.scheduleFeeRamp(new anchor.BN(40), new anchor.BN(now + 2 * DAY))
+            .accounts({config, admin: payer.publicKey, pool: tier.pool}).rpc();
+        let tierAccount = await program.account.pool.fetch(tier.pool);
+        assert.equal(tierAccount.rampStartFee.toNumber(), 5);
+        assert.equal(tierAccount.feeNumerator.toNumber(), 40, "fee_numerator holds the target during a ramp");
+        await expectError(program.methods.scheduleFeeRamp(new anchor.BN(10), new anchor.BN(now + 3 * DAY))
+            .accounts({config, admin: payer.publicKey, pool: tier.pool}).rpc(), "RampInProgress");
+
+        // seconds into a two-day ramp the interpolated fee has barely moved
+        await program.methods.stopRamp().accounts({config, admin: payer.publicKey, pool: tier.pool}).rpc();
+        tierAccount = await program.account.pool.fetch(tier.pool);
+        assert.equal(tierAccount.feeNumerator.toNumber(), 5);
+        assert.isAtMost(tierAccount.rampEndTime.toNumber(), Math.floor(Date.now() / 1000) + 1);
+        console.log("✅ Ramp bounds enforced, stop froze the fee at 5 bp\n");
+    });
+
+    it("Prices an 80/20 weighted pool", async () => {
+        console.log("🔧 TEST 14m: Weighted pool\n");
+        const [mintX, mintY] = sortMints(
+            await createMint(provider.connection, payer.payer, payer.publicKey, null, 9),
+            await createMint(provider.connection, payer.payer, payer.publicKey, null, 9),
+        );
+        const weighted = derivePoolPdas(mintX, mintY, POOL_INDEX);
+        const initAccounts = {
+            config, ...weighted, tokenAMint: mintX, tokenBMint: mintY,
+            payer: payer.publicKey, systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS, rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        };
+        try {
+            await program.methods.initializePool(POOL_INDEX, new anchor.BN(30), WEIGHTED, new anchor.BN(0), new anchor.BN(9_950))
+                .accounts(initAccounts).rpc();
+            assert.fail("Should have failed with InvalidWeight");
+        } catch (err) {
+            assert.include(err.toString(), "InvalidWeight");
+        }
+        await program.methods.initializePool(POOL_INDEX, new anchor.BN(30), WEIGHTED, new anchor.BN(0), new anchor.BN(8_000))
+            .accounts(initAccounts).rpc();
+        const poolAccount = await program.account.pool.fetch(weighted.pool);
+        assert.equal(poolAccount.weightA.toNumber(), 8_000);
+        assert.equal(poolAccount.weightB.toNumber(), 2_000);
+
+        const userX = await createAccount(provider.connection, payer.payer, mintX, user.publicKey);
+        const userY = await createAccount(provider.connection, payer.payer, mintY, user.publicKey);
+        const weightedLp = await createAccount(provider.connection, payer.payer, weighted.lpTokenMint, user.publicKey);
+        const weightedFeeLp = await createAccount(provider.connection, payer.payer, weighted.lpTokenMint, payer.publicKey);
+        await mintTo(provider.connection, payer.payer, mintX, userX, payer.publicKey, 20_000 * 1e9);
+        await mintTo(provider.connection, payer.payer, mintY, userY, payer.publicKey, 20_000 * 1e9);
+        // 8,000 A against 2,000 B at 80/20 prices A at 1 B
+        await program.methods.addLiquidity(new anchor.BN(8_000 * 1e9), new anchor.BN(2_000 * 1e9), new anchor.BN(0), new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, ...weighted, feeRecipientLpToken: weightedFeeLp,
+            userTokenA: userX, userTokenB: userY, userLpToken: weightedLp,
+            user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+        }).signers([user]).rpc();
+        // first deposit mints the weighted geometric mean 8000^0.8 * 2000^0.2
+        const lpAccount = await getAccount(provider.connection, weightedLp);
+        assert.approximately(Number(lpAccount.amount) / 1e9, 6_062.866, 0.001);
+
+        const swapAccounts = {
+            config, pool: weighted.pool, poolAuthority: weighted.poolAuthority,
+            tokenAVault: weighted.tokenAVault, tokenBVault: weighted.tokenBVault,
+            userTokenA: userX, userTokenB: userY, user: user.publicKey, ...TOKEN_PROGRAMS,
+        };
+        const beforeY = Number((await getAccount(provider.connection, userY)).amount);
+        await program.methods.swap(new anchor.BN(10 * 1e9), new anchor.BN(0), true, null).accounts(swapAccounts).signers([user]).rpc();
+        const received = Number((await getAccount(provider.connection, userY)).amount) - beforeY;
+        console.log("💱 Received for 10 A:", received / 1e9);
+        assert.approximately(received / 1e9, 9.939, 0.001);
+
+        // swaps are capped at 30% of a reserve
+        try {
+            await program.methods.swap(new anchor.BN(3_000 * 1e9), new anchor.BN(0), true, null).accounts(swapAccounts).signers([user]).rpc();
+            assert.fail("Should have failed with MaxSwapRatioExceeded");
+        } catch (err) {
+            assert.include(err.toString(), "MaxSwapRatioExceeded");
+        }
+        console.log("✅ Weighted pool priced at 80/20\n");
+    });
+
+    it("Swaps across ticks of a concentrated liquidity pool", async () => {
+        console.log("🔧 TEST 14n: Concentrated liquidity\n");
+        const [mintX, mintY] = sortMints(
+            await createMint(provider.connection, payer.payer, payer.publicKey, null, 9),
+            await createMint(provider.connection, payer.payer, payer.publicKey, null, 9),
+        );
+        const clmm = derivePoolPdas(mintX, mintY, POOL_INDEX);
+        const initAccounts = {
+            config, ...clmm, tokenAMint: mintX, tokenBMint: mintY,
+            payer: payer.publicKey, systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS, rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        };
+        try {
+            await program.methods.initializePool(POOL_INDEX, new anchor.BN(30), CONCENTRATED, new anchor.BN(0), new anchor.BN(0))
+                .accounts(initAccounts).rpc();
+            assert.fail("Should have failed with WrongCurveType");
+        } catch (err) {
+            assert.include(err.toString(), "WrongCurveType");
+        }
+        // price 1: sqrt price 2^64 in Q64.64, tick 0
+        await program.methods.initializeConcentratedPool(POOL_INDEX, new anchor.BN(30), 10, new anchor.BN(1).shln(64))
+            .accounts(initAccounts).rpc();
+
+        const i32 = (value: number) => {
+            const buffer = Buffer.alloc(4);
+            buffer.writeInt32LE(value);
+            return buffer;
+        };
+        // 32 ticks per array at spacing 10: arrays start every 320 ticks
+        const tickArray = (start: number) => PublicKey.findProgramAddressSync(
+            [Buffer.from("tick_array"), clmm.pool.toBuffer(), i32(start)], program.programId
+        )[0];
+        for (const start of [0, -320]) {
+            await program.methods.initializeTickArray(start)
+                .accounts({pool: clmm.pool, tickArray: tickArray(start), payer: payer.publicKey, systemProgram: SystemProgram.programId})
+                .rpc();
+        }
+        const position = (lower: number, upper: number) => PublicKey.findProgramAddressSync(
+            [Buffer.from("position"), clmm.pool.toBuffer(), user.publicKey.toBuffer(), i32(lower), i32(upper)], program.programId
+        )[0];
+        const wide = position(-100, 100);
+        const below = position(-200, -50);
+        for (const [lower, upper] of [[-100, 100], [-200, -50]]) {
+            await program.methods.openPosition(lower, upper)
+                .accounts({pool: clmm.pool, position: position(lower, upper), owner: user.publicKey, systemProgram: SystemProgram.programId})
+                .signers([user]).rpc();
+        }
+
+        const userX = await createAccount(provider.connection, payer.payer, mintX, user.publicKey);
+        const userY = await createAccount(provider.connection, payer.payer, mintY, user.publicKey);
+        await mintTo(provider.connection, payer.payer, mintX, userX, payer.publicKey, 1_000 * 1e9);
+        await mintTo(provider.connection, payer.payer, mintY, userY, payer.publicKey, 1_000 * 1e9);
+        const positionAccounts = (positionKey: PublicKey, lowerStart: number, upperStart: number | null) => ({
+            config, pool: clmm.pool, poolAuthority: clmm.poolAuthority,
+            tokenAMint: mintX, tokenBMint: mintY, tokenAVault: clmm.tokenAVault, tokenBVault: clmm.tokenBVault,
+            position: positionKey, tickArrayLower: tickArray(lowerStart),
+            tickArrayUpper: upperStart === null ? null : tickArray(upperStart),
+            userTokenA: userX, userTokenB: userY, owner: user.publicKey, ...TOKEN_PROGRAMS,
+        });
+        const maxAmount = new anchor.BN(100 * 1e9);
+        await program.methods.increaseLiquidity(new anchor.BN(1_000 * 1e9), maxAmount, maxAmount, null)
+            .accounts(positionAccounts(wide, -320, 0)).signers([user]).rpc();
+        // entirely below the price, so only Token B
+        await program.methods.increaseLiquidity(new anchor.BN(2_000 * 1e9), maxAmount, maxAmount, null)
+            .accounts(positionAccounts(below, -320, null)).signers([user]).rpc();
+        let poolAccount = await program.account.pool.fetch(clmm.pool);
+        assert.equal(poolAccount.liquidity.toString(), (1_000 * 1e9).toString());
+
+        // selling 8 A walks the price below tick -50, where both positions are in range
+        const beforeY = Number((await getAccount(provider.connection, userY)).amount);
+        await program.methods.swapConcentrated(new anchor.BN(8 * 1e9), new anchor.BN(0), true, null, null).accounts({
+            config, pool: clmm.pool, poolAuthority: clmm.poolAuthority,
+            tokenAMint: mintX, tokenBMint: mintY, tokenAVault: clmm.tokenAVault, tokenBVault: clmm.tokenBVault,
+            tickArray0: tickArray(0), tickArray1: tickArray(-320), tickArray2: null,
+            userTokenA: userX, userTokenB: userY, user: user.publicKey, ...TOKEN_PROGRAMS,
+        }).signers([user]).rpc();
+        const received = Number((await getAccount(provider.connection, userY)).amount) - beforeY;
+        console.log("💱 Received for 8 A:", received / 1e9);
+        poolAccount = await program.account.pool.fetch(clmm.pool);
+        assert.isBelow(poolAccount.tickCurrent, -50);
+        assert.equal(poolAccount.liquidity.toString(), (3_000 * 1e9).toString());
+        assert.approximately(received / 1e9, 7.93, 0.01);
+
+        // the wide position earned Token A fees on the whole swap
+        const beforeX = Number((await getAccount(provider.connection, userX)).amount);
+        await program.methods.collectFees().accounts(positionAccounts(wide, -320, 0)).signers([user]).rpc();
+        const fees = Number((await getAccount(provider.connection, userX)).amount) - beforeX;
+        console.log("💰 Fees collected:", fees / 1e9);
+        assert.isAbove(fees, 0);
+
+        // a position change passed the oracle snapshots the active liquidity it ends
+        const [observations] = PublicKey.findProgramAddressSync(
+            [Buffer.from("observations"), clmm.pool.toBuffer()],
+            program.programId
+        );
+        await program.methods.initializeObservations().accounts({pool: clmm.pool, payer: payer.publicKey}).rpc();
+        await new Promise((resolve) => setTimeout(resolve, 1_100));
+        await program.methods.decreaseLiquidity(new anchor.BN(1_000 * 1e9), new anchor.BN(0), new anchor.BN(0), null)
+            .accounts({...positionAccounts(wide, -320, 0), observations}).signers([user]).rpc();
+        const wideAccount = await program.account.position.fetch(wide);
+        assert.equal(wideAccount.liquidity.toNumber(), 0);
+        poolAccount = await program.account.pool.fetch(clmm.pool);
+        assert.equal(poolAccount.liquidity.toString(), (2_000 * 1e9).toString());
+        const observationsAccount = await program.account.observations.fetch(observations);
+        const latest = observationsAccount.observations[observationsAccount.index];
+        assert.equal(latest.timestamp.toNumber(), poolAccount.lastUpdateTimestamp.toNumber());
+        assert.equal(latest.liquidityCumulative.toString(), poolAccount.liquidityCumulative.toString());
+        console.log("✅ Concentrated liquidity swapped across ticks\n");
+    });
+
+    it("Zaps a single token into the pool", async () => {
+        console.log("🔧 TEST 14o: Single-sided deposit\n");
+        const accounts = {
+            config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken, lockedLpToken,
+            userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+        };
+        const poolBefore = await program.account.pool.fetch(pool);
+        const lpBefore = Number((await getAccount(provider.connection, userLpToken)).amount);
+        const supplyBefore = Number((await getMint(provider.connection, lpTokenMint)).supply);
+        try {
+            await program.methods.addLiquiditySingle(new anchor.BN(10 * 1e9), true, new anchor.BN(supplyBefore), null)
+                .accounts(accounts).signers([user]).rpc();
+            assert.fail("Should have failed with SlippageExceeded");
+        } catch (err) {
+            assert.include(err.toString(), "SlippageExceeded");
+        }
+        await program.methods.addLiquiditySingle(new anchor.BN(10 * 1e9), true, new anchor.BN(1), null)
+            .accounts(accounts).signers([user]).rpc();
+
+        // the swapped B is deposited straight back, so only reserve A moves
+        const poolAfter = await program.account.pool.fetch(pool);
+        assert.equal(poolAfter.reserveA.toNumber() - poolBefore.reserveA.toNumber(), 10 * 1e9);
+        assert.equal(poolAfter.reserveB.toNumber(), poolBefore.reserveB.toNumber());
+        // about half the input is swapped, so the deposit is worth just under 10 A of
+        // pool value: the share grows by roughly sqrt((reserve_a + 10) / reserve_a) - 1
+        const lpMinted = Number((await getAccount(provider.connection, userLpToken)).amount) - lpBefore;
+        const reserveA = poolBefore.reserveA.toNumber();
+        const expected = supplyBefore * (Math.sqrt((reserveA + 10 * 1e9) / reserveA) - 1);
+        console.log("💧 LP minted:", lpMinted / 1e9, "expected about", expected / 1e9);
+        assert.approximately(lpMinted / expected, 1, 0.01);
+        console.log("✅ Single-sided deposit minted LP tokens\n");
+    });
+
+    it("Pulls only the ratio-matched amounts on deposit", async () => {
+        console.log("🔧 TEST 14p: Ratio-correct deposit\n");
+        const accounts = {
+            config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken, lockedLpToken,
+            userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+        };
+        const poolBefore = await program.account.pool.fetch(pool);
+        const amountB = new anchor.BN(10 * 1e9).mul(poolBefore.reserveB).div(poolBefore.reserveA);
+        // the minimum B is above what the ratio needs for 10 A
+        try {
+            await program.methods.addLiquidity(new anchor.BN(10 * 1e9), new anchor.BN(1_000 * 1e9), new anchor.BN(0), amountB.addn(1), new anchor.BN(0), null)
+                .accounts(accounts).signers([user]).rpc();
+            assert.fail("Should have failed with SlippageExceeded");
+        } catch (err) {
+            assert.include(err.toString(), "SlippageExceeded");
+        }
+
+        const beforeA = Number((await getAccount(provider.connection, userTokenA)).amount);
+        const beforeB = Number((await getAccount(provider.connection, userTokenB)).amount);
+        await program.methods.addLiquidity(new anchor.BN(10 * 1e9), new anchor.BN(1_000 * 1e9), new anchor.BN(0), amountB, new anchor.BN(0), null)
+            .accounts(accounts).signers([user]).rpc();
+        const spentA = beforeA - Number((await getAccount(provider.connection, userTokenA)).amount);
+        const spentB = beforeB - Number((await getAccount(provider.connection, userTokenB)).amount);
+        console.log("📥 Pulled:", spentA / 1e9, "A,", spentB / 1e9, "B");
+        assert.equal(spentA, 10 * 1e9);
+        assert.equal(spentB, amountB.toNumber(), "Only the B matching the ratio should be pulled");
+        console.log("✅ Excess B stayed with the user\n");
+    });
+
+    it("Zaps out of the pool into a single token", async () => {
+        console.log("🔧 TEST 14q: Single-sided withdrawal\n");
+        const accounts = {
+            config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken,
+            userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+        };
+        const poolBefore = await program.account.pool.fetch(pool);
+        const supply = Number((await getMint(provider.connection, lpTokenMint)).supply);
+        const lpAmount = new anchor.BN(Number((await getAccount(provider.connection, userLpToken)).amount) / 10);
+        // the proportional A alone, before the B leg is swapped into it
+        const shareA = poolBefore.reserveA.toNumber() * lpAmount.toNumber() / supply;
+        try {
+            await program.methods.removeLiquiditySingle(lpAmount, true, new anchor.BN(2 * shareA), null)
+                .accounts(accounts).signers([user]).rpc();
+            assert.fail("Should have failed with SlippageExceeded");
+        } catch (err) {
+            assert.include(err.toString(), "SlippageExceeded");
+        }
+
+        const beforeA = Number((await getAccount(provider.connection, userTokenA)).amount);
+        const beforeB = Number((await getAccount(provider.connection, userTokenB)).amount);
+        await program.methods.removeLiquiditySingle(lpAmount, true, new anchor.BN(shareA), null)
+            .accounts(accounts).signers([user]).rpc();
+        const receivedA = Number((await getAccount(provider.connection, userTokenA)).amount) - beforeA;
+        const receivedB = Number((await getAccount(provider.connection, userTokenB)).amount) - beforeB;
+        console.log("📤 Received:", receivedA / 1e9, "A");
+        assert.equal(receivedB, 0, "No Token B should be paid out");
+        // the B share is swapped back in, so almost twice the proportional A comes out
+        assert.isAbove(receivedA, shareA);
+        assert.isBelow(receivedA, 2 * shareA);
+
+        // only reserve A moves; the swapped B never leaves the vault
+        const poolAfter = await program.account.pool.fetch(pool);
+        assert.equal(poolBefore.reserveA.toNumber() - poolAfter.reserveA.toNumber(), receivedA);
+        assert.equal(poolAfter.reserveB.toNumber(), poolBefore.reserveB.toNumber());
+
+        // in LpMint mode the swap leg's fee growth is left for the next liquidity event to mint
+        await program.methods.updateConfig(new anchor.BN(30), new anchor.BN(1_667), LP_MINT, payer.publicKey)
+            .accounts({config, admin: payer.publicKey}).rpc();
+        await program.methods.removeLiquiditySingle(lpAmount, true, new anchor.BN(0), null)
+            .accounts(accounts).signers([user]).rpc();
+        const feeLpBefore = Number((await getAccount(provider.connection, feeRecipientLpToken)).amount);
+        await program.methods.addLiquidity(new anchor.BN(1e9), new anchor.BN(1e9), new anchor.BN(0), new anchor.BN(0), new anchor.BN(0), null)
+            .accounts({...accounts, lockedLpToken}).signers([user]).rpc();
+        const feeLpAfter = Number((await getAccount(provider.connection, feeRecipientLpToken)).amount);
+        assert.isAbove(feeLpAfter, feeLpBefore, "The zap-out swap fee should be minted to the fee recipient");
+        await program.methods.updateConfig(new anchor.BN(30), new anchor.BN(0), PER_SWAP, payer.publicKey)
+            .accounts({config, admin: payer.publicKey}).rpc();
+        console.log("✅ Single-sided withdrawal paid out Token A\n");
+    });
+
+    it("Routes a swap A→B→C through two pools", async () => {
+        console.log("🔧 TEST 14r: Multi-hop route\n");
+        const tokenCMint = await createMint(provider.connection, payer.payer, payer.publicKey, null, 9);
+        const [mintX, mintY] = sortMints(tokenBMint, tokenCMint);
+        const bc = derivePoolPdas(mintX, mintY, POOL_INDEX);
+        await program.methods.initializePool(POOL_INDEX, new anchor.BN(30), CONSTANT_PRODUCT, new anchor.BN(0), new anchor.BN(0)).accounts({
+            config, ...bc, tokenAMint: mintX, tokenBMint: mintY,
+            payer: payer.publicKey, systemProgram: SystemProgram.programId,
+            tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS, rent: anchor.web3.SYSVAR_RENT_PUBKEY,
+        }).rpc();
+        const userTokenC = await createAccount(provider.connection, payer.payer, tokenCMint, user.publicKey);
+        await mintTo(provider.connection, payer.payer, tokenCMint, userTokenC, payer.publicKey, 1_000 * 1e9);
+        const cIsA = mintX.equals(tokenCMint);
+        await program.methods.addLiquidity(new anchor.BN(1_000 * 1e9), new anchor.BN(1_000 * 1e9), new anchor.BN(0), new anchor.BN(0), new anchor.BN(0), null).accounts({
+            config, ...bc,
+            feeRecipientLpToken: await createAccount(provider.connection, payer.payer, bc.lpTokenMint, payer.publicKey),
+            userTokenA: cIsA ? userTokenC : userTokenB, userTokenB: cIsA ? userTokenB : userTokenC,
+            userLpToken: await createAccount(provider.connection, payer.payer, bc.lpTokenMint, user.publicKey),
+            user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
+        }).signers([user]).rpc();
+
+        // [pool, pool_authority, vault_in, vault_out, mint_out, token_program_out, observations] per hop;
+        // the program id skips a pool's oracle.
+        const meta = (pubkey: PublicKey, isWritable = false) => ({pubkey, isWritable, isSigner: false});
+        const [observations] = PublicKey.findProgramAddressSync(
+            [Buffer.from("observations"), pool.toBuffer()],
+            program.programId
+        );
+        const hopAB = [meta(pool, true), meta(poolAuthority), meta(tokenAVault, true), meta(tokenBVault, true), meta(tokenBMint), meta(TOKEN_PROGRAM_ID), meta(observations, true)];
+        const [bcVaultB, bcVaultC] = cIsA ? [bc.tokenBVault, bc.tokenAVault] : [bc.tokenAVault, bc.tokenBVault];
+        const hopBC = [meta(bc.pool, true), meta(bc.poolAuthority), meta(bcVaultB, true), meta(bcVaultC, true), meta(tokenCMint), meta(TOKEN_PROGRAM_ID), meta(program.programId)];
+        const route = (minAmountOut: anchor.BN, hops: anchor.web3.AccountMeta[]) =>
+            program.methods.swapRoute(new anchor.BN(10 * 1e9), minAmountOut, hops.length / 7, null).accounts({
+                config, tokenInMint: tokenAMint, tokenOutMint: tokenCMint, userTokenIn: userTokenA, userTokenOut: userTokenC,
+                user: user.publicKey, tokenInProgram: TOKEN_PROGRAM_ID, tokenOutProgram: TOKEN_PROGRAM_ID,
+            }).remainingAccounts(hops).signers([user]).rpc();
+
+        // quote both hops with the constant product formula
+        const quote = (amountIn: anchor.BN, reserveIn: anchor.BN, reserveOut: anchor.BN, feeBps: anchor.BN) => {
+            const withFee = amountIn.mul(new anchor.BN(10_000).sub(feeBps));
+            return withFee.mul(reserveOut).div(reserveIn.muln(10_000).add(withFee));
+        };
+        const ab = await program.account.pool.fetch(pool);
+        const bcAccount = await program.account.pool.fetch(bc.pool);
+        const [bcReserveB, bcReserveC] = cIsA ? [bcAccount.reserveB, bcAccount.reserveA] : [bcAccount.reserveA, bcAccount.reserveB];
+        const amountB = quote(new anchor.BN(10 * 1e9), ab.reserveA, ab.reserveB, ab.feeNumerator);
+        const expected = quote(amountB, bcReserveB, bcReserveC, bcAccount.feeNumerator);
+
+        try {
+            await route(expected.addn(1), [...hopAB, ...hopBC]);
+            assert.fail("Should have failed with SlippageExceeded");
+        } catch (err) {
+            assert.include(err.toString(), "SlippageExceeded");
+        }
+        // the second hop must take B in through the B/C pool's own vaults
+        try {
+            await route(new anchor.BN(0), [...hopAB, ...hopBC.slice(0, 2), meta(tokenBVault, true), ...hopBC.slice(3)]);
+            assert.fail("Should have failed with InvalidRoute");
+        } catch (err) {
+            assert.include(err.toString(), "InvalidRoute");
+        }
+
+        const beforeB = Number((await getAccount(provider.connection, userTokenB)).amount);
+        const beforeC = Number((await getAccount(provider.connection, userTokenC)).amount);
+        const observationsBefore = await program.account.observations.fetch(observations);
+        await new Promise((resolve) => setTimeout(resolve, 1_100));
+        await route(expected, [...hopAB, ...hopBC]);
+        const receivedC = Number((await getAccount(provider.connection, userTokenC)).amount) - beforeC;
+        console.log("💱 Received for 10 A:", receivedC / 1e9, "C");
+        assert.equal(receivedC, expected.toNumber());
+        assert.equal(Number((await getAccount(provider.connection, userTokenB)).amount), beforeB, "B moves vault to vault");
+        const bcAfter = await program.account.pool.fetch(bc.pool);
+        const bcReserveBAfter = cIsA ? bcAfter.reserveB : bcAfter.reserveA;
+        assert.equal(bcReserveBAfter.sub(bcReserveB).toString(), amountB.toString());
+        const observationsAfter = await program.account.observations.fetch(observations);
+        assert.equal(
+            observationsAfter.index,
+            (observationsBefore.index + 1) % observationsBefore.cardinality,
+            "The A/B hop writes its oracle"
+        );
+        console.log("✅ Route swapped A→B→C in one instruction\n");
+    });
+
+    it("Lends from the vaults for one transaction", async () => {
+        console.log("🔧 TEST 14s: Flash loan\n");
+        await program.methods.setFlashLoanFee(new anchor.BN(9)).accounts({config, admin: payer.publicKey}).rpc();
+        const loanAmount = new anchor.BN(100 * 1e9);
+        const fee = loanAmount.muln(9).divn(10_000);
+        const tokenAccounts = {
+            pool, tokenAVault, tokenBVault, userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
+        };
+        // the loan is sent with `postInstructions` carrying everything up to its repayment
+        const loan = (postInstructions: TransactionInstruction[]) =>
+            program.methods.flashLoan(loanAmount, new anchor.BN(0)).accounts({
+                config, poolAuthority, ...tokenAccounts, instructions: anchor.web3.SYSVAR_INSTRUCTIONS_PUBKEY,
+            }).postInstructions(postInstructions).signers([user]).rpc();
+        const repay = await program.methods.flashRepay().accounts(tokenAccounts).instruction();
+
+        try {
+            await loan([]);
+            assert.fail("Should have failed with FlashRepayMissing");
+        } catch (err) {
+            assert.include(err.toString(), "FlashRepayMissing");
+        }
+        // the pool is locked between the loan and its repayment
+        const swap = await program.methods.swap(new anchor.BN(1e9), new anchor.BN(0), true, null).accounts({
+            config, pool, poolAuthority, tokenAVault, tokenBVault,
+            userTokenA, userTokenB, user: user.publicKey, ...TOKEN_PROGRAMS,
+        }).instruction();
+        try {
+            await loan([swap, repay]);
+            assert.fail("Should have failed with FlashLoanActive");
+        } catch (err) {
+            assert.include(err.toString(), "FlashLoanActive");
+        }
+
+        const poolBefore = await program.account.pool.fetch(pool);
+        const beforeA = Number((await getAccount(provider.connection, userTokenA)).amount);
+        await loan([repay]);
+        const paid = beforeA - Number((await getAccount(provider.connection, userTokenA)).amount);
+        console.log("💸 Flash loan fee paid:", paid / 1e9, "A");
+        assert.equal(paid, fee.toNumber());
+        // the fee goes to LPs through the reserves
+        const poolAfter = await program.account.pool.fetch(pool);
+        assert.equal(poolAfter.reserveA.sub(poolBefore.reserveA).toString(), fee.toString());
+        assert.equal(poolAfter.reserveB.toString(), poolBefore.reserveB.toString());
+        assert.isFalse(poolAfter.flashLoanActive);
+        console.log("✅ Flash loan repaid with its fee in one transaction\n");
+    });
+
     it("Removing all liquidity works correctly", async () => {
         console.log("🔧 TEST 15: Remove all remaining liquidity\n");
 
@@ -340,10 +1530,10 @@ describe("nja-swap", () => {
         const allLpTokens = new anchor.BN(Number(lpTokenAccount.amount));
         console.log("💧 LP tokens to burn:", Number(lpTokenAccount.amount) / 1e9);
 
-        const tx = await program.methods.removeLiquidity(allLpTokens, new anchor.BN(0), new anchor.BN(0))
+        const tx = await program.methods.removeLiquidity(allLpTokens, new anchor.BN(0), new anchor.BN(0), null)
             .accounts({
-                pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint,
-                userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID,
+                config, pool, poolAuthority, tokenAVault, tokenBVault, lpTokenMint, feeRecipientLpToken,
+                userTokenA, userTokenB, userLpToken, user: user.publicKey, tokenProgram: TOKEN_PROGRAM_ID, ...TOKEN_PROGRAMS,
             }).signers([user]).rpc();
 
         const lpTokenAccountAfter = await getAccount(provider.connection, userLpToken);